@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ArchiveConfig;
+use crate::object::{self, Object};
+
+// Objects bundled in one file, like a standard library of math, screen and
+// keyboard routines. The linker takes only the members the program needs,
+// see linker::select().
+//
+//     hack-archive 1
+//     member Math.obj 12         the next 12 lines are Math.obj
+//     hack-object 2
+//     ...
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub members: Vec<(String, Object)>,
+}
+
+const HEADER: &str = "hack-archive 1";
+
+impl fmt::Display for Archive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for (name, object) in &self.members {
+            let object = object.to_string();
+            writeln!(f, "member {} {}", name, object.lines().count())?;
+            write!(f, "{}", object)?;
+        }
+        Ok(())
+    }
+}
+
+// None when the text is not an archive written by this version
+pub fn read(text: &str) -> Option<Archive> {
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return None;
+    }
+
+    let mut members = vec![];
+    while let Some(line) = lines.next() {
+        let (name, count) = line.strip_prefix("member ")?.rsplit_once(' ')?;
+        let count: usize = count.parse().ok()?;
+        let object: Vec<&str> = lines.by_ref().take(count).collect();
+        if object.len() < count {
+            return None;
+        }
+        members.push((String::from(name), object::read(&object.join("\n"))?));
+    }
+
+    Some(Archive { members })
+}
+
+// assembler archive Std.lib Math.obj Screen.obj
+pub fn run(config: ArchiveConfig) -> Result<(), Box<dyn Error>> {
+    let mut archive = Archive { members: vec![] };
+
+    for file in &config.files {
+        let contents = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+        let object = object::read(&contents)
+            .ok_or_else(|| format!("{} is not an object written by this assembler", file))?;
+        // members are known by their file name, wherever they were
+        let name = Path::new(file)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("invalid file name")?;
+        if archive.members.iter().any(|(member, _)| member == name) {
+            return Err(format!("{} is in the archive twice", name).into());
+        }
+        archive.members.push((String::from(name), object));
+    }
+
+    fs::write(&config.output, archive.to_string())?;
+    println!("{}: {} members", config.output, archive.members.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn write_and_read() {
+        let config = Config::new(&[String::from("assembler"), String::from("Main.asm")]).unwrap();
+        let compile = |contents| object::compile(contents, &config, &mut vec![]).unwrap();
+        let archive = Archive {
+            members: vec![
+                (String::from("Mult.obj"), compile("(MULT)\n@i\nM=D\n")),
+                (String::from("Empty.obj"), compile("")),
+                (String::from("Div.obj"), compile("(DIV)\n@MULT\n0;JMP\n")),
+            ],
+        };
+
+        assert_eq!(Some(archive.clone()), read(&archive.to_string()));
+        assert_eq!(
+            None,
+            read("hack-archive 1\nmember Mult.obj 3\nhack-object 2\n")
+        );
+        assert_eq!(None, read("hack-object 2\n"));
+    }
+}
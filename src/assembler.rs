@@ -1,79 +1,1383 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::error::Error;
 use std::fs;
-use std::io::Write;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
 use crate::code;
-use crate::config;
+use crate::config::{self, Format};
+use crate::data::{self, Directive};
+use crate::diagnostic::Diagnostic;
+use crate::expression::{self, Expression};
+use crate::jack_compiler;
+use crate::json::Value;
+use crate::json_output;
+use crate::junit;
+use crate::linker;
+use crate::listing::Listing;
+use crate::optimizer;
 use crate::parser;
-use crate::parser::InstructionType;
+use crate::parser::{Instruction, Location, Statement};
+use crate::pseudo;
+use crate::sarif;
+use crate::source_map::SourceMap;
+use crate::symbol_table::{Layout, SymbolTable};
+use crate::test_script;
+use crate::vm;
+
+// What a build leaves to print: the report, and for `--format json` the
+// instructions and symbols, see json_output.rs
+pub struct Output {
+    pub report: String,
+    pub program: Option<(Value, Value)>,
+}
 
 pub fn assembly(config: config::Config) -> Result<(), Box<dyn Error>> {
-    let output_filename: String;
+    let mut diagnostics = vec![];
+    let result = build(&config, &mut diagnostics);
+    let file = config.filename.as_str();
+
+    match config.format {
+        Format::Text | Format::Gcc => {
+            for diagnostic in &diagnostics {
+                if config.format == Format::Gcc {
+                    println!("{}", diagnostic.to_gcc(file));
+                } else {
+                    println!("{}", render(diagnostic, file));
+                }
+            }
+            print!("{}", result?.report);
+            return Ok(());
+        }
+        Format::Json => {
+            let program = result
+                .as_ref()
+                .ok()
+                .and_then(|output| output.program.clone());
+            let mut document = json_output::document(file, program, &diagnostics);
+            if let Err(error) = &result {
+                document = document.with("error", error.to_string());
+            }
+            println!("{}", document);
+        }
+        Format::Sarif => {
+            let error = result.as_ref().err().map(|error| error.to_string());
+            println!("{}", sarif::document(file, &diagnostics, error.as_deref()));
+        }
+        Format::Junit => {
+            let (cases, failure) = test_cases(&config, &diagnostics, &result);
+            print!("{}", junit::document(file, &cases));
+            if let Some(failure) = failure {
+                return Err(failure.into());
+            }
+        }
+    }
+    result.map(|_| ())
+}
+
+// A test case for every file with the diagnostics in it, and one for the
+// test script with `--test`. Returns why the test script failed, if it did.
+fn test_cases(
+    config: &config::Config,
+    diagnostics: &[Diagnostic],
+    result: &Result<Output, Box<dyn Error>>,
+) -> (Vec<junit::Case>, Option<String>) {
+    let file_of = |diagnostic: &Diagnostic| {
+        diagnostic
+            .file
+            .clone()
+            .unwrap_or_else(|| config.filename.clone())
+    };
+    let mut files = config.files.clone();
+    for file in diagnostics.iter().map(file_of) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
 
-    if config.filename.find(".").is_some() {
-        let filename: Vec<&str> = config.filename.split(".").collect();
-        output_filename = format!("{}.hack", filename[0]);
+    let mut cases: Vec<junit::Case> = files
+        .iter()
+        .map(|file| {
+            let found: Vec<&Diagnostic> = diagnostics
+                .iter()
+                .filter(|diagnostic| file_of(diagnostic) == *file)
+                .collect();
+            junit::Case::assembled(file, &found)
+        })
+        .collect();
+    // errors that are not about the source, like a file that is missing
+    if let Err(error) = result {
+        if cases.iter().all(|case| case.failure.is_none()) {
+            cases[0].failure = Some((error.to_string(), String::new()));
+        }
+    }
+
+    if !config.test {
+        return (cases, None);
+    }
+    let script = test_script::script_for(&config.filename).unwrap_or(config.filename.clone());
+    let outcome = match result {
+        Ok(_) => test_script::run_for(&config.filename).map_err(|error| error.to_string()),
+        Err(_) => Err(String::from("not run, the program did not assemble")),
+    };
+    let (failure, output) = match &outcome {
+        Ok(message) => (None, format!("{}\n", message)),
+        Err(error) => (Some((error.clone(), String::new())), String::new()),
+    };
+    cases.push(junit::Case {
+        class: "test",
+        name: script,
+        failure,
+        output,
+    });
+    (
+        cases,
+        outcome.err().map(|error| format!("Test failed: {}", error)),
+    )
+}
+
+// The diagnostic with its source line, in colour when printed to a
+// terminal that does not ask for none
+pub fn render(diagnostic: &Diagnostic, filename: &str) -> String {
+    let colour = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    diagnostic.render(filename, &|file| fs::read_to_string(file).ok(), colour)
+}
+
+// Assembles the program and writes its output files, collecting warnings
+// and errors in `diagnostics`. Returns the report to print on success, the
+// RAM map when asked for and the size summary.
+pub fn build(
+    config: &config::Config,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Output, Box<dyn Error>> {
+    if config.compile {
+        return linker::compile(config, diagnostics);
+    }
+    if config.links() {
+        return linker::build(config, diagnostics);
+    }
+
+    // Jack classes are compiled to VM code, and VM code is translated, the
+    // generated .vm and .asm files are kept next to their sources
+    let mut source = config.filename.clone();
+    if jack_compiler::is_jack(&source) {
+        source = jack_compiler::compile(&source, config.xml)?;
+    }
+
+    // the whole file is read before parsing, there is no streaming, the
+    // statements borrow from it so that it is only held once
+    let (filename, contents) = if vm::is_vm(&source) {
+        let contents = vm::translate(&source, diagnostics)?;
+        let filename = vm::output_filename(&source);
+        fs::write(&filename, &contents)?;
+        (filename.to_string_lossy().into_owned(), contents)
     } else {
-        output_filename = format!("{}.hack", config.filename);
+        let contents = fs::read_to_string(&source)?;
+        (source, contents)
+    };
+    let (mut statements, directives) = parse(&contents);
+
+    if statements.is_empty() {
+        // TODO, return error
+        return Ok(Output {
+            report: String::new(),
+            program: None,
+        });
+    }
+
+    let entries = entries(&directives);
+    let jump_words = jump_to_entry(&mut statements, &entries);
+
+    // the checks are for the source, not the jump put before it
+    let (symbol_table, errors) = allocate(&statements, &directives, &config.layout);
+    let source = &statements[jump_words..];
+    diagnostics.extend(check(source, &symbol_table));
+    if config.warn_case {
+        diagnostics.extend(case_warnings(source));
+        diagnostics.sort_by_key(|diagnostic| diagnostic.location.line);
+    }
+    let count = diagnostics.iter().filter(|d| d.is_error()).count();
+    if count > 0 {
+        return Err(format!("could not assemble {} due to {} errors", filename, count).into());
+    }
+
+    // labels are resolved again after optimizing, so their addresses
+    // reflect the final layout of the program
+    let (symbol_table, mut errors) = match config.optimize {
+        true => {
+            optimizer::optimize(&mut statements);
+            allocate(&statements, &directives, &config.layout)
+        }
+        false => (symbol_table, errors),
+    };
+    errors.extend(check_entries(&entries, &symbol_table));
+    errors.extend(check_rom(&statements, &symbol_table, config.rom_size));
+    let image = match data::image(&directives, &symbol_table) {
+        Ok(image) => image,
+        Err(image_errors) => {
+            errors.extend(image_errors);
+            vec![]
+        }
+    };
+    if !errors.is_empty() {
+        let count = errors.len();
+        diagnostics.extend(errors);
+        return Err(format!("could not assemble {} due to {} errors", filename, count).into());
+    }
+
+    let mut report = String::new();
+    if config.ram_map {
+        report.push_str(&symbol_table.ram_map());
+    }
+    if !image.is_empty() {
+        fs::write(output_filename(&filename, "ram"), data::to_ram(&image))?;
+    }
+
+    let mut file = BufWriter::new(fs::File::create(output_filename(&filename, "hack"))?);
+    let mut source_map = SourceMap::new(&filename);
+    let mut listing = config.listing.then(|| Listing::new(&contents));
+    let mut instructions = vec![];
+    let resolve = |name: &str| symbol_table.get_address(name);
+
+    for statement in &statements {
+        match encode(&statement.instruction, &symbol_table) {
+            Some(word) => {
+                let address = source_map.locations.len() as u32;
+                if config.format == Format::Json {
+                    let expression = match &statement.instruction {
+                        Instruction::A(symbol) => Some(symbol.as_ref()),
+                        _ => None,
+                    };
+                    let bits = u16::from_str_radix(&word, 2)?;
+                    let location = statement.location;
+                    instructions.push(json_output::instruction(
+                        address as usize,
+                        bits,
+                        &filename,
+                        location,
+                        expression,
+                        &resolve,
+                    ));
+                }
+                if let Some(listing) = &mut listing {
+                    listing.add(statement, Some((address, &word)));
+                }
+                source_map.add_instruction(statement.location);
+                writeln!(file, "{}", word)?;
+            }
+            None => {
+                if let Some(listing) = &mut listing {
+                    listing.add(statement, None);
+                }
+                if let Instruction::L(label) = &statement.instruction {
+                    if let Some(address) = symbol_table.get_address(label) {
+                        source_map.add_label(label, address, statement.location);
+                    }
+                }
+            }
+        }
+    }
+
+    file.flush()?;
+
+    if let Some(listing) = listing {
+        fs::write(output_filename(&filename, "lst"), listing.to_string())?;
+    }
+
+    if config.source_map {
+        for variable in &symbol_table.variables {
+            if let Some(address) = symbol_table.get_address(variable) {
+                source_map.add_variable(variable, address);
+            }
+        }
+
+        fs::write(
+            output_filename(&filename, "hack.map"),
+            source_map.to_json().to_string(),
+        )?;
+    }
+
+    report.push_str(&format!(
+        "{}: {} of {} words, {} labels, {} variables\n",
+        output_filename(&filename, "hack").display(),
+        source_map.locations.len(),
+        config.rom_size,
+        symbol_table.labels.len(),
+        symbol_table.variables.len()
+    ));
+
+    let program = (config.format == Format::Json).then(|| {
+        (
+            Value::from(instructions),
+            json_output::symbols(&symbol_table),
+        )
+    });
+    Ok(Output { report, program })
+}
+
+// The statements that go in ROM, which borrow from `contents`, and the
+// directives, in one pass over the lines. Only the lines are not copied,
+// `contents` has to be read in full first.
+pub fn parse(contents: &str) -> (Vec<Statement<'_>>, Vec<(Directive, Location)>) {
+    let source = read(contents);
+    let mut statements: Vec<Statement> = vec![];
+    let mut directives = vec![];
+
+    // local and anonymous labels get their full names, references to
+    // labels that do not exist are left for check() to report
+    let mut names = SymbolTable::new();
+    let defined: HashSet<String> = source
+        .iter()
+        .filter_map(|statement| match &statement.instruction {
+            Instruction::L(label) => Some(names.scoped_label(label)),
+            _ => None,
+        })
+        .collect();
+    let mut names = SymbolTable::new();
+
+    for mut statement in source {
+        // data directives fill RAM rather than ROM, see data::image()
+        if let Some(Ok(directive)) = data::directive(&statement.instruction) {
+            directives.push((directive, statement.location));
+            continue;
+        }
+
+        if let Instruction::L(label) = &statement.instruction {
+            let name = names.scoped_label(label);
+            if name != label.as_ref() {
+                statement.instruction = Instruction::L(name.into());
+            }
+        }
+
+        // pseudo-ops are replaced by their expansion, the ones with bad
+        // operands are left for check() to report, as are bad directives
+        let expansion = match pseudo::expand(&statement.instruction, statement.location.line) {
+            Some(Ok(expansion)) => expansion,
+            _ => vec![statement.instruction],
+        };
+        for instruction in expansion {
+            statements.push(Statement {
+                instruction: scoped(instruction, &names, &defined),
+                location: statement.location,
+            });
+        }
     }
 
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(output_filename)
-        .unwrap();
+    (statements, directives)
+}
 
-    let contents = fs::read_to_string(config.filename)?;
+// @.loop+1 -> @MAIN.loop+1, @+ -> @:3
+// only the instructions that change are copied
+fn scoped<'a>(
+    instruction: Instruction<'a>,
+    names: &SymbolTable,
+    defined: &HashSet<String>,
+) -> Instruction<'a> {
+    let symbol = match &instruction {
+        Instruction::A(symbol) => symbol,
+        _ => return instruction,
+    };
+    if let Some(name) = names.scoped_symbol(symbol) {
+        if defined.contains(&name) {
+            return Instruction::A(name.into());
+        }
+    }
+
+    // only local and anonymous references have a scope
+    if !symbol.contains(['.', '+', '-']) {
+        return instruction;
+    }
+    let replacements: Vec<(usize, usize, String)> = match expression::parse(symbol) {
+        Ok(parsed) => parsed
+            .symbols()
+            .into_iter()
+            .filter_map(|(name, offset)| {
+                let full = names.scoped_symbol(name).filter(|n| defined.contains(n))?;
+                Some((offset as usize, name.len(), full))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    if replacements.is_empty() {
+        return instruction;
+    }
+
+    let mut text = symbol.to_string();
+    // from the end, so that the offsets stay valid
+    for (start, length, full) in replacements.into_iter().rev() {
+        text.replace_range(start..start + length, &full);
+    }
+    Instruction::A(text.into())
+}
+
+// one `.entry` per program, at a label
+// `.entry MAIN`, with where it is set
+pub fn entries(directives: &[(Directive, Location)]) -> Vec<(&str, Location)> {
+    directives
+        .iter()
+        .filter_map(|(directive, location)| match directive {
+            Directive::Entry(label) => Some((label.as_str(), *location)),
+            _ => None,
+        })
+        .collect()
+}
+
+// The program starts with a jump to its entry point, as the linker would
+// put it there. Returns the number of words put before the source.
+pub fn jump_to_entry(statements: &mut Vec<Statement>, entries: &[(&str, Location)]) -> usize {
+    let (label, location) = match entries.first() {
+        Some(entry) => entry,
+        None => return 0,
+    };
+    let jump = [
+        Instruction::A(String::from(*label).into()),
+        Instruction::C {
+            dest: "".into(),
+            comp: "0".into(),
+            jump: "JMP".into(),
+        },
+    ];
+    let jump = jump.into_iter().map(|instruction| Statement {
+        instruction,
+        location: *location,
+    });
+    statements.splice(0..0, jump);
+    2
+}
+
+pub fn check_entries(entries: &[(&str, Location)], symbol_table: &SymbolTable) -> Vec<Diagnostic> {
+    let length = ".entry".len() as u32;
+    let mut diagnostics = vec![];
+    for (i, (label, location)) in entries.iter().enumerate() {
+        if i > 0 {
+            let message = String::from("the entry point is set twice");
+            let first = String::from("first set here");
+            diagnostics.push(Diagnostic::error(*location, length, message).with_note(
+                entries[0].1,
+                length,
+                first,
+            ));
+        } else if !symbol_table.labels.iter().any(|name| name == label) {
+            let message = format!("`{}` is not a label of the program", label);
+            diagnostics.push(Diagnostic::error(*location, length, message));
+        }
+    }
+    diagnostics
+}
+
+// every line as it was written
+pub fn read(contents: &str) -> Vec<Statement<'_>> {
     let mut parser = parser::Parser::new(contents);
+    let mut statements: Vec<Statement> = vec![];
 
-    if parser.lines.len() == 0 {
-        return Ok(()); // TODO, return error
+    if parser.lines.is_empty() {
+        return statements;
     }
 
     loop {
-        let instruction_type = parser.instruction_type();
+        statements.push(parser.statement());
+
+        if !parser.has_more_lines() {
+            break;
+        }
+
+        parser.advance();
+    }
+
+    statements
+}
+
+// offers the closest valid mnemonic, if one is close
+fn suggest(
+    diagnostic: Diagnostic,
+    table: &[(&'static str, &'static str)],
+    mnemonic: &str,
+) -> Diagnostic {
+    match code::suggest(table, mnemonic) {
+        Some(valid) => diagnostic.with_help(format!("did you mean `{}`?", valid)),
+        None => diagnostic,
+    }
+}
+
+// Reports every problem in the program rather than stopping at the first one.
+// Expressions are evaluated with `symbol_table`, the resolved program.
+pub fn check(statements: &[Statement], symbol_table: &SymbolTable) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    // labels and the symbols used as variables so far, with where they
+    // first appear
+    let mut labels = SymbolTable::new();
+    labels.add_predefined_symbols();
+    let mut seen: HashMap<String, (Location, u32)> = HashMap::new();
+
+    for (i, statement) in statements.iter().enumerate() {
+        let location = statement.location;
+
+        let invalid = match pseudo::expand(&statement.instruction, location.line) {
+            Some(Err(message)) => Some(message),
+            _ => match data::directive(&statement.instruction) {
+                Some(Err(message)) => Some(message),
+                _ => None,
+            },
+        };
+        if let Some(message) = invalid {
+            diagnostics.push(Diagnostic::error(
+                location,
+                statement.instruction.to_string().len() as u32,
+                message,
+            ));
+            continue;
+        }
+
+        match &statement.instruction {
+            Instruction::A(symbol) => {
+                if symbol.is_empty() {
+                    diagnostics.push(Diagnostic::error(
+                        location,
+                        1,
+                        String::from("missing symbol after `@`"),
+                    ));
+                } else if symbol.chars().all(|c| c == '+') || symbol.chars().all(|c| c == '-') {
+                    // left as written by parse() when there is no such label
+                    diagnostics.push(Diagnostic::error(
+                        offset(location, 1),
+                        symbol.len() as u32,
+                        format!("`@{}` has no anonymous label to refer to", symbol),
+                    ));
+                } else {
+                    match expression::parse(symbol) {
+                        Err(error) => diagnostics.push(Diagnostic::error(
+                            offset(location, 1 + error.offset),
+                            error.length,
+                            error.message,
+                        )),
+                        Ok(Expression::Number(value)) if value > code::MAX_CONSTANT as i64 => {
+                            diagnostics.push(Diagnostic::error(
+                                offset(location, 1),
+                                symbol.len() as u32,
+                                format!("constant `{}` does not fit in 15 bits", symbol),
+                            ))
+                        }
+                        // the value depends on the symbols, see below
+                        Ok(parsed) if accesses_memory(statements.get(i + 1)) => {
+                            for (name, at) in parsed.symbols() {
+                                if !labels.contains(name) && !name.starts_with('.') {
+                                    let _ = labels.add_variable(String::from(name));
+                                    seen.insert(
+                                        String::from(name),
+                                        (offset(location, 1 + at), name.len() as u32),
+                                    );
+                                }
+                            }
+                        }
+                        Ok(_) => {}
+                    }
+                }
+            }
+            Instruction::L(label) => {
+                let at = (offset(location, 1), label.len() as u32);
+                if !expression::is_symbol(label) {
+                    diagnostics.push(Diagnostic::error(
+                        at.0,
+                        at.1,
+                        format!(
+                            "invalid label `{}`, labels are letters, digits, `_`, `.`, `$` and `:` and may not start with a digit",
+                            label
+                        ),
+                    ));
+                    continue;
+                }
+                match labels.add_label(label.to_string(), 0) {
+                    Ok(()) => {
+                        seen.insert(label.to_string(), at);
+                    }
+                    Err(message) => {
+                        let mut diagnostic = Diagnostic::error(at.0, at.1, message);
+                        if let Some((first, length)) = seen.get(label.as_ref()) {
+                            let note = if labels.variables.iter().any(|v| v == label) {
+                                "used as a variable here"
+                            } else {
+                                "first defined here"
+                            };
+                            diagnostic = diagnostic.with_note(*first, *length, String::from(note));
+                        }
+                        diagnostics.push(diagnostic);
+                    }
+                }
+            }
+            Instruction::C { comp, .. } if comp.starts_with('(') => {
+                diagnostics.push(Diagnostic::error(
+                    location,
+                    statement.instruction.to_string().len() as u32,
+                    String::from("expected `)` at the end of the label"),
+                ));
+            }
+            Instruction::C { dest, comp, jump } => {
+                let mut column = 0;
+
+                if !dest.is_empty() {
+                    if code::lookup(&code::DEST, dest).is_none() {
+                        diagnostics.push(suggest(
+                            Diagnostic::error(
+                                location,
+                                dest.len() as u32,
+                                format!("invalid dest `{}`", dest),
+                            ),
+                            &code::DEST,
+                            dest,
+                        ));
+                    }
+                    column += dest.len() as u32 + 1;
+                }
+
+                if comp.is_empty() {
+                    diagnostics.push(Diagnostic::error(
+                        offset(location, column),
+                        1,
+                        String::from("missing comp"),
+                    ));
+                } else if code::lookup(&code::COMP, comp).is_none() {
+                    diagnostics.push(suggest(
+                        Diagnostic::error(
+                            offset(location, column),
+                            comp.len() as u32,
+                            format!("invalid comp `{}`", comp),
+                        ),
+                        &code::COMP,
+                        comp,
+                    ));
+                }
+                column += comp.len() as u32 + 1;
+
+                if !jump.is_empty() && code::lookup(&code::JUMP, jump).is_none() {
+                    diagnostics.push(suggest(
+                        Diagnostic::error(
+                            offset(location, column),
+                            jump.len() as u32,
+                            format!("invalid jump `{}`", jump),
+                        ),
+                        &code::JUMP,
+                        jump,
+                    ));
+                }
+            }
+        }
+    }
+
+    // expressions can only be evaluated once labels and variables have
+    // their addresses
+    for statement in statements {
+        let symbol = match &statement.instruction {
+            Instruction::A(symbol) if code::literal(symbol).is_none() => symbol,
+            _ => continue,
+        };
+        let location = offset(statement.location, 1);
+
+        match value(symbol, symbol_table) {
+            Ok(value) if (0..=code::MAX_CONSTANT as i64).contains(&value) => {}
+            Ok(value) => diagnostics.push(Diagnostic::error(
+                location,
+                symbol.len() as u32,
+                format!(
+                    "`{}` evaluates to {}, which does not fit in 15 bits",
+                    symbol, value
+                ),
+            )),
+            Err(error) if error.message.starts_with("division") => diagnostics.push(
+                Diagnostic::error(location, symbol.len() as u32, error.message),
+            ),
+            // local labels are the only symbols not allocated as variables
+            Err(error) if error.message.starts_with("unknown") => diagnostics.push(
+                Diagnostic::error(offset(location, error.offset), error.length, error.message),
+            ),
+            // syntax errors were reported above
+            Err(_) => {}
+        }
+    }
 
-        match instruction_type {
-            InstructionType::AINSTRUCTION => {
-                // println!("A-INSTRUCTION");
-                let symbol = parser.symbol();
-                // println!("symbol: {}", symbol);
-                write!(file, "{}", format!("{}\n", code::variable(&symbol)))?;
+    // in source order, problems found on the same line keep their order
+    diagnostics.sort_by_key(|diagnostic| diagnostic.location.line);
+    diagnostics
+}
+
+// Warns about symbols that differ only in case from an earlier one or a
+// predefined symbol, `@loop` is a new variable rather than the label
+// `LOOP`.
+pub fn case_warnings(statements: &[Statement]) -> Vec<Diagnostic> {
+    // the first spelling of every name in lowercase, with where it is
+    // first used, predefined symbols have no location
+    let mut predefined = SymbolTable::new();
+    predefined.add_predefined_symbols();
+    let mut first: HashMap<String, (String, Option<(Location, u32)>)> = predefined
+        .symbols
+        .into_keys()
+        .map(|name| (name.to_lowercase(), (name, None)))
+        .collect();
+    let mut seen: HashSet<String> = first.values().map(|(name, _)| name.clone()).collect();
+    let mut warnings = vec![];
+
+    for statement in statements {
+        let location = statement.location;
+        let symbols = match &statement.instruction {
+            Instruction::A(symbol) => match expression::parse(symbol) {
+                Ok(parsed) => parsed
+                    .symbols()
+                    .into_iter()
+                    .map(|(name, at)| (String::from(name), offset(location, 1 + at)))
+                    .collect(),
+                Err(_) => vec![],
+            },
+            Instruction::L(label) => vec![(label.to_string(), offset(location, 1))],
+            Instruction::C { .. } => vec![],
+        };
+
+        for (name, at) in symbols {
+            if !seen.insert(name.clone()) {
+                continue;
             }
-            InstructionType::LINSTRUCTION => {
-                // println!("L-INSTRUCTION");
-                let label = parser.symbol();
-                // println!("label: {}", label);
-                // TODO
+            let length = name.len() as u32;
+
+            match first.get(&name.to_lowercase()) {
+                Some((other, used)) => {
+                    let mut warning = Diagnostic::warning(
+                        at,
+                        length,
+                        format!("`{}` differs only in case from `{}`", name, other),
+                    );
+                    if let Some((used, other_length)) = used {
+                        warning = warning.with_note(
+                            *used,
+                            *other_length,
+                            format!("`{}` is used here", other),
+                        );
+                    }
+                    warnings.push(warning);
+                }
+                None => {
+                    first.insert(name.to_lowercase(), (name, Some((at, length))));
+                }
             }
-            InstructionType::CINSTRUCTION => {
-                // println!("C-INSTRUCTION");
-                let dest = parser.dest();
-                let dest_bin = code::dest(&dest);
+        }
+    }
 
-                let comp = parser.comp();
-                let comp_bin = code::comp(&comp);
+    warnings
+}
 
-                let jump = parser.jump();
-                let jump_bin = code::jump(&jump);
+// Reports a program that does not fit in a ROM of `size` words, and the
+// labels past its end, which no A-instruction can reach.
+pub fn check_rom(
+    statements: &[Statement],
+    symbol_table: &SymbolTable,
+    size: u32,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let words = statements
+        .iter()
+        .filter(|statement| !matches!(statement.instruction, Instruction::L(_)))
+        .count();
 
-                // println!("symbolic: {}:{}:{}", comp, dest, jump);
-                // println!("  binary: {}:{}:{}", comp_bin, dest_bin, jump_bin);
-                write!(
-                    file,
-                    "{}",
-                    format!("111{}{}{}\n", comp_bin, dest_bin, jump_bin)
-                )?;
+    let mut address = 0;
+    for statement in statements {
+        let location = statement.location;
+        match &statement.instruction {
+            Instruction::L(label) => {
+                let address = symbol_table.get_address(label).unwrap_or(address);
+                if address >= size {
+                    diagnostics.push(Diagnostic::error(
+                        offset(location, 1),
+                        label.len() as u32,
+                        format!(
+                            "label `{}` is at ROM[{}], past the end of the {} word ROM",
+                            label, address, size
+                        ),
+                    ));
+                }
+            }
+            instruction => {
+                if address == size {
+                    diagnostics.push(Diagnostic::error(
+                        location,
+                        instruction.to_string().len() as u32,
+                        format!(
+                            "the program needs {} words but the ROM holds {}",
+                            words, size
+                        ),
+                    ));
+                }
+                address += 1;
             }
         }
+    }
+
+    diagnostics
+}
+
+// @x followed by M=D uses x as a variable
+fn accesses_memory(next: Option<&Statement>) -> bool {
+    match next.map(|statement| &statement.instruction) {
+        Some(Instruction::C { dest, comp, .. }) => dest.contains('M') || comp.contains('M'),
+        _ => false,
+    }
+}
 
-        if parser.has_more_lines() != true {
+// Evaluates the expression of an A-instruction or directive.
+pub fn value(text: &str, symbol_table: &SymbolTable) -> Result<i64, expression::Error> {
+    if let Some(address) = symbol_table.get_address(text) {
+        return Ok(address as i64);
+    }
+    let resolve = |name: &str| symbol_table.get_address(name).map(|address| address as i64);
+    expression::parse(text)?.evaluate(&resolve)
+}
+
+// Builds the symbol table for the program, labels first so that variables
+// are allocated only for symbols that are never declared.
+pub fn resolve(statements: &[Statement]) -> SymbolTable {
+    allocate(statements, &[], &Layout::new()).0
+}
+
+// Like resolve(), with the variables laid out as configured and the ones
+// pinned by `.var`. Reports the variables that do not fit and bad `.var`
+// directives.
+pub fn allocate(
+    statements: &[Statement],
+    directives: &[(Directive, Location)],
+    layout: &Layout,
+) -> (SymbolTable, Vec<Diagnostic>) {
+    let mut symbol_table = SymbolTable::with_layout(layout);
+    symbol_table.add_predefined_symbols();
+    add_labels(statements, &mut symbol_table);
+    let mut diagnostics = vec![];
+
+    for (directive, location) in directives {
+        if let Directive::Var(name, address) = directive {
+            let pinned = match value(address, &symbol_table) {
+                Ok(address) if address >= 0 => {
+                    symbol_table.pin_variable(name.clone(), address as u32)
+                }
+                Ok(address) => Err(format!(
+                    "RAM[{}] is outside of the variable memory",
+                    address
+                )),
+                Err(error) => Err(error.message),
+            };
+            if let Err(message) = pinned {
+                diagnostics.push(Diagnostic::error(*location, 4, message));
+            }
+        }
+    }
+
+    // every other symbol in order of first use, a local label that is not
+    // defined is an error rather than a variable
+    let mut variables: Vec<(String, Location)> = vec![];
+    let mut seen = HashSet::new();
+    for statement in statements {
+        let parsed = match &statement.instruction {
+            Instruction::A(symbol) => match expression::parse(symbol) {
+                Ok(parsed) => parsed,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        for (name, at) in parsed.symbols() {
+            if !symbol_table.contains(name)
+                && !name.starts_with('.')
+                && seen.insert(String::from(name))
+            {
+                variables.push((String::from(name), offset(statement.location, 1 + at)));
+            }
+        }
+    }
+    if layout.alphabetical {
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    for (name, location) in variables {
+        let length = name.len() as u32;
+        if let Err(message) = symbol_table.add_variable(name) {
+            // the variables after it do not fit either
+            diagnostics.push(Diagnostic::error(location, length, message));
             break;
         }
+    }
 
-        parser.advance();
+    (symbol_table, diagnostics)
+}
+
+// Returns the binary word for an instruction, labels produce no code.
+pub fn encode(instruction: &Instruction, symbol_table: &SymbolTable) -> Option<String> {
+    match instruction {
+        Instruction::A(symbol) => {
+            if code::literal(symbol).is_some() {
+                Some(code::variable(symbol))
+            } else {
+                // check() reports the values that do not fit
+                let value = value(symbol, symbol_table).unwrap_or(0);
+                Some(format!("{:016b}", value & code::MAX_CONSTANT as i64))
+            }
+        }
+        Instruction::L(_) => None,
+        Instruction::C { dest, comp, jump } => {
+            let dest_bin = code::dest(dest);
+            let comp_bin = code::comp(comp);
+            let jump_bin = code::jump(jump);
+
+            Some(format!("111{}{}{}", comp_bin, dest_bin, jump_bin))
+        }
+    }
+}
+
+// Max.asm -> Max.hack
+pub fn output_filename(filename: &str, extension: &str) -> PathBuf {
+    Path::new(filename).with_extension(extension)
+}
+
+fn offset(location: Location, columns: u32) -> Location {
+    Location {
+        line: location.line,
+        column: location.column + columns,
+    }
+}
+
+// first pass, a label refers to the address of the instruction following it
+fn add_labels(statements: &[Statement], symbol_table: &mut SymbolTable) {
+    let mut address = 0;
+
+    for statement in statements {
+        match &statement.instruction {
+            // check() reports labels defined twice
+            Instruction::L(label) => {
+                let _ = symbol_table.add_label(label.to_string(), address);
+            }
+            _ => address += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assembled(contents: &str) -> Vec<String> {
+        let (statements, _) = parse(contents);
+        let symbol_table = resolve(&statements);
+        statements
+            .iter()
+            .filter_map(|s| encode(&s.instruction, &symbol_table))
+            .collect()
+    }
+
+    fn checked(contents: &str) -> Vec<Diagnostic> {
+        let (statements, _) = parse(contents);
+        check(&statements, &resolve(&statements))
+    }
+
+    fn errors(contents: &str) -> Vec<String> {
+        checked(contents).iter().map(|d| d.to_string()).collect()
     }
 
-    Ok(())
+    #[test]
+    fn labels_and_variables() {
+        let contents = "@i\nM=1\n(LOOP)\n@LOOP\n0;JMP\n@j";
+        assert_eq!(
+            vec![
+                "0000000000010000",
+                "1110111111001000",
+                "0000000000000010",
+                "1110101010000111",
+                "0000000000010001",
+            ],
+            assembled(contents)
+        );
+    }
+
+    #[test]
+    fn predefined_symbols() {
+        assert_eq!(vec!["0100000000000000"], assembled("@SCREEN"));
+    }
+
+    #[test]
+    fn comp_only() {
+        assert_eq!(vec!["1110001100000000"], assembled("D"));
+    }
+
+    #[test]
+    fn check_valid() {
+        assert!(errors("@i\nM=1\n(LOOP)\n@LOOP\nD;JGT\n@32767").is_empty());
+    }
+
+    #[test]
+    fn check_invalid_comp() {
+        assert_eq!(
+            vec!["error: invalid comp `D+2` (line 2, column 5)"],
+            errors("@1\n  D=D+2")
+        );
+    }
+
+    #[test]
+    fn check_invalid_dest_and_jump() {
+        assert_eq!(
+            vec![
+                "error: invalid dest `X` (line 1, column 1)",
+                "error: invalid jump `JMQ` (line 1, column 7)",
+            ],
+            errors("X=D+1;JMQ")
+        );
+    }
+
+    #[test]
+    fn check_suggestions() {
+        let help: Vec<Option<String>> = checked("D=D+2\nMD=D;JMQ\nX=1\n")
+            .into_iter()
+            .map(|d| d.help)
+            .collect();
+        assert_eq!(
+            vec![
+                Some(String::from("did you mean `D+1`?")),
+                Some(String::from("did you mean `JMP`?")),
+                None
+            ],
+            help
+        );
+    }
+
+    #[test]
+    fn check_missing_comp() {
+        assert_eq!(vec!["error: missing comp (line 1, column 3)"], errors("D="));
+    }
+
+    #[test]
+    fn check_constant_too_large() {
+        assert_eq!(
+            vec!["error: constant `32768` does not fit in 15 bits (line 1, column 2)"],
+            errors("@32768")
+        );
+    }
+
+    #[test]
+    fn check_missing_symbol() {
+        assert_eq!(
+            vec!["error: missing symbol after `@` (line 1, column 1)"],
+            errors("@")
+        );
+    }
+
+    #[test]
+    fn pseudo_ops_shift_addresses() {
+        let contents = "GOTO END\nJEQ D, END\nMOV R1, R2\n(END)\nHALT";
+        assert_eq!(
+            vec![
+                "0000000000001000",
+                "1110101010000111",
+                "0000000000001000",
+                "1110001100000010",
+                "0000000000000010",
+                "1111110000010000",
+                "0000000000000001",
+                "1110001100001000",
+                "0000000000001000",
+                "1110101010000111",
+            ],
+            assembled(contents)
+        );
+    }
+
+    #[test]
+    fn check_pseudo_ops() {
+        assert_eq!(
+            vec![
+                "error: `GOTO` expects a label (line 1, column 1)",
+                "error: `PUSH` expects `D` (line 2, column 3)",
+            ],
+            errors("GOTO\n  PUSH M\nPOP D")
+        );
+    }
+
+    #[test]
+    fn directives_take_no_rom() {
+        assert_eq!(
+            vec!["0000000000000000", "1110101010000111"],
+            assembled(".data 16\n.word 1, 2\n(L)\n@L\n0;JMP")
+        );
+        assert_eq!(
+            vec![
+                "error: `.data` expects an address (line 1, column 1)",
+                "error: unterminated string (line 2, column 1)",
+            ],
+            errors(".data\n.word \"a")
+        );
+    }
+
+    #[test]
+    fn literals_and_expressions() {
+        assert_eq!(
+            vec![
+                "0100000000000000",
+                "0000000000001010",
+                "0000000001000001",
+                "0000000010000000",
+                "0100000000100000",
+                "0000000000001011",
+                "0000000000010000",
+                "0000000000010001",
+            ],
+            assembled("@0x4000\n@0b1010\n@'A'\n@'\\n'\n@SCREEN+32\n@WIDTH*2-1\n(WIDTH)\n@i\n@i+1")
+        );
+    }
+
+    #[test]
+    fn local_and_anonymous_labels() {
+        let contents =
+            "(A)\n@.loop\n(.loop)\n@.loop+1\n()\n@-\n@+\n(B)\n()\n@.loop\n(.loop)\n@--\nHALT";
+        let statements: Vec<String> = parse(contents)
+            .0
+            .iter()
+            .map(|s| s.instruction.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "(A)",
+                "@A.loop",
+                "(A.loop)",
+                "@A.loop+1",
+                "(:1)",
+                "@:1",
+                "@:2",
+                "(B)",
+                "(:2)",
+                "@B.loop",
+                "(B.loop)",
+                "@:1",
+                "(:halt.13)",
+                "@:halt.13",
+                "0;JMP",
+            ],
+            statements
+        );
+        assert_eq!(
+            vec!["0000000000000001", "0000000000000010", "0000000000000010"],
+            assembled(contents)[..3]
+        );
+    }
+
+    #[test]
+    fn check_labels() {
+        assert_eq!(
+            vec![
+                "error: `@-` has no anonymous label to refer to (line 1, column 2)",
+                "error: unknown symbol `.end` (line 3, column 2)",
+                "error: label `LOOP` is defined twice (line 5, column 2)\n  \
+                 note: first defined here (line 2, column 2)",
+                "error: label `LOOP.x` is defined twice (line 7, column 2)\n  \
+                 note: first defined here (line 6, column 2)",
+                "error: `@++` has no anonymous label to refer to (line 8, column 2)",
+            ],
+            errors("@-\n(LOOP)\n@.end\n()\n(LOOP)\n(.x)\n(.x)\n@++")
+        );
+    }
+
+    #[test]
+    fn check_conflicts() {
+        assert_eq!(
+            vec![
+                "error: label `SCREEN` shadows a predefined symbol (line 3, column 2)",
+                "error: label `count` is also used as a variable (line 5, column 2)\n  \
+                 note: used as a variable here (line 1, column 2)",
+            ],
+            errors("@count\nM=0\n(SCREEN)\n@END\n(count)\n0;JMP\n(END)\n@END\nD=A")
+        );
+    }
+
+    #[test]
+    fn check_symbol_names() {
+        assert_eq!(
+            vec![
+                "error: invalid label `LO OP`, labels are letters, digits, `_`, `.`, `$` and `:` and may not start with a digit (line 1, column 2)",
+                "error: invalid label `1st`, labels are letters, digits, `_`, `.`, `$` and `:` and may not start with a digit (line 2, column 2)",
+                "error: expected `)` at the end of the label (line 3, column 1)",
+                "error: unexpected `x` (line 4, column 6)",
+                "error: invalid number `1st` (line 5, column 2)",
+            ],
+            errors("(LO OP)\n(1st)\n(END\n@END x // end\n@1st")
+        );
+    }
+
+    #[test]
+    fn warnings_for_case() {
+        let warnings: Vec<String> =
+            case_warnings(&parse("(LOOP)\n@loop\n@Loop\n@loop\n@sp\n(main)\n@MAIN").0)
+                .iter()
+                .map(|d| d.to_string())
+                .collect();
+        assert_eq!(
+            vec![
+                "warning: `loop` differs only in case from `LOOP` (line 2, column 2)\n  \
+                 note: `LOOP` is used here (line 1, column 2)",
+                "warning: `Loop` differs only in case from `LOOP` (line 3, column 2)\n  \
+                 note: `LOOP` is used here (line 1, column 2)",
+                "warning: `sp` differs only in case from `SP` (line 5, column 2)",
+                "warning: `MAIN` differs only in case from `main` (line 7, column 2)\n  \
+                 note: `main` is used here (line 6, column 2)",
+            ],
+            warnings
+        );
+        assert!(checked("(LOOP)\n@loop\nM=0").is_empty());
+    }
+
+    #[test]
+    fn variable_layout() {
+        let allocated = |contents: &str, layout: &Layout| {
+            let (statements, directives) = parse(contents);
+            let (symbol_table, diagnostics) = allocate(&statements, &directives, layout);
+            let errors: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+            (symbol_table.ram_map(), errors)
+        };
+        let contents = ".var counter 0x20\n@sum\n@i\n@counter\n@b";
+        let mut layout = Layout::new();
+
+        assert_eq!(
+            (
+                String::from("RAM map\n   16  sum\n   17  i\n   18  b\n   32  counter  (pinned)\n"),
+                vec![]
+            ),
+            allocated(contents, &layout)
+        );
+
+        layout.base = 31;
+        layout.alphabetical = true;
+        assert_eq!(
+            (
+                String::from("RAM map\n   31  b\n   32  counter  (pinned)\n   33  i\n   34  sum\n"),
+                vec![]
+            ),
+            allocated(contents, &layout)
+        );
+
+        layout.limit = 33;
+        assert_eq!(
+            vec![String::from(
+                "error: no RAM left for variable `sum`, variables end at RAM[33] (line 2, column 2)"
+            )],
+            allocated(contents, &layout).1
+        );
+        assert_eq!(
+            vec![
+                String::from("error: `LOOP` is already defined (line 1, column 1)"),
+                String::from("error: unknown symbol `nope` (line 2, column 1)"),
+            ],
+            allocated(".var LOOP 1\n.var x nope\n(LOOP)", &Layout::new()).1
+        );
+    }
+
+    #[test]
+    fn rom_limit() {
+        let rom_errors = |contents: &str, size: u32| -> Vec<String> {
+            let (statements, _) = parse(contents);
+            check_rom(&statements, &resolve(&statements), size)
+                .iter()
+                .map(|d| d.to_string())
+                .collect()
+        };
+        assert!(rom_errors("@1\n(END)\n@END\n0;JMP", 4).is_empty());
+        assert_eq!(
+            vec![
+                "error: the program needs 4 words but the ROM holds 2 (line 3, column 1)",
+                "error: label `END` is at ROM[4], past the end of the 2 word ROM (line 5, column 2)",
+            ],
+            rom_errors("@1\nD=A\n@END\n0;JMP\n(END)", 2)
+        );
+    }
+
+    #[test]
+    fn entry_point() {
+        let dir = std::env::temp_dir().join(format!("hack-entry-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let built = |contents: &str| {
+            fs::write(file("Main.asm"), contents).unwrap();
+            let args = ["assembler", &file("Main.asm"), "-l"].map(String::from);
+            let mut diagnostics = vec![];
+            let output = build(&config::Config::new(&args).unwrap(), &mut diagnostics);
+            let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+            output.map(|_| ()).map_err(|_| messages)
+        };
+
+        // a single file is not linked, so there is no cache, and the
+        // listing is written
+        assert_eq!(Ok(()), built(".entry MAIN\n@1\n(MAIN)\n@2\n"));
+        assert_eq!(
+            "0000000000000011\n1110101010000111\n0000000000000001\n0000000000000010\n",
+            fs::read_to_string(file("Main.hack")).unwrap()
+        );
+        assert!(Path::new(&file("Main.lst")).exists());
+        assert!(!dir.join(".hack-cache").exists());
+
+        assert_eq!(
+            Err(vec![String::from(
+                "error: `NOPE` is not a label of the program (line 1, column 1)"
+            )]),
+            built(".entry NOPE\n@1\n")
+        );
+        assert_eq!(
+            Err(vec![String::from(
+                "error: the entry point is set twice (line 2, column 1)\n  note: first set here (line 1, column 1)"
+            )]),
+            built(".entry A\n.entry A\n(A)\n@1\n")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // VM translator output of 1.8 million lines assembles in about a
+    // second, the budget of 3 seconds, 600,000 lines a second, leaves room
+    // for slower machines. It is for release builds:
+    //
+    //     cargo test --release throughput -- --ignored
+    #[test]
+    #[ignore]
+    fn throughput() {
+        const BUDGET: std::time::Duration = std::time::Duration::from_secs(3);
+        // like VM translator output, the ROM limit is not checked here
+        let mut contents = String::from("(LOOP)\n");
+        for i in 0..200_000 {
+            contents.push_str(&format!(
+                "// push static {}\n@Foo.{}\nD=M\n@SP\nAM=M+1 // push\nA=A-1\nM=D\n@LOOP\nD;JGT\n",
+                i % 100,
+                i % 100
+            ));
+        }
+        let lines = contents.lines().count();
+
+        let start = std::time::Instant::now();
+        let (statements, directives) = parse(&contents);
+        let (symbol_table, _) = allocate(&statements, &directives, &Layout::new());
+        assert_eq!(None, check(&statements, &symbol_table).first());
+        let mut output = BufWriter::new(std::io::sink());
+        for statement in &statements {
+            if let Some(word) = encode(&statement.instruction, &symbol_table) {
+                writeln!(output, "{}", word).unwrap();
+            }
+        }
+        let elapsed = start.elapsed();
+
+        assert!(
+            elapsed < BUDGET,
+            "{} lines took {:.2?}, the budget is {:?}",
+            lines,
+            elapsed,
+            BUDGET
+        );
+    }
+
+    #[test]
+    fn check_expressions() {
+        assert_eq!(
+            vec![
+                "error: constant `0x8000` does not fit in 15 bits (line 1, column 2)",
+                "error: invalid number `0b12` (line 2, column 9)",
+                "error: `SCREEN*2` evaluates to 32768, which does not fit in 15 bits (line 3, column 2)",
+                "error: `1-2` evaluates to -1, which does not fit in 15 bits (line 4, column 4)",
+                "error: division by zero (line 5, column 2)",
+            ],
+            errors("@0x8000\n@SCREEN+0b12\n@SCREEN*2\n  @1-2\n@1/0")
+        );
+    }
 }
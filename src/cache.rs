@@ -0,0 +1,80 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::object::{self, Object};
+
+// Assembled files of multi-file programs, stored by the hash of their
+// source so that only the files that changed are assembled again:
+//
+//     .hack-cache/3c9a5e0d41b27f86.obj
+//
+// Entries are never removed, deleting the directory is always safe.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+// FNV-1a, unlike the standard hasher it is the same on every run and
+// every version of Rust
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Cache {
+    // .hack-cache next to the program
+    pub fn new(program: &str) -> Cache {
+        let dir = Path::new(program)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(".hack-cache");
+        Cache { dir }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.obj", key))
+    }
+
+    // entries that cannot be read are assembled again
+    pub fn get(&self, key: u64) -> Option<Object> {
+        object::read(&fs::read_to_string(self.path(key)).ok()?)
+    }
+
+    pub fn put(&self, key: u64, object: &Object) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(key), object.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Word;
+    use crate::parser::Location;
+
+    #[test]
+    fn hashes() {
+        assert_eq!(0xcbf29ce484222325, hash(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, hash(b"a"));
+        assert_ne!(hash(b"@1\nD=A\n"), hash(b"@2\nD=A\n"));
+    }
+
+    #[test]
+    fn entries() {
+        let dir = std::env::temp_dir().join(format!("hack-cache-{}", std::process::id()));
+        let cache = Cache::new(dir.join("Main.asm").to_str().unwrap());
+        let object = Object {
+            code: vec![(Word::Fixed(7), Location { line: 1, column: 1 })],
+            labels: vec![],
+            imports: vec![],
+            directives: vec![],
+        };
+
+        assert_eq!(None, cache.get(1));
+        cache.put(1, &object).unwrap();
+        assert_eq!(Some(object), cache.get(1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
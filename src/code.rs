@@ -1,121 +1,249 @@
-pub fn dest(mnemonic: &str) -> String {
-    let binary: &str;
-
-    // TODO: sort to handle XY and YX?
-    match mnemonic {
-        "null" => binary = "000",
-        "M" => binary = "001",
-        "D" => binary = "010",
-        "DM" => binary = "011",
-        "MD" => binary = "011",
-        "A" => binary = "100",
-        "AM" => binary = "101",
-        "MA" => binary = "101",
-        "AD" => binary = "110",
-        "DA" => binary = "110",
-        "ADM" => binary = "111",
-        "AMD" => binary = "111",
-        "DMA" => binary = "111",
-        "DAM" => binary = "111",
-        "MDA" => binary = "111",
-        "MAD" => binary = "111",
-        _ => binary = "000",
+// largest value an A-instruction can load
+pub const MAX_CONSTANT: u32 = 32767;
+
+// words in the Hack ROM, the most an A-instruction can address
+pub const ROM_SIZE: u32 = MAX_CONSTANT + 1;
+
+// Binary encodings from the Hack specification, also used to offer the
+// valid mnemonics in diagnostics and completions.
+pub const DEST: [(&str, &str); 16] = [
+    ("null", "000"),
+    ("M", "001"),
+    ("D", "010"),
+    ("DM", "011"),
+    ("MD", "011"),
+    ("A", "100"),
+    ("AM", "101"),
+    ("MA", "101"),
+    ("AD", "110"),
+    ("DA", "110"),
+    ("ADM", "111"),
+    ("AMD", "111"),
+    ("DMA", "111"),
+    ("DAM", "111"),
+    ("MDA", "111"),
+    ("MAD", "111"),
+];
+
+pub const COMP: [(&str, &str); 28] = [
+    ("0", "0101010"),
+    ("1", "0111111"),
+    ("-1", "0111010"),
+    ("D", "0001100"),
+    ("A", "0110000"),
+    ("M", "1110000"),
+    ("!D", "0001101"),
+    ("!A", "0110001"),
+    ("!M", "1110001"),
+    ("-D", "0001111"),
+    ("-A", "0110011"),
+    ("-M", "1110011"),
+    ("D+1", "0011111"),
+    ("A+1", "0110111"),
+    ("M+1", "1110111"),
+    ("D-1", "0001110"),
+    ("A-1", "0110010"),
+    ("M-1", "1110010"),
+    ("D+A", "0000010"),
+    ("D+M", "1000010"),
+    ("D-A", "0010011"),
+    ("D-M", "1010011"),
+    ("A-D", "0000111"),
+    ("M-D", "1000111"),
+    ("D&A", "0000000"),
+    ("D&M", "1000000"),
+    ("D|A", "0010101"),
+    ("D|M", "1010101"),
+];
+
+pub const JUMP: [(&str, &str); 8] = [
+    ("null", "000"),
+    ("JGT", "001"),
+    ("JEQ", "010"),
+    ("JGE", "011"),
+    ("JLT", "100"),
+    ("JNE", "101"),
+    ("JLE", "110"),
+    ("JMP", "111"),
+];
+
+// Hack character set codes of the keys that are not printable, the
+// printable ones use their ASCII code.
+pub const KEYS: [(&str, u16); 25] = [
+    ("newline", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+    ("f1", 141),
+    ("f2", 142),
+    ("f3", 143),
+    ("f4", 144),
+    ("f5", 145),
+    ("f6", 146),
+    ("f7", 147),
+    ("f8", 148),
+    ("f9", 149),
+    ("f10", 150),
+    ("f11", 151),
+    ("f12", 152),
+];
+
+// `A` -> 65, `space` -> 32, `newline` -> 128
+pub fn key(name: &str) -> Option<u16> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if (' '..='~').contains(&c) {
+            return Some(c as u16);
+        }
     }
 
-    String::from(binary)
+    let name = name.to_ascii_lowercase();
+    if name == "space" {
+        return Some(32);
+    }
+    KEYS.iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, code)| *code)
 }
 
-pub fn comp(mnemonic: &str) -> String {
-    let binary: &str;
-
-    match mnemonic {
-        "0" => binary = "0101010",
-        "1" => binary = "0111111",
-        "-1" => binary = "0111010",
-        "D" => binary = "0001100",
-        "A" => binary = "0110000",
-        "M" => binary = "1110000",
-        "!D" => binary = "0001101",
-        "!A" => binary = "0110001",
-        "!M" => binary = "1110001",
-        "-D" => binary = "0001111",
-        "-A" => binary = "0110011",
-        "-M" => binary = "1110011",
-        "D+1" => binary = "0011111",
-        "A+1" => binary = "0110111",
-        "M+1" => binary = "1110111",
-        "D-1" => binary = "0001110",
-        "A-1" => binary = "0110010",
-        "M-1" => binary = "1110010",
-        "D+A" => binary = "0000010",
-        "D+M" => binary = "1000010",
-        "D-A" => binary = "0010011",
-        "D-M" => binary = "1010011",
-        "A-D" => binary = "0000111",
-        "M-D" => binary = "1000111",
-        "D&A" => binary = "0000000",
-        "D&M" => binary = "1000000",
-        "D|A" => binary = "0010101",
-        "D|M" => binary = "1010101",
-        _ => binary = "",
+// 42, 0x2a, 0b101010, 'A', '\n', 'left'
+pub fn literal(text: &str) -> Option<u32> {
+    let digits = |digits: &str, radix: u32| {
+        if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+            u32::from_str_radix(digits, radix).ok()
+        } else {
+            None
+        }
+    };
+
+    if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        return digits(hex, 16);
+    }
+    if let Some(binary) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+        return digits(binary, 2);
+    }
+    if let Some(character) = text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        return match character {
+            "\\n" => key("newline"),
+            "\\b" => key("backspace"),
+            "\\'" => Some(39),
+            "\\\\" => Some(92),
+            _ => key(character),
+        }
+        .map(u32::from);
     }
 
-    String::from(binary)
+    digits(text, 10)
 }
 
-pub fn jump(mnemonic: &str) -> String {
-    let binary: &str;
+pub fn lookup(table: &[(&'static str, &'static str)], mnemonic: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(symbol, _)| *symbol == mnemonic)
+        .map(|(_, binary)| *binary)
+}
+
+// the first mnemonic with these bits, the reverse of lookup()
+pub fn mnemonic(table: &[(&'static str, &'static str)], binary: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(_, bits)| *bits == binary)
+        .map(|(symbol, _)| *symbol)
+}
+
+// The valid mnemonic closest to a misspelt one, by edit distance and then
+// by how much of the start they share: `D+2` -> `D+1`, `JMQ` -> `JMP`.
+// None when nothing is close.
+pub fn suggest(table: &[(&'static str, &'static str)], mnemonic: &str) -> Option<&'static str> {
+    let wanted: Vec<char> = mnemonic.to_uppercase().chars().collect();
+    let (distance, _, symbol) = table
+        .iter()
+        .filter(|(symbol, _)| *symbol != "null")
+        .map(|(symbol, _)| {
+            let candidate: Vec<char> = symbol.chars().collect();
+            let prefix = wanted
+                .iter()
+                .zip(&candidate)
+                .take_while(|(a, b)| a == b)
+                .count();
+            (distance(&wanted, &candidate), usize::MAX - prefix, *symbol)
+        })
+        .min_by_key(|(distance, prefix, _)| (*distance, *prefix))?;
+    (distance <= wanted.len() / 2).then_some(symbol)
+}
 
-    match mnemonic {
-        "null" => binary = "000",
-        "JGT" => binary = "001",
-        "JEQ" => binary = "010",
-        "JGE" => binary = "011",
-        "JLT" => binary = "100",
-        "JNE" => binary = "101",
-        "JLE" => binary = "110",
-        "JMP" => binary = "111",
-        _ => binary = "000",
+// Levenshtein distance, one row at a time
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substituted = diagonal + (x != y) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
     }
+    row[b.len()]
+}
 
-    String::from(binary)
+pub fn dest(mnemonic: &str) -> String {
+    // TODO: sort to handle XY and YX?
+    String::from(lookup(&DEST, mnemonic).unwrap_or("000"))
 }
 
-pub fn variable(a_instruction: &str) -> String {
-    let binary: &str;
+pub fn comp(mnemonic: &str) -> String {
+    String::from(lookup(&COMP, mnemonic).unwrap_or(""))
+}
 
+pub fn jump(mnemonic: &str) -> String {
+    String::from(lookup(&JUMP, mnemonic).unwrap_or("000"))
+}
+
+pub fn variable(a_instruction: &str) -> String {
     // pre-defined
-    match a_instruction {
-        "R0" => binary = "0000000000000000",
-        "R1" => binary = "0000000000000001",
-        "R2" => binary = "0000000000000010",
-        "R3" => binary = "0000000000000011",
-        "R4" => binary = "0000000000000100",
-        "R5" => binary = "0000000000000101",
-        "R6" => binary = "0000000000000110",
-        "R7" => binary = "0000000000000111",
-        "R8" => binary = "0000000000001000",
-        "R9" => binary = "0000000000001001",
-        "R10" => binary = "0000000000001010",
-        "R11" => binary = "0000000000001011",
-        "R12" => binary = "0000000000001100",
-        "R13" => binary = "0000000000001101",
-        "R14" => binary = "0000000000001110",
-        "R15" => binary = "0000000000001111",
-        "SP" => binary = "0000000000000000",
-        "LCL" => binary = "0000000000000001",
-        "ARG" => binary = "0000000000000010",
-        "THIS" => binary = "0000000000000011",
-        "THAT" => binary = "0000000000000100",
-        "SCREEN" => binary = "0100000000000000",
-        "KBD" => binary = "0110000000000000",
-        _ => binary = "",
-    }
+    let binary = match a_instruction {
+        "R0" => "0000000000000000",
+        "R1" => "0000000000000001",
+        "R2" => "0000000000000010",
+        "R3" => "0000000000000011",
+        "R4" => "0000000000000100",
+        "R5" => "0000000000000101",
+        "R6" => "0000000000000110",
+        "R7" => "0000000000000111",
+        "R8" => "0000000000001000",
+        "R9" => "0000000000001001",
+        "R10" => "0000000000001010",
+        "R11" => "0000000000001011",
+        "R12" => "0000000000001100",
+        "R13" => "0000000000001101",
+        "R14" => "0000000000001110",
+        "R15" => "0000000000001111",
+        "SP" => "0000000000000000",
+        "LCL" => "0000000000000001",
+        "ARG" => "0000000000000010",
+        "THIS" => "0000000000000011",
+        "THAT" => "0000000000000100",
+        "SCREEN" => "0100000000000000",
+        "KBD" => "0110000000000000",
+        _ => "",
+    };
 
     if binary.is_empty() {
-        let parsed = a_instruction.parse::<i32>();
-        if parsed.is_ok() {
-            return format!("{:016b}", parsed.unwrap());
+        if let Some(parsed) = literal(a_instruction) {
+            return format!("{:016b}", parsed);
         } else {
             // TODO
             return String::from("variable");
@@ -129,6 +257,16 @@ pub fn variable(a_instruction: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn suggestions() {
+        assert_eq!(Some("D+1"), suggest(&COMP, "D+2"));
+        assert_eq!(Some("M-D"), suggest(&COMP, "m-d"));
+        assert_eq!(Some("JMP"), suggest(&JUMP, "JMQ"));
+        assert_eq!(Some("AM"), suggest(&DEST, "AMX"));
+        assert_eq!(None, suggest(&DEST, "X"));
+        assert_eq!(None, suggest(&COMP, "2*A"));
+    }
+
     #[test]
     fn dest_null() {
         assert_eq!("000", dest("null"));
@@ -533,4 +671,35 @@ mod tests {
     fn variable_16() {
         assert_eq!("0000000000010000", variable("16"));
     }
+
+    #[test]
+    fn keys() {
+        assert_eq!(Some(65), key("A"));
+        assert_eq!(Some(48), key("0"));
+        assert_eq!(Some(32), key("space"));
+        assert_eq!(Some(128), key("newline"));
+        assert_eq!(Some(130), key("Left"));
+        assert_eq!(Some(152), key("f12"));
+        assert_eq!(None, key("enter"));
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(Some(42), literal("42"));
+        assert_eq!(Some(0x4000), literal("0x4000"));
+        assert_eq!(Some(0xff), literal("0XFF"));
+        assert_eq!(Some(10), literal("0b1010"));
+        assert_eq!(Some(65), literal("'A'"));
+        assert_eq!(Some(128), literal("'\\n'"));
+        assert_eq!(Some(133), literal("'down'"));
+        assert_eq!(None, literal("0x"));
+        assert_eq!(None, literal("0b102"));
+        assert_eq!(None, literal("+1"));
+        assert_eq!(None, literal("'AB'"));
+    }
+
+    #[test]
+    fn variable_hex() {
+        assert_eq!("0100000000000000", variable("0x4000"));
+    }
 }
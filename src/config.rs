@@ -1,5 +1,48 @@
+use crate::code;
+use crate::expression;
+use crate::screen;
+use crate::symbol_table::Layout;
+
+// how the results are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    // one document for tools, see json_output.rs
+    Json,
+    // diagnostics as file:line:column: error: message
+    Gcc,
+    // diagnostics for code scanning, see sarif.rs
+    Sarif,
+    // the files and the test script as test cases, see junit.rs
+    Junit,
+}
+
 pub struct Config {
     pub filename: String,
+    // the filename first, then the other files of a multi-file program
+    pub files: Vec<String>,
+    // archives to take routines from, see archive.rs
+    pub libraries: Vec<String>,
+    pub optimize: bool,
+    pub source_map: bool,
+    pub listing: bool,
+    pub xml: bool,
+    pub warn_case: bool,
+    pub layout: Layout,
+    pub ram_map: bool,
+    pub rom_size: u32,
+    pub watch: bool,
+    pub test: bool,
+    // write Foo.obj rather than Foo.hack, see object.rs
+    pub compile: bool,
+    // write Foo.map, see linker::map()
+    pub map: bool,
+    // remove the routines nothing reaches, see gc.rs
+    pub gc_sections: bool,
+    // where the program starts, see linker::start()
+    pub entry: Option<String>,
+    pub bootstrap: bool,
+    pub format: Format,
 }
 
 impl Config {
@@ -8,7 +51,543 @@ impl Config {
             return Err("not enough arguments");
         }
         let filename = args[1].clone();
+        let mut files = vec![filename.clone()];
+        let mut libraries = vec![];
+
+        let mut optimize = false;
+        let mut source_map = false;
+        let mut listing = false;
+        let mut xml = false;
+        let mut warn_case = false;
+        let mut layout = Layout::new();
+        let mut ram_map = false;
+        let mut rom_size = code::ROM_SIZE;
+        let mut watch = false;
+        let mut test = false;
+        let mut compile = false;
+        let mut map = false;
+        let mut gc_sections = false;
+        let mut entry = None;
+        let mut bootstrap = false;
+        let mut format = Format::Text;
+
+        let mut options = args[2..].iter();
+        while let Some(arg) = options.next() {
+            match arg.as_str() {
+                "-O" | "--optimize" => optimize = true,
+                "-g" | "--source-map" => source_map = true,
+                "-l" | "--listing" => listing = true,
+                "--xml" => xml = true,
+                "--warn-case" => warn_case = true,
+                "--ram-map" => ram_map = true,
+                "--watch" => watch = true,
+                "--test" => test = true,
+                "-c" | "--compile" => compile = true,
+                "--map" => map = true,
+                "--gc-sections" => gc_sections = true,
+                "--bootstrap" => bootstrap = true,
+                "--format" => {
+                    format = match options.next().map(|v| v.as_str()) {
+                        Some("text") => Format::Text,
+                        Some("json") => Format::Json,
+                        Some("gcc") => Format::Gcc,
+                        Some("sarif") => Format::Sarif,
+                        Some("junit") => Format::Junit,
+                        Some(_) => return Err("unknown output format"),
+                        None => return Err("missing option value"),
+                    }
+                }
+                _ if entry_option(arg, &mut options, &mut entry)? => {}
+                _ if layout_option(arg, &mut options, &mut layout, &mut rom_size)? => {}
+                file if file.ends_with(".asm") => files.push(String::from(file)),
+                file if file.ends_with(".lib") => libraries.push(String::from(file)),
+                _ => return Err("unknown option"),
+            }
+        }
+
+        let linked = files.len() > 1 || !libraries.is_empty();
+        if linked && !filename.ends_with(".asm") {
+            return Err("only .asm files can be assembled together");
+        }
+        if linked && (source_map || listing) {
+            return Err("listings and source maps need a single input file");
+        }
+        if compile && !filename.ends_with(".asm") {
+            return Err("only .asm files can be assembled to objects");
+        }
+        if compile && (source_map || listing) {
+            return Err("objects have no listing or source map");
+        }
+        // options that only the linker knows
+        let link_options = map || gc_sections || entry.is_some() || bootstrap;
+        if compile && link_options {
+            return Err("objects are given link options when they are linked");
+        }
+        if link_options && !filename.ends_with(".asm") {
+            return Err("only .asm files can be linked");
+        }
+        if link_options && (source_map || listing) {
+            return Err("listings and source maps are not written when linking");
+        }
+        if watch && format != Format::Text {
+            return Err("--watch only prints text");
+        }
+        check_layout(&layout)?;
+
+        Ok(Config {
+            filename,
+            files,
+            libraries,
+            optimize,
+            source_map,
+            listing,
+            xml,
+            warn_case,
+            layout,
+            ram_map,
+            rom_size,
+            watch,
+            test,
+            compile,
+            map,
+            gc_sections,
+            entry,
+            bootstrap,
+            format,
+        })
+    }
+
+    // programs of several files, or that need the linker for one
+    pub fn links(&self) -> bool {
+        self.files.len() > 1
+            || !self.libraries.is_empty()
+            || self.map
+            || self.gc_sections
+            || self.entry.is_some()
+            || self.bootstrap
+    }
+}
+
+// --var-base N, --var-limit N, --var-order first-use|alphabetical and
+// --rom-size N, for assembling and linking. Returns false for the other
+// options.
+fn layout_option(
+    arg: &str,
+    options: &mut std::slice::Iter<String>,
+    layout: &mut Layout,
+    rom_size: &mut u32,
+) -> Result<bool, &'static str> {
+    match arg {
+        "--var-base" | "--var-limit" => {
+            let value = options.next().ok_or("missing option value")?;
+            let address = value.parse().map_err(|_| "invalid variable address")?;
+            if arg == "--var-base" {
+                layout.base = address;
+            } else {
+                layout.limit = address;
+            }
+        }
+        "--rom-size" => {
+            let value = options.next().ok_or("missing option value")?;
+            *rom_size = value
+                .parse()
+                .ok()
+                .filter(|size| (1..=code::ROM_SIZE).contains(size))
+                .ok_or("the ROM size must be between 1 and 32768 words")?;
+        }
+        "--var-order" => match options.next().map(|v| v.as_str()) {
+            Some("first-use") => layout.alphabetical = false,
+            Some("alphabetical") => layout.alphabetical = true,
+            Some(_) => return Err("unknown variable order"),
+            None => return Err("missing option value"),
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// --entry LABEL, for assembling and linking
+fn entry_option(
+    arg: &str,
+    options: &mut std::slice::Iter<String>,
+    entry: &mut Option<String>,
+) -> Result<bool, &'static str> {
+    if arg != "--entry" {
+        return Ok(false);
+    }
+    let label = options.next().ok_or("missing option value")?;
+    if !expression::is_symbol(label) {
+        return Err("the entry point must be a label");
+    }
+    *entry = Some(label.clone());
+    Ok(true)
+}
+
+fn check_layout(layout: &Layout) -> Result<(), &'static str> {
+    if layout.limit >= screen::SCREEN as u32 {
+        return Err("variables must end below SCREEN");
+    }
+    if layout.base > layout.limit {
+        return Err("variables must start below their limit");
+    }
+    Ok(())
+}
+
+// assembler link Main.obj Mult.obj -o Prog.hack --ram-map --map --entry MAIN
+pub struct LinkConfig {
+    pub files: Vec<String>,
+    pub libraries: Vec<String>,
+    pub output: String,
+    pub layout: Layout,
+    pub ram_map: bool,
+    pub rom_size: u32,
+    pub map: bool,
+    pub gc_sections: bool,
+    pub entry: Option<String>,
+    pub bootstrap: bool,
+}
+
+impl LinkConfig {
+    pub fn new(args: &[String]) -> Result<LinkConfig, &'static str> {
+        let mut files = vec![];
+        let mut libraries = vec![];
+        let mut output = None;
+        let mut layout = Layout::new();
+        let mut ram_map = false;
+        let mut rom_size = code::ROM_SIZE;
+        let mut map = false;
+        let mut gc_sections = false;
+        let mut entry = None;
+        let mut bootstrap = false;
+
+        let mut options = args[2..].iter();
+        while let Some(arg) = options.next() {
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    output = Some(options.next().ok_or("missing option value")?.clone())
+                }
+                "--ram-map" => ram_map = true,
+                "--map" => map = true,
+                "--gc-sections" => gc_sections = true,
+                "--bootstrap" => bootstrap = true,
+                _ if entry_option(arg, &mut options, &mut entry)? => {}
+                _ if layout_option(arg, &mut options, &mut layout, &mut rom_size)? => {}
+                file if file.ends_with(".obj") => files.push(String::from(file)),
+                file if file.ends_with(".lib") => libraries.push(String::from(file)),
+                _ => return Err("unknown option"),
+            }
+        }
+        check_layout(&layout)?;
+
+        // Main.obj -> Main.hack
+        let output = match (output, files.first()) {
+            (Some(output), _) => output,
+            (None, Some(first)) => first.trim_end_matches(".obj").to_string() + ".hack",
+            (None, None) => return Err("missing objects to link"),
+        };
+
+        Ok(LinkConfig {
+            files,
+            libraries,
+            output,
+            layout,
+            ram_map,
+            rom_size,
+            map,
+            gc_sections,
+            entry,
+            bootstrap,
+        })
+    }
+}
+
+// assembler archive Std.lib Math.obj Screen.obj
+pub struct ArchiveConfig {
+    pub output: String,
+    pub files: Vec<String>,
+}
+
+impl ArchiveConfig {
+    pub fn new(args: &[String]) -> Result<ArchiveConfig, &'static str> {
+        let output = match args.get(2) {
+            Some(output) if output.ends_with(".lib") => output.clone(),
+            Some(_) => return Err("archives are named Foo.lib"),
+            None => return Err("missing archive to write"),
+        };
+        let files: Vec<String> = args[3..].to_vec();
+        if files.is_empty() {
+            return Err("missing objects to archive");
+        }
+        if files.iter().any(|file| !file.ends_with(".obj")) {
+            return Err("only .obj files can be archived");
+        }
+
+        Ok(ArchiveConfig { output, files })
+    }
+}
+
+// assembler run Prog.asm --cycles 100000 --keys keys.txt --snapshot 5000:a.png --screen end.pbm
+pub struct RunConfig {
+    pub filename: String,
+    pub cycles: u64,
+    pub keys: Option<String>,
+    pub snapshots: Vec<(u64, String)>,
+    pub screen: Option<String>,
+}
+
+impl RunConfig {
+    pub fn new(args: &[String]) -> Result<RunConfig, &'static str> {
+        if args.len() < 3 {
+            return Err("missing file to run");
+        }
+        let filename = args[2].clone();
+
+        let mut cycles = 10_000_000;
+        let mut keys = None;
+        let mut snapshots = vec![];
+        let mut screen = None;
+
+        let mut options = args[3..].iter();
+        while let Some(option) = options.next() {
+            let value = options.next().ok_or("missing option value")?;
+            match option.as_str() {
+                "--cycles" => cycles = value.parse().map_err(|_| "invalid cycle count")?,
+                "--keys" => keys = Some(value.clone()),
+                "--screen" => screen = Some(value.clone()),
+                "--snapshot" => {
+                    // 5000:frame.png
+                    let (cycle, file) = value.split_once(':').ok_or("invalid snapshot")?;
+                    let cycle = cycle.parse().map_err(|_| "invalid snapshot cycle")?;
+                    snapshots.push((cycle, String::from(file)));
+                }
+                _ => return Err("unknown option"),
+            }
+        }
+
+        Ok(RunConfig {
+            filename,
+            cycles,
+            keys,
+            snapshots,
+            screen,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn flags() {
+        let config = Config::new(&args("assembler Max.asm -O -g")).unwrap();
+        assert_eq!("Max.asm", config.filename);
+        assert!(config.optimize);
+        assert!(config.source_map);
+        assert!(!config.listing);
+        assert!(!config.xml);
+        assert!(!config.warn_case);
+        assert!(
+            Config::new(&args("assembler Max.asm --warn-case"))
+                .unwrap()
+                .warn_case
+        );
+        let config = Config::new(&args("assembler Max.asm --watch --test")).unwrap();
+        assert!(config.watch && config.test);
+        assert_eq!(Format::Text, config.format);
+        assert_eq!(
+            Format::Json,
+            Config::new(&args("assembler Max.asm --format json"))
+                .unwrap()
+                .format
+        );
+        assert_eq!(
+            Format::Junit,
+            Config::new(&args("assembler Max.asm --test --format junit"))
+                .unwrap()
+                .format
+        );
+        assert_eq!(
+            Some("unknown output format"),
+            Config::new(&args("assembler Max.asm --format xml")).err()
+        );
+        assert_eq!(
+            Some("--watch only prints text"),
+            Config::new(&args("assembler Max.asm --watch --format json")).err()
+        );
+        assert!(Config::new(&args("assembler Max.asm -x")).is_err());
+
+        assert!(Config::new(&args("assembler Max.asm -c")).unwrap().compile);
+        assert_eq!(
+            Some("only .asm files can be assembled to objects"),
+            Config::new(&args("assembler Prog -c")).err()
+        );
+
+        let config = Config::new(&args("assembler Main.asm Math.asm -O Screen.asm")).unwrap();
+        assert_eq!(vec!["Main.asm", "Math.asm", "Screen.asm"], config.files);
+        assert_eq!(
+            Some("only .asm files can be assembled together"),
+            Config::new(&args("assembler Prog Math.asm")).err()
+        );
+        assert_eq!(
+            Some("listings and source maps need a single input file"),
+            Config::new(&args("assembler Main.asm Math.asm -l")).err()
+        );
+
+        let config = Config::new(&args("assembler Main.asm --map --gc-sections")).unwrap();
+        assert!(config.map && config.gc_sections && config.links());
+        assert!(!Config::new(&args("assembler Main.asm")).unwrap().links());
+        assert_eq!(
+            Some("only .asm files can be linked"),
+            Config::new(&args("assembler Prog --map")).err()
+        );
+        assert_eq!(
+            Some("objects are given link options when they are linked"),
+            Config::new(&args("assembler Main.asm -c --map")).err()
+        );
+
+        let config = Config::new(&args("assembler Main.asm --entry MAIN --bootstrap")).unwrap();
+        assert_eq!(Some(String::from("MAIN")), config.entry);
+        assert!(config.bootstrap && config.links());
+        assert_eq!(
+            Some("the entry point must be a label"),
+            Config::new(&args("assembler Main.asm --entry 12")).err()
+        );
+    }
+
+    #[test]
+    fn variable_layout() {
+        let config = Config::new(&args(
+            "assembler Max.asm --var-base 64 --var-limit 1023 --var-order alphabetical --ram-map",
+        ))
+        .unwrap();
+        assert_eq!(
+            Layout {
+                base: 64,
+                limit: 1023,
+                alphabetical: true
+            },
+            config.layout
+        );
+        assert!(config.ram_map);
+        assert_eq!(32768, config.rom_size);
+        assert_eq!(
+            1024,
+            Config::new(&args("assembler Max.asm --rom-size 1024"))
+                .unwrap()
+                .rom_size
+        );
+        assert!(Config::new(&args("assembler Max.asm --rom-size 40000")).is_err());
+
+        let error = |line| Config::new(&args(line)).err();
+        assert_eq!(
+            Some("variables must end below SCREEN"),
+            error("assembler Max.asm --var-limit 16384")
+        );
+        assert_eq!(
+            Some("variables must start below their limit"),
+            error("assembler Max.asm --var-base 200 --var-limit 100")
+        );
+        assert_eq!(
+            Some("unknown variable order"),
+            error("assembler Max.asm --var-order random")
+        );
+        assert_eq!(
+            Some("missing option value"),
+            error("assembler Max.asm --var-base")
+        );
+    }
+
+    #[test]
+    fn link_options() {
+        let config = LinkConfig::new(&args(
+            "assembler link Main.obj Mult.obj --ram-map --var-base 100 --rom-size 1024",
+        ))
+        .unwrap();
+        assert_eq!(vec!["Main.obj", "Mult.obj"], config.files);
+        assert_eq!("Main.hack", config.output);
+        assert!(config.ram_map);
+        assert_eq!(100, config.layout.base);
+        assert_eq!(1024, config.rom_size);
+        assert_eq!(
+            "Prog.hack",
+            LinkConfig::new(&args("assembler link Main.obj -o Prog.hack"))
+                .unwrap()
+                .output
+        );
+        let config =
+            LinkConfig::new(&args("assembler link Main.obj Std.lib --map --gc-sections")).unwrap();
+        assert_eq!(vec!["Std.lib"], config.libraries);
+        assert!(config.map && config.gc_sections);
+        let config = LinkConfig::new(&args("assembler link Main.obj --entry Sys.init")).unwrap();
+        assert_eq!(Some(String::from("Sys.init")), config.entry);
+        assert!(!config.bootstrap);
+        assert_eq!(
+            Some("missing objects to link"),
+            LinkConfig::new(&args("assembler link")).err()
+        );
+        assert_eq!(
+            Some("unknown option"),
+            LinkConfig::new(&args("assembler link Main.asm")).err()
+        );
+    }
+
+    #[test]
+    fn archive_options() {
+        let config =
+            ArchiveConfig::new(&args("assembler archive Std.lib Math.obj Screen.obj")).unwrap();
+        assert_eq!("Std.lib", config.output);
+        assert_eq!(vec!["Math.obj", "Screen.obj"], config.files);
+
+        let error = |line| ArchiveConfig::new(&args(line)).err();
+        assert_eq!(Some("missing archive to write"), error("assembler archive"));
+        assert_eq!(
+            Some("archives are named Foo.lib"),
+            error("assembler archive Std.obj Math.obj")
+        );
+        assert_eq!(
+            Some("missing objects to archive"),
+            error("assembler archive Std.lib")
+        );
+        assert_eq!(
+            Some("only .obj files can be archived"),
+            error("assembler archive Std.lib Math.asm")
+        );
+    }
+
+    #[test]
+    fn run_options() {
+        let config = RunConfig::new(&args(
+            "assembler run Fill.asm --cycles 500 --keys k.txt --snapshot 100:a.png --snapshot 200:b.pbm --screen end.png",
+        ))
+        .unwrap();
+        assert_eq!("Fill.asm", config.filename);
+        assert_eq!(500, config.cycles);
+        assert_eq!(Some(String::from("k.txt")), config.keys);
+        assert_eq!(
+            vec![(100, String::from("a.png")), (200, String::from("b.pbm"))],
+            config.snapshots
+        );
+        assert_eq!(Some(String::from("end.png")), config.screen);
+    }
 
-        Ok(Config { filename })
+    #[test]
+    fn run_errors() {
+        assert_eq!(
+            Some("missing file to run"),
+            RunConfig::new(&args("assembler run")).err()
+        );
+        assert_eq!(
+            Some("missing option value"),
+            RunConfig::new(&args("assembler run Fill.asm --cycles")).err()
+        );
+        assert_eq!(
+            Some("invalid snapshot"),
+            RunConfig::new(&args("assembler run Fill.asm --snapshot a.png")).err()
+        );
     }
 }
@@ -0,0 +1,332 @@
+use std::fmt;
+
+use crate::assembler;
+use crate::code;
+use crate::diagnostic::Diagnostic;
+use crate::expression;
+use crate::parser::{Instruction, Location};
+use crate::screen;
+use crate::symbol_table::SymbolTable;
+
+// Directives filling RAM before the program starts, written to Foo.ram:
+//
+//     .data 1024              next words go to RAM[1024]
+//     .word 1, -1, LOOP       numbers, labels and predefined symbols
+//     .word "Hi", 0           strings are a word per character
+//
+// `.var counter 100` gives a variable a fixed address instead, see
+// assembler::allocate(). `.global counter` shares a variable with the
+// other files of a linked program, see linker.rs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    Data(String),
+    Word(Vec<String>),
+    Var(String, String),
+    Global(String),
+    // the label the program starts at, see linker::start()
+    Entry(String),
+}
+
+// as written in the source, directive() reads it back
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Directive::Data(address) => write!(f, ".data {}", address),
+            Directive::Word(words) => write!(f, ".word {}", words.join(", ")),
+            Directive::Var(name, address) => write!(f, ".var {} {}", name, address),
+            Directive::Global(name) => write!(f, ".global {}", name),
+            Directive::Entry(label) => write!(f, ".entry {}", label),
+        }
+    }
+}
+
+// The parser reads directives as C-instructions, the text is put back
+// together as strings may contain `=` or `;`.
+pub fn directive(instruction: &Instruction) -> Option<Result<Directive, String>> {
+    // directives start with `.`, which spares the other instructions a copy
+    let text = match instruction {
+        Instruction::C { dest, comp, .. } if dest.starts_with('.') || comp.starts_with('.') => {
+            instruction.to_string()
+        }
+        _ => return None,
+    };
+    let (name, operands) = match text.split_once(char::is_whitespace) {
+        Some((name, operands)) => (name, operands.trim()),
+        None => (text.as_str(), ""),
+    };
+
+    match name {
+        ".data" if operands.is_empty() || operands.contains(',') => {
+            Some(Err(String::from("`.data` expects an address")))
+        }
+        ".data" => Some(Ok(Directive::Data(String::from(operands)))),
+        ".word" => Some(words(operands).map(Directive::Word)),
+        ".var" => match operands
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .as_slice()
+        {
+            [name, address] if expression::is_symbol(name) => Some(Ok(Directive::Var(
+                String::from(*name),
+                String::from(*address),
+            ))),
+            _ => Some(Err(String::from("`.var` expects a name and an address"))),
+        },
+        ".global" if expression::is_symbol(operands) => {
+            Some(Ok(Directive::Global(String::from(operands))))
+        }
+        ".global" => Some(Err(String::from("`.global` expects a name"))),
+        ".entry" if expression::is_symbol(operands) => {
+            Some(Ok(Directive::Entry(String::from(operands))))
+        }
+        ".entry" => Some(Err(String::from("`.entry` expects a label"))),
+        _ => None,
+    }
+}
+
+// 1, "a,b", 2 -> [1, "a,b", 2]
+fn words(operands: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in operands.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.push(c);
+            }
+            ',' if !quoted => words.push(std::mem::take(&mut word)),
+            _ => word.push(c),
+        }
+    }
+    words.push(word);
+
+    if quoted {
+        return Err(String::from("unterminated string"));
+    }
+    let words: Vec<String> = words.iter().map(|word| String::from(word.trim())).collect();
+    if words.iter().any(|word| word.is_empty()) {
+        return Err(String::from("`.word` expects values separated by commas"));
+    }
+    Ok(words)
+}
+
+// A number, or an expression of labels and predefined symbols.
+fn value(word: &str, symbol_table: &SymbolTable) -> Result<u16, String> {
+    match assembler::value(word, symbol_table) {
+        // negative numbers are stored in two's complement
+        Ok(value) if (-32768..=65535).contains(&value) => Ok(value as u16),
+        Ok(_) => Err(format!("`{}` does not fit in 16 bits", word)),
+        Err(error) => Err(error.message),
+    }
+}
+
+// Lays out the directives in RAM, as (address, word) pairs in address
+// order.
+pub fn image(
+    directives: &[(Directive, Location)],
+    symbol_table: &SymbolTable,
+) -> Result<Vec<(u16, u16)>, Vec<Diagnostic>> {
+    let mut image: Vec<(u16, u16)> = vec![];
+    let mut diagnostics = vec![];
+    let mut address = None;
+
+    for (directive, location) in directives {
+        let mut error = |message: String| {
+            diagnostics.push(Diagnostic::error(*location, 5, message));
+        };
+
+        match directive {
+            Directive::Data(operand) => match value(operand, symbol_table) {
+                Ok(value) if value as usize <= screen::KBD => address = Some(value),
+                Ok(value) => error(format!("RAM[{}] is outside of the data memory", value)),
+                Err(message) => error(message),
+            },
+            Directive::Word(words) => {
+                let mut next = match address {
+                    Some(next) => next,
+                    None => {
+                        error(String::from("`.word` needs a `.data` address first"));
+                        continue;
+                    }
+                };
+
+                for word in words {
+                    let values = match word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+                        Some(text) => text
+                            .chars()
+                            .map(|c| {
+                                code::key(&c.to_string())
+                                    .ok_or(format!("`{}` is not a Hack character", c))
+                            })
+                            .collect(),
+                        None => value(word, symbol_table).map(|value| vec![value]),
+                    };
+
+                    match values {
+                        Ok(values) => {
+                            for value in values {
+                                if next as usize > screen::KBD {
+                                    error(format!("RAM[{}] is outside of the data memory", next));
+                                    break;
+                                }
+                                if image.iter().any(|(taken, _)| *taken == next) {
+                                    error(format!("RAM[{}] is initialised twice", next));
+                                }
+                                image.push((next, value));
+                                next += 1;
+                            }
+                        }
+                        Err(message) => error(message),
+                    }
+                }
+                address = Some(next);
+            }
+            Directive::Var(..) | Directive::Global(_) | Directive::Entry(_) => {}
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    image.sort();
+    Ok(image)
+}
+
+// 1024 0000000000000001
+pub fn to_ram(image: &[(u16, u16)]) -> String {
+    image
+        .iter()
+        .map(|(address, word)| format!("{} {:016b}\n", address, word))
+        .collect()
+}
+
+pub fn load_ram(contents: &str) -> Result<Vec<(u16, u16)>, String> {
+    let mut image = vec![];
+
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = line.split_once(' ').and_then(|(address, word)| {
+            let address = address.parse::<u16>().ok()?;
+            let word = u16::from_str_radix(word.trim(), 2).ok()?;
+            Some((address, word))
+        });
+        match entry {
+            Some(entry) => image.push(entry),
+            None => return Err(format!("line {}: expected an address and a word", i + 1)),
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn layout(contents: &str) -> Result<Vec<(u16, u16)>, Vec<String>> {
+        let (statements, directives) = assembler::parse(contents);
+        let symbol_table = assembler::resolve(&statements);
+        image(&directives, &symbol_table)
+            .map_err(|diagnostics| diagnostics.iter().map(|d| d.to_string()).collect())
+    }
+
+    #[test]
+    fn parse_directives() {
+        let parsed = |line: &str| directive(&crate::parser::Parser::new(line).instruction());
+        assert_eq!(
+            Some(Ok(Directive::Data(String::from("SCREEN")))),
+            parsed(".data SCREEN")
+        );
+        assert_eq!(
+            Some(Ok(Directive::Word(vec![
+                String::from("1"),
+                String::from("\"a=b, c;\""),
+                String::from("-1"),
+            ]))),
+            parsed(".word 1, \"a=b, c;\" ,-1")
+        );
+        assert_eq!(
+            Some(Err(String::from(
+                "`.word` expects values separated by commas"
+            ))),
+            parsed(".word 1,,2")
+        );
+        assert_eq!(
+            Some(Err(String::from("unterminated string"))),
+            parsed(".word \"a")
+        );
+        assert_eq!(
+            Some(Ok(Directive::Var(
+                String::from("counter"),
+                String::from("0x64")
+            ))),
+            parsed(".var counter 0x64")
+        );
+        assert_eq!(
+            Some(Err(String::from("`.var` expects a name and an address"))),
+            parsed(".var 1x 100")
+        );
+        assert_eq!(
+            Some(Ok(Directive::Global(String::from("counter")))),
+            parsed(".global counter")
+        );
+        assert_eq!(
+            Some(Err(String::from("`.global` expects a name"))),
+            parsed(".global a b")
+        );
+        assert_eq!(
+            Some(Ok(Directive::Entry(String::from("Main.main")))),
+            parsed(".entry Main.main")
+        );
+        assert_eq!(
+            Some(Err(String::from("`.entry` expects a label"))),
+            parsed(".entry")
+        );
+        assert_eq!(None, parsed("D=M"));
+    }
+
+    #[test]
+    fn words_and_strings() {
+        assert_eq!(
+            Ok(vec![
+                (16, 2),
+                (17, 0xffff),
+                (18, 72),
+                (19, 105),
+                (20, 0),
+                (100, 16384),
+            ]),
+            layout(".data 100\n.word SCREEN\n.data 0x10\n.word LOOP, -1\n.word \"Hi\", 0\n@1\n@2\n(LOOP)\n")
+        );
+    }
+
+    #[test]
+    fn image_errors() {
+        assert_eq!(
+            Err(vec![
+                String::from("error: `.word` needs a `.data` address first (line 1, column 1)"),
+                String::from("error: unknown symbol `nope` (line 3, column 1)"),
+                String::from("error: RAM[17] is initialised twice (line 5, column 1)"),
+                String::from("error: RAM[30000] is outside of the data memory (line 6, column 1)"),
+            ]),
+            layout(".word 1\n.data 16\n.word 1, nope, 2\n.data 17\n.word 3\n.data 30000")
+        );
+    }
+
+    #[test]
+    fn ram_files() {
+        let image = vec![(16, 7), (1024, 0xffff)];
+        let contents = to_ram(&image);
+        assert_eq!("16 0000000000000111\n1024 1111111111111111\n", contents);
+        assert_eq!(Ok(image), load_ram(&contents));
+        assert_eq!(
+            Err(String::from("line 1: expected an address and a word")),
+            load_ram("16")
+        );
+    }
+}
@@ -0,0 +1,751 @@
+use std::io::{self, BufRead, Write};
+
+use crate::simulator::{self, Computer, Program, RAM_SIZE};
+
+// keeps `continue` and `next` from hanging in a loop that never stops
+const CYCLE_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+step [N]          execute N instructions (s)
+next              execute until the following instruction, stepping over jumps (n)
+continue          run until a breakpoint, a watchpoint or the end (c)
+break WHERE       stop at a label, a source line or ROM[n] (b)
+watch WHAT        stop when a variable or RAM[n] changes (w)
+delete [N]        remove breakpoint or watchpoint N, or all of them (d)
+info breakpoints  list breakpoints and watchpoints (i b)
+info registers    show A, D, M and PC (i r)
+print WHAT        show D, A, M, PC, RAM[n], ROM[n] or a symbol (p)
+x/N WHERE         examine N words from RAM[n], ROM[n] or a symbol
+set WHAT VALUE    change D, A, M, PC, RAM[n] or a variable, PC to a label or line N
+list              show the source around PC (l)
+reset             restart the program with cleared memory
+quit              leave the debugger (q)";
+
+enum Until {
+    Steps(u64),
+    Address(u16),
+    Stopped,
+}
+
+pub struct Debugger {
+    program: Program,
+    computer: Computer,
+    breakpoints: Vec<(u32, u16)>,
+    watchpoints: Vec<(u32, u16)>,
+    next_number: u32,
+    quit: bool,
+}
+
+// Runs the command loop until `quit` or the end of the input.
+pub fn run(program: Program, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut debugger = Debugger::new(program);
+    let mut last_command = String::new();
+
+    writeln!(
+        output,
+        "{} instructions, {} labels, {} variables, type `help` for commands",
+        debugger.program.rom.len(),
+        debugger.program.symbol_table.labels.len(),
+        debugger.program.symbol_table.variables.len()
+    )?;
+    writeln!(output, "{}", debugger.position())?;
+
+    while !debugger.quit {
+        write!(output, "(hdb) ")?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        // an empty line repeats the previous command
+        let command = match line.trim() {
+            "" => last_command.clone(),
+            command => String::from(command),
+        };
+        if command.is_empty() {
+            continue;
+        }
+
+        match debugger.execute(&command) {
+            Ok(text) => {
+                if !text.is_empty() {
+                    writeln!(output, "{}", text)?;
+                }
+            }
+            Err(e) => writeln!(output, "error: {}", e)?,
+        }
+        last_command = command;
+    }
+
+    Ok(())
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Debugger {
+        let mut computer = Computer::new(program.rom.clone());
+        computer.preload(&program.ram);
+
+        Debugger {
+            program,
+            computer,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            next_number: 1,
+            quit: false,
+        }
+    }
+
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+
+        // x/10 RAM[256]
+        let (command, count) = match command.split_once('/') {
+            Some((command, count)) => (command, Some(count)),
+            None => (command, None),
+        };
+
+        match command {
+            "help" | "h" => Ok(String::from(HELP)),
+            "step" | "s" => {
+                let steps = match args.first() {
+                    Some(steps) => parse_number(steps)?,
+                    None => 1,
+                };
+                self.resume(Until::Steps(steps as u64))
+            }
+            "next" | "n" => {
+                let following = self.computer.pc.wrapping_add(1);
+                self.resume(Until::Address(following))
+            }
+            "continue" | "c" => self.resume(Until::Stopped),
+            "break" | "b" => self.add_breakpoint(&args.join(" ")),
+            "watch" | "w" => self.add_watchpoint(&args.join(" ")),
+            "delete" | "d" => self.delete(args.first().copied()),
+            "info" | "i" => match args.first().copied() {
+                Some("registers") | Some("r") => Ok(self.registers()),
+                Some("breakpoints") | Some("b") | Some("watchpoints") => Ok(self.breakpoints()),
+                _ => Err(String::from("usage: info registers|breakpoints")),
+            },
+            "registers" | "regs" => Ok(self.registers()),
+            "print" | "p" => self.print(&args.join(" ")),
+            "x" => {
+                let count = match count {
+                    Some(count) => parse_number(count)?,
+                    None => 1,
+                };
+                self.examine(count, &args.join(" "))
+            }
+            "set" => match args.as_slice() {
+                [target, value @ ..] if !value.is_empty() => self.set(target, &value.join(" ")),
+                _ => Err(String::from("usage: set WHAT VALUE")),
+            },
+            "list" | "l" => Ok(self.list()),
+            "reset" => {
+                self.computer.reset();
+                self.computer.preload(&self.program.ram);
+                Ok(format!("Program reset\n{}", self.position()))
+            }
+            "quit" | "q" => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command `{}`, try `help`", command)),
+        }
+    }
+
+    fn resume(&mut self, until: Until) -> Result<String, String> {
+        if self.computer.halted() {
+            return Err(String::from(
+                "the program has halted, use `reset` to start again",
+            ));
+        }
+
+        let mut report = String::new();
+        let mut steps: u64 = 0;
+
+        loop {
+            let before = self.computer.m();
+            let written = self.computer.step();
+            steps += 1;
+
+            if let Some(address) = written {
+                let after = self.computer.ram[address as usize];
+                if let Some((number, _)) = self
+                    .watchpoints
+                    .iter()
+                    .find(|(_, w)| *w == address && before != after)
+                {
+                    report = format!(
+                        "Watchpoint {}: {}\nOld value = {}\nNew value = {}\n",
+                        number,
+                        self.ram_description(address),
+                        before as i16,
+                        after as i16
+                    );
+                    break;
+                }
+            }
+
+            if self.computer.halted() {
+                report = String::from("Program halted\n");
+                break;
+            }
+
+            let pc = self.computer.pc;
+            if let Some((number, _)) = self.breakpoints.iter().find(|(_, b)| *b == pc) {
+                report = format!("Breakpoint {}\n", number);
+                break;
+            }
+
+            let done = match until {
+                Until::Steps(count) => steps >= count,
+                Until::Address(address) => pc == address,
+                Until::Stopped => false,
+            };
+            if done {
+                break;
+            }
+
+            if steps >= CYCLE_LIMIT {
+                report = format!("Stopped after {} cycles\n", steps);
+                break;
+            }
+        }
+
+        Ok(format!("{}{}", report, self.position()))
+    }
+
+    fn position(&self) -> String {
+        let pc = self.computer.pc;
+        let mut result = format!("ROM[{}]", pc);
+
+        if let Some(name) = self.program.rom_name(pc) {
+            result.push_str(&format!(" ({})", name));
+        }
+
+        match self.program.source_line(pc) {
+            Some((line, text)) => result.push_str(&format!(" line {}: {}", line, text)),
+            None => match self.program.rom.get(pc as usize) {
+                Some(_) => result.push_str(&format!(": {}", self.word(pc))),
+                None => result.push_str(": end of program"),
+            },
+        }
+
+        result
+    }
+
+    fn add_breakpoint(&mut self, target: &str) -> Result<String, String> {
+        let address = self.rom_address(target)?;
+        let number = self.next_number;
+        self.next_number += 1;
+        self.breakpoints.push((number, address));
+
+        let mut result = format!("Breakpoint {} at {}", number, self.rom_description(address));
+        if let Some((line, _)) = self.program.source_line(address) {
+            result.push_str(&format!(" line {}", line));
+        }
+        Ok(result)
+    }
+
+    fn add_watchpoint(&mut self, target: &str) -> Result<String, String> {
+        let address = self.ram_address(target)?;
+        let number = self.next_number;
+        self.next_number += 1;
+        self.watchpoints.push((number, address));
+
+        Ok(format!(
+            "Watchpoint {}: {}",
+            number,
+            self.ram_description(address)
+        ))
+    }
+
+    fn delete(&mut self, number: Option<&str>) -> Result<String, String> {
+        match number {
+            None => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                Ok(String::from("Deleted all breakpoints and watchpoints"))
+            }
+            Some(number) => {
+                let number = parse_number(number)?;
+                let count = self.breakpoints.len() + self.watchpoints.len();
+                self.breakpoints.retain(|(n, _)| *n != number);
+                self.watchpoints.retain(|(n, _)| *n != number);
+
+                if self.breakpoints.len() + self.watchpoints.len() == count {
+                    return Err(format!("no breakpoint or watchpoint {}", number));
+                }
+                Ok(format!("Deleted {}", number))
+            }
+        }
+    }
+
+    fn breakpoints(&self) -> String {
+        let mut lines = vec![];
+
+        for (number, address) in &self.breakpoints {
+            lines.push(format!(
+                "{}: break at {}",
+                number,
+                self.rom_description(*address)
+            ));
+        }
+        for (number, address) in &self.watchpoints {
+            lines.push(format!(
+                "{}: watch {}",
+                number,
+                self.ram_description(*address)
+            ));
+        }
+
+        if lines.is_empty() {
+            return String::from("No breakpoints or watchpoints");
+        }
+        lines.join("\n")
+    }
+
+    fn registers(&self) -> String {
+        let a = self.computer.a;
+        let mut a_names = vec![];
+        if let Some(name) = self.program.ram_name(a) {
+            a_names.push(name);
+        }
+        if let Some(name) = self.program.rom_name(a).filter(|n| !n.contains('+')) {
+            a_names.push(name);
+        }
+        let a_names = match a_names.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", a_names.join(", ")),
+        };
+
+        format!(
+            "A  = {}{}\nD  = {}\nM  = {} = {}\nPC = {}",
+            a as i16,
+            a_names,
+            self.computer.d as i16,
+            self.ram_description(a),
+            self.computer.m() as i16,
+            self.rom_description(self.computer.pc)
+        )
+    }
+
+    fn print(&self, what: &str) -> Result<String, String> {
+        match what {
+            "" => Err(String::from("usage: print WHAT")),
+            "D" => Ok(format!("D = {}", self.computer.d as i16)),
+            "A" => Ok(format!("A = {}", self.computer.a as i16)),
+            "PC" => Ok(format!("PC = {}", self.rom_description(self.computer.pc))),
+            _ if what.starts_with("ROM[") || self.program.is_label(what) => {
+                let address = self.rom_address(what)?;
+                Ok(format!(
+                    "{} = {}",
+                    self.rom_description(address),
+                    self.word(address)
+                ))
+            }
+            _ => {
+                let address = self.ram_address(what)?;
+                Ok(format!(
+                    "{} = {}",
+                    self.ram_description(address),
+                    self.computer.ram[address as usize] as i16
+                ))
+            }
+        }
+    }
+
+    fn examine(&self, count: u32, what: &str) -> Result<String, String> {
+        let mut lines = vec![];
+
+        if what.starts_with("ROM[") || self.program.is_label(what) {
+            let start = self.rom_address(what)?;
+            for address in (start as u32..start as u32 + count).take_while(|a| *a < 32768) {
+                let address = address as u16;
+                lines.push(format!(
+                    "{}: {}",
+                    self.rom_description(address),
+                    self.word(address)
+                ));
+            }
+        } else {
+            let start = self.ram_address(what)?;
+            for address in
+                (start as usize..start as usize + count as usize).take_while(|a| *a < RAM_SIZE)
+            {
+                lines.push(format!(
+                    "{}: {}",
+                    self.ram_description(address as u16),
+                    self.computer.ram[address] as i16
+                ));
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn set(&mut self, target: &str, value: &str) -> Result<String, String> {
+        match target {
+            "D" => self.computer.d = parse_value(value)?,
+            "A" => self.computer.a = parse_value(value)?,
+            // a number is an address, as for the other registers, source
+            // lines are asked for with `line N`
+            "PC" => {
+                self.computer.pc = match value.strip_prefix("line ") {
+                    Some(line) => self.rom_address(&parse_number(line.trim())?.to_string())?,
+                    None => match value.parse::<u16>() {
+                        Ok(address) => address,
+                        Err(_) => self.rom_address(value)?,
+                    },
+                }
+            }
+            _ => {
+                let address = self.ram_address(target)?;
+                self.computer.ram[address as usize] = parse_value(value)?;
+            }
+        }
+
+        self.print(target)
+    }
+
+    fn list(&self) -> String {
+        let pc = self.computer.pc;
+
+        match self.program.source_line(pc) {
+            Some((line, _)) => {
+                let first = line.saturating_sub(5) as usize;
+                let last = (line as usize + 4).min(self.program.source.len());
+
+                (first..last)
+                    .map(|index| {
+                        let marker = if index + 1 == line as usize {
+                            "=>"
+                        } else {
+                            "  "
+                        };
+                        format!("{} {:4} {}", marker, index + 1, self.program.source[index])
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            None => {
+                let first = pc.saturating_sub(4);
+                let last = (pc as usize + 5).min(self.program.rom.len()) as u16;
+
+                (first..last)
+                    .map(|address| {
+                        let marker = if address == pc { "=>" } else { "  " };
+                        format!(
+                            "{} {}: {}",
+                            marker,
+                            self.rom_description(address),
+                            self.word(address)
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+        }
+    }
+
+    // LOOP, ROM[12] or a source line number
+    fn rom_address(&self, target: &str) -> Result<u16, String> {
+        if let Some(address) = indexed(target, "ROM") {
+            return address;
+        }
+        if let Some(address) = self.program.symbol_table.get_address(target) {
+            if self.program.is_label(target) {
+                return Ok(address as u16);
+            }
+        }
+
+        if let Ok(line) = target.parse::<u32>() {
+            if self.program.locations.is_empty() {
+                return Err(String::from("no line information, use ROM[n] or a label"));
+            }
+            return self
+                .program
+                .locations
+                .iter()
+                .position(|l| l.line >= line)
+                .map(|address| address as u16)
+                .ok_or(format!("no code at or after line {}", line));
+        }
+
+        Err(format!("`{}` is not a label, a line or ROM[n]", target))
+    }
+
+    // i, SP, M or RAM[256]
+    fn ram_address(&self, target: &str) -> Result<u16, String> {
+        if target == "M" {
+            return Ok(self.computer.a % RAM_SIZE as u16);
+        }
+        if let Some(address) = indexed(target, "RAM") {
+            return address;
+        }
+        if let Some(address) = self.program.symbol_table.get_address(target) {
+            if !self.program.is_label(target) {
+                return Ok(address as u16);
+            }
+        }
+
+        Err(format!("`{}` is not a variable or RAM[n]", target))
+    }
+
+    fn rom_description(&self, address: u16) -> String {
+        match self.program.rom_name(address) {
+            Some(name) => format!("ROM[{}] ({})", address, name),
+            None => format!("ROM[{}]", address),
+        }
+    }
+
+    fn ram_description(&self, address: u16) -> String {
+        match self.program.ram_name(address) {
+            Some(name) => format!("RAM[{}] ({})", address, name),
+            None => format!("RAM[{}]", address),
+        }
+    }
+
+    // @i rather than @16, the instruction after an A-instruction tells
+    // whether it loads a label to jump to or a RAM address
+    fn word(&self, address: u16) -> String {
+        let word = match self.program.rom.get(address as usize) {
+            Some(word) => *word,
+            None => return String::from("-"),
+        };
+        let next = self.program.rom.get(address as usize + 1).copied();
+        let name = match next {
+            _ if word & 0x8000 != 0 => None,
+            Some(next) if next & 0x8000 != 0 && next & 0x0007 != 0 => self
+                .program
+                .rom_name(word)
+                .filter(|name| self.program.is_label(name)),
+            // comp reads M or dest writes it
+            Some(next) if next & 0x8000 != 0 && next & 0x1008 != 0 => self.program.ram_name(word),
+            _ => None,
+        };
+        match name {
+            Some(name) => format!("@{}", name),
+            None => simulator::disassemble(word),
+        }
+    }
+}
+
+// RAM[256] -> 256
+fn indexed(target: &str, memory: &str) -> Option<Result<u16, String>> {
+    let index = target
+        .strip_prefix(memory)?
+        .strip_prefix('[')?
+        .strip_suffix(']')?;
+
+    Some(match index.trim().parse::<u16>() {
+        Ok(address) if (address as usize) < RAM_SIZE => Ok(address),
+        _ => Err(format!("invalid address in `{}`", target)),
+    })
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    text.parse::<u32>()
+        .map_err(|_| format!("`{}` is not a number", text))
+}
+
+// values are shown signed, but any 16 bit pattern can be written
+fn parse_value(text: &str) -> Result<u16, String> {
+    match text.parse::<i32>() {
+        Ok(value) if (-32768..=65535).contains(&value) => Ok(value as u16),
+        _ => Err(format!("`{}` is not a 16 bit value", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SUM: &str = "// sum = 1 + 2 + ... + n
+@i
+M=1
+@sum
+M=0
+(LOOP)
+@i
+D=M
+@R0
+D=D-M
+@END
+D;JGT
+@i
+D=M
+@sum
+M=D+M
+@i
+M=M+1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP
+";
+
+    fn debugger() -> Debugger {
+        let mut debugger = Debugger::new(Program::from_asm(SUM).unwrap());
+        debugger.computer.ram[0] = 3;
+        debugger
+    }
+
+    #[test]
+    fn step_shows_source() {
+        let mut debugger = debugger();
+        assert_eq!(
+            Ok(String::from("ROM[1] line 3: M=1")),
+            debugger.execute("step")
+        );
+        assert_eq!(
+            Ok(String::from("ROM[4] (LOOP) line 7: @i")),
+            debugger.execute("s 3")
+        );
+    }
+
+    #[test]
+    fn break_on_label_and_continue() {
+        let mut debugger = debugger();
+        assert_eq!(
+            Ok(String::from("Breakpoint 1 at ROM[9] (LOOP+5) line 12")),
+            debugger.execute("break 12")
+        );
+        assert_eq!(
+            Ok(String::from("Breakpoint 2 at ROM[18] (END) line 22")),
+            debugger.execute("b END")
+        );
+        assert_eq!(
+            Ok(String::from("Breakpoint 1\nROM[9] (LOOP+5) line 12: D;JGT")),
+            debugger.execute("continue")
+        );
+        debugger.execute("delete 1").unwrap();
+        assert_eq!(
+            Ok(String::from("Program halted\nROM[18] (END) line 22: @END")),
+            debugger.execute("c")
+        );
+        assert_eq!(
+            Ok(String::from("RAM[17] (sum) = 6")),
+            debugger.execute("print sum")
+        );
+        assert!(debugger.execute("step").is_err());
+    }
+
+    #[test]
+    fn watchpoint() {
+        let mut debugger = debugger();
+        assert_eq!(
+            Ok(String::from("Watchpoint 1: RAM[17] (sum)")),
+            debugger.execute("watch sum")
+        );
+        // M=0 does not change sum
+        assert_eq!(
+            Ok(String::from(
+                "Watchpoint 1: RAM[17] (sum)\nOld value = 0\nNew value = 1\nROM[14] (LOOP+10) line 17: @i"
+            )),
+            debugger.execute("c")
+        );
+    }
+
+    #[test]
+    fn next_steps_over_jumps() {
+        let call = "@RET\nD=A\n@FUNC\n0;JMP\n(RET)\n@R1\nM=D\n(END)\n@END\n0;JMP\n(FUNC)\n@R0\nM=M+1\n@RET\n0;JMP";
+        let mut debugger = Debugger::new(Program::from_asm(call).unwrap());
+        debugger.execute("s 3").unwrap();
+        assert_eq!(
+            Ok(String::from("ROM[4] (RET) line 6: @R1")),
+            debugger.execute("next")
+        );
+        assert_eq!(
+            Ok(String::from("RAM[0] (SP) = 1")),
+            debugger.execute("p R0")
+        );
+    }
+
+    #[test]
+    fn print_registers_and_memory() {
+        let mut debugger = debugger();
+        debugger.execute("s 3").unwrap();
+        assert_eq!(Ok(String::from("D = 0")), debugger.execute("print D"));
+        assert_eq!(Ok(String::from("A = 17")), debugger.execute("p A"));
+        assert_eq!(
+            Ok(String::from("RAM[0] (SP) = 3")),
+            debugger.execute("p RAM[0]")
+        );
+        assert_eq!(
+            Ok(String::from(
+                "A  = 17 (sum)\nD  = 0\nM  = RAM[17] (sum) = 0\nPC = ROM[3]"
+            )),
+            debugger.execute("info registers")
+        );
+        assert_eq!(
+            Ok(String::from("RAM[16] (i): 1\nRAM[17] (sum): 0\nRAM[18]: 0")),
+            debugger.execute("x/3 RAM[16]")
+        );
+        assert_eq!(
+            Ok(String::from("ROM[4] (LOOP): @i\nROM[5] (LOOP+1): D=M")),
+            debugger.execute("x/2 LOOP")
+        );
+        assert_eq!(
+            Ok(String::from(
+                "ROM[8] (LOOP+4): @END\nROM[9] (LOOP+5): D;JGT"
+            )),
+            debugger.execute("x/2 ROM[8]")
+        );
+    }
+
+    #[test]
+    fn set_values() {
+        let mut debugger = debugger();
+        assert_eq!(Ok(String::from("D = -1")), debugger.execute("set D -1"));
+        assert_eq!(
+            Ok(String::from("RAM[16] (i) = 42")),
+            debugger.execute("set i 42")
+        );
+        assert!(debugger.execute("set D 70000").is_err());
+
+        // ROM[5], not the code of line 5
+        assert_eq!(
+            Ok(String::from("PC = ROM[5] (LOOP+1)")),
+            debugger.execute("set PC 5")
+        );
+        assert_eq!(
+            Ok(String::from("PC = ROM[2]")),
+            debugger.execute("set PC line 4")
+        );
+        assert_eq!(
+            Ok(String::from("PC = ROM[4] (LOOP)")),
+            debugger.execute("set PC LOOP")
+        );
+        assert!(debugger.execute("set PC line LOOP").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let mut debugger = debugger();
+        assert!(debugger.execute("frobnicate").is_err());
+        assert!(debugger.execute("break NOWHERE").is_err());
+        assert!(debugger.execute("watch LOOP").is_err());
+        assert!(debugger.execute("delete 7").is_err());
+    }
+
+    #[test]
+    fn scripted_session() {
+        let input = Cursor::new("b END\nc\n\nq\n");
+        let mut output: Vec<u8> = vec![];
+        run(Program::from_asm(SUM).unwrap(), input, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("20 instructions, 2 labels, 2 variables"));
+        assert!(output.contains("(hdb) Breakpoint 1 at ROM[18] (END) line 22\n"));
+        assert!(output.contains("Program halted"));
+        // the empty line repeated `c`
+        assert!(output.contains("error: the program has halted"));
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+
+use crate::parser::Location;
+
+// warnings do not stop the assembler, notes only go with another
+// diagnostic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+// A problem found in the source, `location` and `length` cover the
+// offending part of the line so that editors can underline it. Notes
+// point at other places involved, like the first definition of a label
+// defined twice. `file` is set when a program has several files, `help`
+// when there is an easy fix to offer, like a mnemonic that was misspelt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Location,
+    pub length: u32,
+    pub message: String,
+    pub notes: Vec<Diagnostic>,
+    pub file: Option<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(location: Location, length: u32, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            location,
+            length,
+            message,
+            notes: vec![],
+            file: None,
+            help: None,
+        }
+    }
+
+    pub fn warning(location: Location, length: u32, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(location, length, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_note(mut self, location: Location, length: u32, message: String) -> Diagnostic {
+        self.notes.push(Diagnostic {
+            severity: Severity::Note,
+            ..Diagnostic::error(location, length, message)
+        });
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Diagnostic {
+        self.help = Some(help);
+        self
+    }
+
+    // the diagnostic and its notes come from `file`, unless a note already
+    // points at another one
+    pub fn in_file(mut self, file: &str) -> Diagnostic {
+        for note in &mut self.notes {
+            note.file.get_or_insert_with(|| String::from(file));
+        }
+        self.file = Some(String::from(file));
+        self
+    }
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+impl Diagnostic {
+    // `Max.asm:2:3: error: invalid comp` and a line per note, as GCC
+    // prints them for editor problem matchers. `file` is for the
+    // diagnostics that do not say which file they are in.
+    pub fn to_gcc(&self, file: &str) -> String {
+        let at = |diagnostic: &Diagnostic| {
+            let file = diagnostic.file.as_deref().unwrap_or(file);
+            let location = diagnostic.location;
+            format!("{}:{}:{}", file, location.line, location.column)
+        };
+        let mut text = format!("{}: {}: {}", at(self), self.severity.name(), self.message);
+        for note in &self.notes {
+            text.push_str(&format!("\n{}: note: {}", at(note), note.message));
+        }
+        text
+    }
+}
+
+// ANSI colours of the parts of a rendered diagnostic
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+const RESET: &str = "\x1b[0m";
+
+impl Diagnostic {
+    // The way rustc shows it, for people at a terminal:
+    //
+    //     error: invalid comp `D+2`
+    //      --> Max.asm:2:3
+    //       |
+    //     2 | D=D+2
+    //       |   ^^^
+    //       = help: did you mean `D+1`?
+    //
+    // `source` gives the contents of a file, the line is left out when it
+    // cannot. `file` is for the diagnostics that do not say which file they
+    // are in.
+    pub fn render(
+        &self,
+        file: &str,
+        source: &dyn Fn(&str) -> Option<String>,
+        colour: bool,
+    ) -> String {
+        let paint = |colour_code: &str, text: &str| match colour {
+            true => format!("{}{}{}", colour_code, text, RESET),
+            false => String::from(text),
+        };
+        let severity = match self.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => CYAN,
+        };
+
+        let mut text = format!(
+            "{}{}\n",
+            paint(severity, self.severity.name()),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        text.push_str(&self.snippet(file, source, &paint, severity));
+        if let Some(help) = &self.help {
+            let gutter = " ".repeat(self.location.line.to_string().len());
+            text.push_str(&format!(
+                "{} {} {}: {}\n",
+                gutter,
+                paint(BLUE, "="),
+                paint(CYAN, "help"),
+                help
+            ));
+        }
+        for note in &self.notes {
+            let file = self.file.as_deref().unwrap_or(file);
+            text.push_str(&format!(
+                "{}{}\n",
+                paint(CYAN, "note"),
+                paint(BOLD, &format!(": {}", note.message))
+            ));
+            text.push_str(&note.snippet(file, source, &paint, CYAN));
+        }
+        text
+    }
+
+    // where it is, and the line with the part of it underlined
+    fn snippet(
+        &self,
+        file: &str,
+        source: &dyn Fn(&str) -> Option<String>,
+        paint: &dyn Fn(&str, &str) -> String,
+        underline: &str,
+    ) -> String {
+        let file = self.file.as_deref().unwrap_or(file);
+        let Location { line, column } = self.location;
+        let gutter = " ".repeat(line.to_string().len());
+        let mut text = format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            paint(BLUE, "-->"),
+            file,
+            line,
+            column
+        );
+
+        let contents = source(file);
+        let code = match contents
+            .as_deref()
+            .and_then(|c| c.lines().nth(line as usize - 1))
+        {
+            Some(code) if line > 0 => code,
+            _ => return text,
+        };
+        // tabs stay tabs, so that the carets line up
+        let indent: String = code
+            .chars()
+            .take(column.saturating_sub(1) as usize)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = paint(underline, &"^".repeat(self.length.max(1) as usize));
+
+        let bar = paint(BLUE, "|");
+        text.push_str(&format!("{} {}\n", gutter, bar));
+        text.push_str(&format!(
+            "{} {} {}\n",
+            paint(BLUE, &line.to_string()),
+            bar,
+            code
+        ));
+        text.push_str(&format!("{} {} {}{}\n", gutter, bar, indent, carets));
+        text
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = self.severity.name();
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        write!(
+            f,
+            "{}: {} (line {}, column {})",
+            severity, self.message, self.location.line, self.location.column
+        )?;
+        for note in &self.notes {
+            // notes in another file say which one
+            let file = match &note.file {
+                Some(file) if note.file != self.file => format!("{}, ", file),
+                _ => String::new(),
+            };
+            write!(
+                f,
+                "\n  note: {} ({}line {}, column {})",
+                note.message, file, note.location.line, note.location.column
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gcc_style() {
+        let at = |line, column| Location { line, column };
+        let diagnostic = Diagnostic::error(at(2, 3), 3, String::from("label `A` is defined twice"))
+            .with_note(at(1, 2), 1, String::from("first defined here"));
+        assert_eq!(
+            "Max.asm:2:3: error: label `A` is defined twice\nMax.asm:1:2: note: first defined here",
+            diagnostic.to_gcc("Max.asm")
+        );
+        assert_eq!(Severity::Note, diagnostic.notes[0].severity);
+        assert!(!diagnostic.notes[0].is_error());
+        let warning = Diagnostic::warning(at(4, 1), 1, String::from("unused")).in_file("B.asm");
+        assert_eq!("B.asm:4:1: warning: unused", warning.to_gcc("Max.asm"));
+    }
+
+    #[test]
+    fn rendered() {
+        let at = |line, column| Location { line, column };
+        let source = |file: &str| (file == "Max.asm").then(|| String::from("@1\n\tD=D+2\n"));
+        let diagnostic = Diagnostic::error(at(2, 4), 3, String::from("invalid comp `D+2`"))
+            .with_help(String::from("did you mean `D+1`?"));
+        assert_eq!(
+            "error: invalid comp `D+2`\n --> Max.asm:2:4\n  |\n2 | \tD=D+2\n  | \t  ^^^\n  = help: did you mean `D+1`?\n",
+            diagnostic.render("Max.asm", &source, false)
+        );
+
+        let diagnostic = Diagnostic::error(at(3, 2), 1, String::from("label `A` is defined twice"))
+            .with_note(at(1, 2), 1, String::from("first defined here"))
+            .in_file("B.asm");
+        assert_eq!(
+            "error: label `A` is defined twice\n --> B.asm:3:2\nnote: first defined here\n --> B.asm:1:2\n",
+            diagnostic.render("Max.asm", &source, false)
+        );
+        assert!(diagnostic
+            .render("Max.asm", &source, true)
+            .starts_with("\x1b[1;31merror\x1b[0m"));
+    }
+}
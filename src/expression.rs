@@ -0,0 +1,337 @@
+use crate::code;
+
+// Constant expressions in A-instructions and data directives, evaluated
+// once the symbols are resolved:
+//
+//     @SCREEN+32    @WIDTH*2-1    @0x4000    @'A'    @-(1|2)
+//
+// The usual precedence applies, `*` and `/` bind tighter than `+` and
+// `-`, which bind tighter than `&` and `|`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(i64),
+    // with its offset in the text
+    Symbol(String, u32),
+    Negate(Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+}
+
+// `offset` and `length` locate the problem within the expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub offset: u32,
+    pub length: u32,
+    pub message: String,
+}
+
+fn error(offset: usize, length: usize, message: String) -> Error {
+    Error {
+        offset: offset as u32,
+        length: length.max(1) as u32,
+        message,
+    }
+}
+
+fn is_symbol_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || "_.$:".contains(c)
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+// Symbols are letters, digits, `_`, `.`, `$` and `:`, and may not start
+// with a digit.
+pub fn is_symbol(text: &str) -> bool {
+    text.starts_with(is_symbol_start) && text.chars().all(is_symbol_char)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(char),
+}
+
+// tokens with their offset and length
+fn tokenize(text: &str) -> Result<Vec<(Token, usize, usize)>, Error> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let mut end = i + 1;
+
+        let token = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() {
+            // 42, 0x2a, 0b101010
+            while end < chars.len() && chars[end].1.is_ascii_alphanumeric() {
+                end += 1;
+            }
+            let literal = &text[start..offset(&chars, end, text)];
+            match code::literal(literal) {
+                Some(value) => Token::Number(value as i64),
+                None => {
+                    return Err(error(
+                        start,
+                        literal.len(),
+                        format!("invalid number `{}`", literal),
+                    ))
+                }
+            }
+        } else if c == '\'' {
+            // 'A', '\n', 'left'
+            while end < chars.len() && chars[end].1 != '\'' {
+                if chars[end].1 == '\\' {
+                    end += 1;
+                }
+                end += 1;
+            }
+            end = (end + 1).min(chars.len());
+            let literal = &text[start..offset(&chars, end, text)];
+            match code::literal(literal) {
+                Some(value) => Token::Number(value as i64),
+                None => {
+                    return Err(error(
+                        start,
+                        literal.len(),
+                        format!("invalid character `{}`", literal),
+                    ))
+                }
+            }
+        } else if is_symbol_start(c) {
+            while end < chars.len() && is_symbol_char(chars[end].1) {
+                end += 1;
+            }
+            Token::Symbol(String::from(&text[start..offset(&chars, end, text)]))
+        } else if "+-*/&|()".contains(c) {
+            Token::Operator(c)
+        } else {
+            return Err(error(start, 1, format!("unexpected `{}`", c)));
+        };
+
+        tokens.push((token, start, offset(&chars, end, text) - start));
+        i = end;
+    }
+
+    Ok(tokens)
+}
+
+// byte offset of the i-th character
+fn offset(chars: &[(usize, char)], i: usize, text: &str) -> usize {
+    chars
+        .get(i)
+        .map(|(offset, _)| *offset)
+        .unwrap_or(text.len())
+}
+
+pub fn parse(text: &str) -> Result<Expression, Error> {
+    // most A-instructions are a single symbol
+    if is_symbol(text) {
+        return Ok(Expression::Symbol(String::from(text), 0));
+    }
+
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens,
+        current: 0,
+        length: text.len(),
+    };
+
+    let expression = parser.binary(0)?;
+    if let Some((_, offset, length)) = parser.tokens.get(parser.current) {
+        return Err(error(
+            *offset,
+            *length,
+            format!("unexpected `{}`", &text[*offset..*offset + *length]),
+        ));
+    }
+    Ok(expression)
+}
+
+// operators from the loosest to the tightest
+const PRECEDENCE: [&str; 3] = ["&|", "+-", "*/"];
+
+struct Parser {
+    tokens: Vec<(Token, usize, usize)>,
+    current: usize,
+    length: usize,
+}
+
+impl Parser {
+    fn operator(&self, operators: &str) -> Option<char> {
+        match self.tokens.get(self.current) {
+            Some((Token::Operator(c), _, _)) if operators.contains(*c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    // left associative, a-b-c is (a-b)-c
+    fn binary(&mut self, level: usize) -> Result<Expression, Error> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self.operator(PRECEDENCE[level]) {
+            self.current += 1;
+            let right = self.binary(level + 1)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, Error> {
+        let (token, offset, length) = match self.tokens.get(self.current) {
+            Some(token) => token.clone(),
+            None => return Err(error(self.length, 1, String::from("expected a value"))),
+        };
+        self.current += 1;
+
+        match token {
+            Token::Number(value) => Ok(Expression::Number(value)),
+            Token::Symbol(name) => Ok(Expression::Symbol(name, offset as u32)),
+            Token::Operator('-') => Ok(Expression::Negate(Box::new(self.unary()?))),
+            Token::Operator('(') => {
+                let expression = self.binary(0)?;
+                match self.operator(")") {
+                    Some(_) => {
+                        self.current += 1;
+                        Ok(expression)
+                    }
+                    None => Err(error(offset, length, String::from("unclosed `(`"))),
+                }
+            }
+            Token::Operator(c) => Err(error(
+                offset,
+                length,
+                format!("expected a value, found `{}`", c),
+            )),
+        }
+    }
+}
+
+impl Expression {
+    // every symbol with its offset, in order
+    pub fn symbols(&self) -> Vec<(&str, u32)> {
+        match self {
+            Expression::Number(_) => vec![],
+            Expression::Symbol(name, offset) => vec![(name.as_str(), *offset)],
+            Expression::Negate(operand) => operand.symbols(),
+            Expression::Binary(_, left, right) => {
+                let mut symbols = left.symbols();
+                symbols.extend(right.symbols());
+                symbols
+            }
+        }
+    }
+
+    pub fn evaluate(&self, resolve: &dyn Fn(&str) -> Option<i64>) -> Result<i64, Error> {
+        match self {
+            Expression::Number(value) => Ok(*value),
+            Expression::Symbol(name, offset) => resolve(name).ok_or_else(|| {
+                error(
+                    *offset as usize,
+                    name.len(),
+                    format!("unknown symbol `{}`", name),
+                )
+            }),
+            Expression::Negate(operand) => Ok(-operand.evaluate(resolve)?),
+            Expression::Binary(operator, left, right) => {
+                let x = left.evaluate(resolve)?;
+                let y = right.evaluate(resolve)?;
+                let value = match operator {
+                    '+' => x.checked_add(y),
+                    '-' => x.checked_sub(y),
+                    '*' => x.checked_mul(y),
+                    '/' => x.checked_div(y),
+                    '&' => Some(x & y),
+                    _ => Some(x | y),
+                };
+                value.ok_or_else(|| {
+                    let message = if *operator == '/' && y == 0 {
+                        String::from("division by zero")
+                    } else {
+                        String::from("overflow")
+                    };
+                    error(0, 0, message)
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Result<i64, String> {
+        let resolve = |name: &str| match name {
+            "SCREEN" => Some(16384),
+            "WIDTH" => Some(32),
+            _ => None,
+        };
+        parse(text)
+            .and_then(|expression| expression.evaluate(&resolve))
+            .map_err(|e| format!("{} at {}+{}", e.message, e.offset, e.length))
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(Ok(16384), value("0x4000"));
+        assert_eq!(Ok(10), value("0b1010"));
+        assert_eq!(Ok(65), value("'A'"));
+        assert_eq!(Ok(128), value("'\\n'"));
+        assert_eq!(Ok(130), value("'left'"));
+        assert_eq!(Ok(39), value("'\\''"));
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(Ok(16416), value("SCREEN+32"));
+        assert_eq!(Ok(63), value("WIDTH*2-1"));
+        assert_eq!(Ok(7), value("1|2+4"));
+        assert_eq!(Ok(3), value("10-4-3"));
+        assert_eq!(Ok(-2), value("-(1|1)*2"));
+        assert_eq!(Ok(6), value(" 2 * ( 1 + 2 ) "));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err(String::from("invalid number `0x` at 2+2")),
+            value("1+0x")
+        );
+        assert_eq!(
+            Err(String::from("invalid number `12ab` at 0+4")),
+            value("12ab")
+        );
+        assert_eq!(
+            Err(String::from("invalid character `'ab'` at 0+4")),
+            value("'ab'")
+        );
+        assert_eq!(
+            Err(String::from("unknown symbol `HEIGHT` at 6+6")),
+            value("WIDTH*HEIGHT")
+        );
+        assert_eq!(Err(String::from("expected a value at 2+1")), value("1+"));
+        assert_eq!(Err(String::from("unclosed `(` at 0+1")), value("(1+2"));
+        assert_eq!(Err(String::from("unexpected `2` at 2+1")), value("1 2"));
+        assert_eq!(Err(String::from("unexpected `#` at 1+1")), value("1#"));
+        assert_eq!(Err(String::from("division by zero at 0+1")), value("1/0"));
+    }
+
+    #[test]
+    fn symbols() {
+        let expression = parse("END-(BASE+1)").unwrap();
+        assert_eq!(vec![("END", 0), ("BASE", 5)], expression.symbols());
+        let expression = parse("sum.1$x:y*2").unwrap();
+        assert_eq!(vec![("sum.1$x:y", 0)], expression.symbols());
+        assert!(is_symbol("Main.main$ret.0"));
+        assert!(!is_symbol("1x"));
+        assert!(!is_symbol("LO OP"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use crate::data::Directive;
+use crate::expression;
+use crate::linker::Module;
+use crate::object::{self, Object, Word};
+
+// Removes the routines the program never reaches, like `ld --gc-sections`.
+// A routine runs from a global label to the next one. It is kept when the
+// entry point reaches it through the symbols of the A-instructions, or by
+// running off the end of the routine before it. The entry point is the
+// start of the first module, and the routines `.word` tables point at are
+// kept as well.
+pub struct Removed {
+    pub file: String,
+    pub label: String,
+    pub words: usize,
+}
+
+struct Section {
+    module: usize,
+    start: usize,
+    end: usize,
+}
+
+// the unconditional jumps, 0;JMP and the like
+fn is_jump(word: &Word) -> bool {
+    matches!(word, Word::Fixed(word) if word & 0xe000 == 0xe000 && word & 7 == 7)
+}
+
+fn symbols(expression: &str) -> Vec<String> {
+    match expression::parse(expression) {
+        Ok(parsed) => parsed
+            .symbols()
+            .into_iter()
+            .map(|(name, _)| String::from(name))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+fn sections(modules: &[Module]) -> Vec<Section> {
+    let mut sections = vec![];
+    for (module, Module { object, .. }) in modules.iter().enumerate() {
+        let mut starts: Vec<usize> = object
+            .labels
+            .iter()
+            .filter(|(label, _, _)| !object::is_local(label))
+            .map(|(_, offset, _)| *offset as usize)
+            .collect();
+        starts.push(0);
+        starts.sort();
+        starts.dedup();
+
+        let ends = starts.iter().skip(1).copied().chain([object.code.len()]);
+        for (start, end) in starts.iter().zip(ends) {
+            sections.push(Section {
+                module,
+                start: *start,
+                end,
+            });
+        }
+    }
+    sections
+}
+
+// Returns the routines removed, in ROM order.
+pub fn collect(modules: &mut [Module]) -> Vec<Removed> {
+    let sections = sections(modules);
+    let section = |module: usize, offset: u32| {
+        // labels at the end of the code are in the last section
+        sections
+            .iter()
+            .rposition(|s| s.module == module && s.start <= offset as usize)
+            .unwrap()
+    };
+
+    // local labels are only seen by their own module
+    let mut globals: HashMap<&str, usize> = HashMap::new();
+    let mut locals: HashMap<(usize, &str), usize> = HashMap::new();
+    for (module, Module { object, .. }) in modules.iter().enumerate() {
+        for (label, offset, _) in &object.labels {
+            if object::is_local(label) {
+                locals.insert((module, label), section(module, *offset));
+            } else {
+                globals.insert(label, section(module, *offset));
+            }
+        }
+    }
+    let find = |module: usize, name: &str| {
+        locals
+            .get(&(module, name))
+            .or_else(|| globals.get(name))
+            .copied()
+    };
+
+    let mut reached = vec![false; sections.len()];
+    let mut pending = vec![0];
+    for (module, Module { object, .. }) in modules.iter().enumerate() {
+        for (directive, _) in &object.directives {
+            let expressions = match directive {
+                Directive::Word(words) => words.clone(),
+                Directive::Entry(label) => vec![label.clone()],
+                Directive::Data(address) | Directive::Var(_, address) => vec![address.clone()],
+                Directive::Global(_) => continue,
+            };
+            for name in expressions.iter().flat_map(|e| symbols(e)) {
+                pending.extend(find(module, &name));
+            }
+        }
+    }
+
+    while let Some(index) = pending.pop() {
+        if reached[index] {
+            continue;
+        }
+        reached[index] = true;
+
+        let Section { module, start, end } = sections[index];
+        let code = &modules[module].object.code[start..end];
+        for (word, _) in code {
+            if let Word::Symbol(symbol) = word {
+                for name in symbols(symbol) {
+                    pending.extend(find(module, &name));
+                }
+            }
+        }
+        let falls_through = code.last().is_none_or(|(word, _)| !is_jump(word));
+        if falls_through && index + 1 < sections.len() {
+            pending.push(index + 1);
+        }
+    }
+
+    let mut removed = vec![];
+    for (index, section) in sections.iter().enumerate() {
+        let words = section.end - section.start;
+        if reached[index] || words == 0 {
+            continue;
+        }
+        let object = &modules[section.module].object;
+        let label = object
+            .labels
+            .iter()
+            .find(|(label, offset, _)| {
+                !object::is_local(label) && *offset as usize == section.start
+            })
+            .map(|(label, _, _)| label.clone())
+            .unwrap_or_default();
+        removed.push(Removed {
+            file: modules[section.module].file.clone(),
+            label,
+            words,
+        });
+    }
+
+    for (module, Module { object, .. }) in modules.iter_mut().enumerate() {
+        let kept = |offset: u32| reached[section(module, offset)];
+        *object = without(object, &kept);
+    }
+    removed
+}
+
+// the object with only the words and labels of the kept sections, the
+// labels moved down over the words removed before them
+fn without(object: &Object, kept: &dyn Fn(u32) -> bool) -> Object {
+    let mut code = vec![];
+    let mut moved = vec![];
+    for (offset, word) in object.code.iter().enumerate() {
+        moved.push(code.len() as u32);
+        if kept(offset as u32) {
+            code.push(word.clone());
+        }
+    }
+    moved.push(code.len() as u32);
+
+    let labels: Vec<_> = object
+        .labels
+        .iter()
+        .filter(|(_, offset, _)| kept(*offset))
+        .map(|(label, offset, location)| (label.clone(), moved[*offset as usize], *location))
+        .collect();
+    Object {
+        imports: object::imports(&code, &labels),
+        code,
+        labels,
+        directives: object.directives.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn modules(files: &[(&str, &str)]) -> Vec<Module> {
+        let config = Config::new(&[String::from("assembler"), String::from("Main.asm")]).unwrap();
+        files
+            .iter()
+            .map(|(file, contents)| Module {
+                file: String::from(*file),
+                object: object::compile(contents, &config, &mut vec![]).unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unreferenced_routines() {
+        let mut program = modules(&[
+            ("Main.asm", "@MULT\n0;JMP\n(UNUSED)\n@1\n0;JMP\n"),
+            (
+                "Math.asm",
+                "(DIV)\n@DIV\n0;JMP\n(MULT)\n@.loop\n(.loop)\n@ADD\nD=A\n(ADD)\n@i\n0;JMP\n(END)\n",
+            ),
+        ]);
+        // END is dropped too, but there is nothing to report
+        let removed = collect(&mut program);
+
+        let removed: Vec<(&str, &str, usize)> = removed
+            .iter()
+            .map(|r| (r.file.as_str(), r.label.as_str(), r.words))
+            .collect();
+        assert_eq!(
+            vec![("Main.asm", "UNUSED", 2), ("Math.asm", "DIV", 2)],
+            removed
+        );
+
+        let math = &program[1].object;
+        assert_eq!(5, math.code.len());
+        let labels: Vec<(&str, u32)> = math
+            .labels
+            .iter()
+            .map(|(label, offset, _)| (label.as_str(), *offset))
+            .collect();
+        assert_eq!(vec![("MULT", 0), ("MULT.loop", 1), ("ADD", 3)], labels);
+        assert_eq!(
+            vec!["i"],
+            math.imports
+                .iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn tables_are_kept() {
+        let mut program = modules(&[
+            ("Main.asm", ".word 100, HANDLER\n0;JMP\n"),
+            ("Handler.asm", "(HANDLER)\nD=0\n"),
+        ]);
+        assert!(collect(&mut program).is_empty());
+        assert_eq!(1, program[1].object.code.len());
+    }
+}
@@ -0,0 +1,711 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::Diagnostic;
+use crate::jack_parser::{self, Node};
+use crate::jack_tokenizer::{self, Token, TokenKind};
+
+// Foo.jack and directories with .jack files are compiled to VM code first
+pub fn is_jack(path: &str) -> bool {
+    !jack_files(Path::new(path)).is_empty()
+}
+
+fn jack_files(path: &Path) -> Vec<PathBuf> {
+    let is_jack = |file: &Path| {
+        file.extension()
+            .is_some_and(|extension| extension == "jack")
+    };
+
+    if !path.is_dir() {
+        return if is_jack(path) {
+            vec![path.to_path_buf()]
+        } else {
+            vec![]
+        };
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| is_jack(file))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+// Compiles every class to a .vm file next to its .jack file, with the
+// token and parse tree XML files when asked for. Returns what the VM
+// translator should read: Foo.vm, or the directory.
+pub fn compile(path: &str, xml: bool) -> Result<String, Box<dyn Error>> {
+    let mut errors = 0;
+
+    for file in jack_files(Path::new(path)) {
+        let contents = fs::read_to_string(&file)?;
+        let name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+
+        match compile_class(&contents) {
+            Ok((tokens, class, vm)) => {
+                if xml {
+                    fs::write(
+                        file.with_file_name(format!("{}T.xml", name)),
+                        jack_tokenizer::tokens_xml(&tokens),
+                    )?;
+                    fs::write(file.with_extension("xml"), class.to_xml())?;
+                }
+                fs::write(file.with_extension("vm"), vm.join("\n") + "\n")?;
+            }
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    println!("{}.jack: {}", name, diagnostic);
+                }
+                errors += diagnostics.len();
+            }
+        }
+    }
+
+    if errors > 0 {
+        return Err(format!("could not compile {} due to {} errors", path, errors).into());
+    }
+
+    if Path::new(path).is_dir() {
+        Ok(String::from(path))
+    } else {
+        Ok(Path::new(path)
+            .with_extension("vm")
+            .to_string_lossy()
+            .into_owned())
+    }
+}
+
+type Compiled = (Vec<Token>, Node, Vec<String>);
+
+pub fn compile_class(contents: &str) -> Result<Compiled, Vec<Diagnostic>> {
+    let tokens = jack_tokenizer::tokenize(contents).map_err(|diagnostic| vec![diagnostic])?;
+    let class = jack_parser::parse(tokens.clone()).map_err(|diagnostic| vec![diagnostic])?;
+
+    let mut compiler = Compiler::new();
+    compiler.class(&class);
+    if !compiler.diagnostics.is_empty() {
+        return Err(compiler.diagnostics);
+    }
+
+    Ok((tokens, class, compiler.output))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Static,
+    Field,
+    Argument,
+    Local,
+}
+
+impl Kind {
+    fn segment(self) -> &'static str {
+        match self {
+            Kind::Static => "static",
+            Kind::Field => "this",
+            Kind::Argument => "argument",
+            Kind::Local => "local",
+        }
+    }
+}
+
+struct Variable {
+    kind: Kind,
+    type_name: String,
+    index: u16,
+}
+
+// Generates VM code from the parse tree, one class at a time. Variables
+// live in two scopes, the class and the subroutine being compiled.
+struct Compiler {
+    class: String,
+    class_scope: HashMap<String, Variable>,
+    subroutine_scope: HashMap<String, Variable>,
+    output: Vec<String>,
+    labels: u32,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Compiler {
+    fn new() -> Compiler {
+        Compiler {
+            class: String::new(),
+            class_scope: HashMap::new(),
+            subroutine_scope: HashMap::new(),
+            output: vec![],
+            labels: 0,
+            diagnostics: vec![],
+        }
+    }
+
+    fn emit(&mut self, line: String) {
+        self.output.push(line);
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.diagnostics.push(Diagnostic::error(
+            token.location,
+            token.text.len() as u32,
+            message,
+        ));
+    }
+
+    fn define(&mut self, kind: Kind, type_name: &str, name: &Token) {
+        let scope = match kind {
+            Kind::Static | Kind::Field => &mut self.class_scope,
+            Kind::Argument | Kind::Local => &mut self.subroutine_scope,
+        };
+        if scope.contains_key(&name.text) {
+            let message = format!("`{}` is already defined", name.text);
+            self.error(name, message);
+            return;
+        }
+
+        let index = scope.values().filter(|v| v.kind == kind).count() as u16;
+        scope.insert(
+            name.text.clone(),
+            Variable {
+                kind,
+                type_name: String::from(type_name),
+                index,
+            },
+        );
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.subroutine_scope
+            .get(name)
+            .or_else(|| self.class_scope.get(name))
+    }
+
+    // ('static' | 'field' | 'var') type varName (',' varName)* ';'
+    fn var_dec(&mut self, kind: Kind, node: &Node) {
+        let children = node.children();
+        let type_name = children[1].text();
+
+        for child in &children[2..] {
+            if let Some(token) = child.token().filter(|t| t.kind == TokenKind::Identifier) {
+                self.define(kind, type_name, token);
+            }
+        }
+    }
+
+    fn class(&mut self, node: &Node) {
+        let children = node.children();
+        self.class = String::from(children[1].text());
+
+        for child in children {
+            match child.rule() {
+                Some("classVarDec") => {
+                    let kind = if child.children()[0].is("static") {
+                        Kind::Static
+                    } else {
+                        Kind::Field
+                    };
+                    self.var_dec(kind, child);
+                }
+                Some("subroutineDec") => self.subroutine(child),
+                _ => {}
+            }
+        }
+    }
+
+    // kind type name '(' parameterList ')' subroutineBody
+    fn subroutine(&mut self, node: &Node) {
+        let children = node.children();
+        let kind = children[0].text();
+        let name = children[2].text();
+        let body = children[6].children();
+
+        self.subroutine_scope.clear();
+        self.labels = 0;
+
+        if kind == "method" {
+            let this = Token {
+                kind: TokenKind::Keyword,
+                text: String::from("this"),
+                location: children[0].location(),
+            };
+            let class = self.class.clone();
+            self.define(Kind::Argument, &class, &this);
+        }
+
+        // type varName (',' type varName)*
+        let parameters: Vec<&Node> = children[4]
+            .children()
+            .iter()
+            .filter(|child| !child.is(","))
+            .collect();
+        for parameter in parameters.chunks(2) {
+            if let Some(token) = parameter[1].token() {
+                self.define(Kind::Argument, parameter[0].text(), token);
+            }
+        }
+
+        for var_dec in body.iter().filter(|child| child.rule() == Some("varDec")) {
+            self.var_dec(Kind::Local, var_dec);
+        }
+        let locals = self
+            .subroutine_scope
+            .values()
+            .filter(|v| v.kind == Kind::Local)
+            .count();
+        self.emit(format!("function {}.{} {}", self.class, name, locals));
+
+        match kind {
+            "constructor" => {
+                let fields = self
+                    .class_scope
+                    .values()
+                    .filter(|v| v.kind == Kind::Field)
+                    .count();
+                self.emit(format!("push constant {}", fields));
+                self.emit(String::from("call Memory.alloc 1"));
+                self.emit(String::from("pop pointer 0"));
+            }
+            "method" => {
+                self.emit(String::from("push argument 0"));
+                self.emit(String::from("pop pointer 0"));
+            }
+            _ => {}
+        }
+
+        if let Some(statements) = body.iter().find(|c| c.rule() == Some("statements")) {
+            self.statements(statements);
+        }
+    }
+
+    fn statements(&mut self, node: &Node) {
+        for statement in node.children() {
+            let children = statement.children();
+            match statement.rule() {
+                Some("letStatement") => self.let_statement(children),
+                Some("ifStatement") => self.if_statement(children),
+                Some("whileStatement") => self.while_statement(children),
+                Some("doStatement") => {
+                    self.call(&children[1..children.len() - 1]);
+                    self.emit(String::from("pop temp 0"));
+                }
+                Some("returnStatement") => {
+                    if children.len() == 3 {
+                        self.expression(&children[1]);
+                    } else {
+                        self.emit(String::from("push constant 0"));
+                    }
+                    self.emit(String::from("return"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // 'let' varName ('[' expression ']')? '=' expression ';'
+    fn let_statement(&mut self, children: &[Node]) {
+        if children[2].is("[") {
+            // the value is computed before THAT points at the element,
+            // as it may index arrays itself
+            self.push_variable(&children[1]);
+            self.expression(&children[3]);
+            self.emit(String::from("add"));
+            self.expression(&children[6]);
+            self.emit(String::from("pop temp 0"));
+            self.emit(String::from("pop pointer 1"));
+            self.emit(String::from("push temp 0"));
+            self.emit(String::from("pop that 0"));
+        } else {
+            self.expression(&children[3]);
+            if let Some((segment, index)) = self.variable(&children[1]) {
+                self.emit(format!("pop {} {}", segment, index));
+            }
+        }
+    }
+
+    // 'if' '(' expression ')' '{' statements '}' ('else' '{' statements '}')?
+    fn if_statement(&mut self, children: &[Node]) {
+        let label = self.labels;
+        self.labels += 1;
+
+        self.expression(&children[2]);
+        self.emit(String::from("not"));
+        self.emit(format!("if-goto IF_ELSE{}", label));
+        self.statements(&children[5]);
+
+        if children.len() > 7 {
+            self.emit(format!("goto IF_END{}", label));
+            self.emit(format!("label IF_ELSE{}", label));
+            self.statements(&children[9]);
+            self.emit(format!("label IF_END{}", label));
+        } else {
+            self.emit(format!("label IF_ELSE{}", label));
+        }
+    }
+
+    // 'while' '(' expression ')' '{' statements '}'
+    fn while_statement(&mut self, children: &[Node]) {
+        let label = self.labels;
+        self.labels += 1;
+
+        self.emit(format!("label WHILE_EXP{}", label));
+        self.expression(&children[2]);
+        self.emit(String::from("not"));
+        self.emit(format!("if-goto WHILE_END{}", label));
+        self.statements(&children[5]);
+        self.emit(format!("goto WHILE_EXP{}", label));
+        self.emit(format!("label WHILE_END{}", label));
+    }
+
+    // term (op term)*, evaluated left to right as the language leaves
+    // precedence out
+    fn expression(&mut self, node: &Node) {
+        let children = node.children();
+        self.term(&children[0]);
+
+        for pair in children[1..].chunks(2) {
+            self.term(&pair[1]);
+            let operation = match pair[0].text() {
+                "+" => "add",
+                "-" => "sub",
+                "*" => "call Math.multiply 2",
+                "/" => "call Math.divide 2",
+                "&" => "and",
+                "|" => "or",
+                "<" => "lt",
+                ">" => "gt",
+                _ => "eq",
+            };
+            self.emit(String::from(operation));
+        }
+    }
+
+    fn term(&mut self, node: &Node) {
+        let children = node.children();
+        let first = match children[0].token() {
+            Some(token) => token.clone(),
+            None => return,
+        };
+
+        match first.kind {
+            TokenKind::IntegerConstant => self.emit(format!("push constant {}", first.text)),
+            TokenKind::StringConstant => {
+                self.emit(format!("push constant {}", first.text.len()));
+                self.emit(String::from("call String.new 1"));
+                for c in first.text.chars() {
+                    self.emit(format!("push constant {}", c as u32));
+                    self.emit(String::from("call String.appendChar 2"));
+                }
+            }
+            TokenKind::Keyword => match first.text.as_str() {
+                "true" => {
+                    self.emit(String::from("push constant 0"));
+                    self.emit(String::from("not"));
+                }
+                "this" => self.emit(String::from("push pointer 0")),
+                _ => self.emit(String::from("push constant 0")),
+            },
+            TokenKind::Symbol => {
+                if first.text == "(" {
+                    self.expression(&children[1]);
+                } else {
+                    self.term(&children[1]);
+                    let operation = if first.text == "-" { "neg" } else { "not" };
+                    self.emit(String::from(operation));
+                }
+            }
+            TokenKind::Identifier => {
+                if children.len() == 1 {
+                    self.push_variable(&children[0]);
+                } else if children[1].is("[") {
+                    self.push_variable(&children[0]);
+                    self.expression(&children[2]);
+                    self.emit(String::from("add"));
+                    self.emit(String::from("pop pointer 1"));
+                    self.emit(String::from("push that 0"));
+                } else {
+                    self.call(children);
+                }
+            }
+        }
+    }
+
+    fn variable(&mut self, node: &Node) -> Option<(&'static str, u16)> {
+        let token = node.token()?;
+        match self.lookup(&token.text) {
+            Some(variable) => Some((variable.kind.segment(), variable.index)),
+            None => {
+                let message = format!("undefined variable `{}`", token.text);
+                self.error(token, message);
+                None
+            }
+        }
+    }
+
+    fn push_variable(&mut self, node: &Node) {
+        if let Some((segment, index)) = self.variable(node) {
+            self.emit(format!("push {} {}", segment, index));
+        }
+    }
+
+    // subroutineName '(' expressionList ')' calls a method on this,
+    // (className | varName) '.' subroutineName '(' expressionList ')'
+    // a function or a method on the variable
+    fn call(&mut self, children: &[Node]) {
+        let (function, mut arguments, list) = if children[1].is(".") {
+            let receiver = children[0].text();
+            let name = children[2].text();
+            match self.lookup(receiver) {
+                Some(variable) => {
+                    let function = format!("{}.{}", variable.type_name, name);
+                    self.push_variable(&children[0]);
+                    (function, 1, &children[4])
+                }
+                None => (format!("{}.{}", receiver, name), 0, &children[4]),
+            }
+        } else {
+            self.emit(String::from("push pointer 0"));
+            (
+                format!("{}.{}", self.class, children[0].text()),
+                1,
+                &children[2],
+            )
+        };
+
+        for expression in list.children().iter().filter(|child| !child.is(",")) {
+            self.expression(expression);
+            arguments += 1;
+        }
+
+        self.emit(format!("call {} {}", function, arguments));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Computer, Program};
+    use crate::vm;
+
+    // enough of the OS for the tests to allocate objects and arrays
+    const MEMORY: &str = "class Memory {
+        static int free;
+        function int alloc(int size) {
+            var int block;
+            if (free = 0) { let free = 2048; }
+            let block = free;
+            let free = free + size;
+            return block;
+        }
+    }";
+    const ARRAY: &str = "class Array {
+        function Array new(int size) { return Memory.alloc(size); }
+    }";
+    const SYS: &str = "class Sys {
+        function void init() { do Main.main(); while (true) {} }
+    }";
+
+    fn vm_code(contents: &str) -> Vec<String> {
+        compile_class(contents).unwrap().2
+    }
+
+    fn errors(contents: &str) -> Vec<String> {
+        match compile_class(contents) {
+            Ok(_) => vec![],
+            Err(diagnostics) => diagnostics.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    // compiles the classes along with the small OS above, and runs them
+    fn run(classes: &[(&str, &str)]) -> (Computer, Program) {
+        let mut sources = vec![];
+        for (name, contents) in
+            classes
+                .iter()
+                .chain(&[("Memory", MEMORY), ("Array", ARRAY), ("Sys", SYS)])
+        {
+            sources.push((String::from(*name), vm_code(contents).join("\n")));
+        }
+
+        let program = Program::from_asm(&vm::generate(&sources).unwrap()).unwrap();
+        let mut computer = Computer::new(program.rom.clone());
+        while computer.cycles < 100_000 {
+            computer.step();
+        }
+        (computer, program)
+    }
+
+    #[test]
+    fn function() {
+        assert_eq!(
+            vec![
+                "function Main.double 1",
+                "push argument 0",
+                "push constant 2",
+                "call Math.multiply 2",
+                "pop local 0",
+                "push local 0",
+                "return",
+            ],
+            vm_code(
+                "class Main { function int double(int x) { var int y; let y = x * 2; return y; } }"
+            )
+        );
+    }
+
+    #[test]
+    fn constructor_and_methods() {
+        let code = vm_code(
+            "class Point {
+                field int x, y;
+                static int count;
+                constructor Point new(int ax) { let x = ax; let count = count + 1; return this; }
+                method int getX() { return x; }
+                method void move(Point other) { let x = other.getX(); do draw(); return; }
+            }",
+        );
+        assert_eq!(
+            vec![
+                "function Point.new 0",
+                "push constant 2",
+                "call Memory.alloc 1",
+                "pop pointer 0",
+                "push argument 0",
+                "pop this 0",
+                "push static 0",
+                "push constant 1",
+                "add",
+                "pop static 0",
+                "push pointer 0",
+                "return",
+                "function Point.getX 0",
+                "push argument 0",
+                "pop pointer 0",
+                "push this 0",
+                "return",
+                "function Point.move 0",
+                "push argument 0",
+                "pop pointer 0",
+                "push argument 1",
+                "call Point.getX 1",
+                "pop this 0",
+                "push pointer 0",
+                "call Point.draw 1",
+                "pop temp 0",
+                "push constant 0",
+                "return",
+            ],
+            code
+        );
+    }
+
+    #[test]
+    fn strings_and_keywords() {
+        let code = vm_code(
+            "class Main { function void f() { do Output.printString(\"Hi\"); return ~true | null; } }",
+        );
+        assert_eq!(
+            vec![
+                "function Main.f 0",
+                "push constant 2",
+                "call String.new 1",
+                "push constant 72",
+                "call String.appendChar 2",
+                "push constant 105",
+                "call String.appendChar 2",
+                "call Output.printString 1",
+                "pop temp 0",
+                "push constant 0",
+                "not",
+                "not",
+                "push constant 0",
+                "or",
+                "return",
+            ],
+            code
+        );
+    }
+
+    #[test]
+    fn undefined_variables() {
+        assert_eq!(
+            vec![
+                "error: `x` is already defined (line 1, column 47)",
+                "error: undefined variable `y` (line 1, column 58)",
+            ],
+            errors("class Main { function void f(int x) { var int x; let x = y; return; } }")
+        );
+    }
+
+    #[test]
+    fn loops_and_branches() {
+        let main = "class Main {
+            static int sum, odd;
+            function void main() {
+                var int i;
+                let i = 1;
+                while (i < 11) {
+                    let sum = sum + i;
+                    if ((i & 1) = 1) { let odd = odd + 1; } else { let odd = odd; }
+                    let i = i + 1;
+                }
+                return;
+            }
+        }";
+        let (computer, program) = run(&[("Main", main)]);
+        let sum = program.symbol_table.get_address("Main.0").unwrap() as usize;
+        let odd = program.symbol_table.get_address("Main.1").unwrap() as usize;
+        assert_eq!(55, computer.ram[sum]);
+        assert_eq!(5, computer.ram[odd]);
+    }
+
+    #[test]
+    fn objects_and_arrays() {
+        let main = "class Main {
+            static int result;
+            function void main() {
+                var Array a;
+                var Box b;
+                let a = Array.new(3);
+                let a[0] = 7;
+                let a[a[0] - 6] = a[0] + 1;
+                let b = Box.new(a[1]);
+                do b.add(5);
+                let result = b.get();
+                return;
+            }
+        }";
+        let boxed = "class Box {
+            field int value;
+            constructor Box new(int v) { let value = v; return this; }
+            method void add(int n) { let value = value + n; return; }
+            method int get() { return value; }
+        }";
+        let (computer, program) = run(&[("Main", main), ("Box", boxed)]);
+        // the array and then the box land at the bottom of the heap
+        assert_eq!([7, 8], computer.ram[2048..2050]);
+        assert_eq!(13, computer.ram[2051]);
+        let result = program.symbol_table.get_address("Main.0").unwrap() as usize;
+        assert_eq!(13, computer.ram[result]);
+    }
+
+    #[test]
+    fn xml_files() {
+        let (tokens, class, _) = compile_class("class Main { }").unwrap();
+        assert_eq!(
+            "<tokens>\n<keyword> class </keyword>\n<identifier> Main </identifier>\n\
+             <symbol> { </symbol>\n<symbol> } </symbol>\n</tokens>\n",
+            jack_tokenizer::tokens_xml(&tokens)
+        );
+        assert!(class
+            .to_xml()
+            .starts_with("<class>\n  <keyword> class </keyword>\n"));
+    }
+}
@@ -0,0 +1,474 @@
+use crate::diagnostic::Diagnostic;
+use crate::jack_tokenizer::{Token, TokenKind};
+use crate::parser::Location;
+
+// Parse tree with the rules of the standard Jack grammar as inner nodes,
+// so that it maps one to one to the expected Foo.xml files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Token(Token),
+    Rule(&'static str, Vec<Node>),
+}
+
+impl Node {
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Node::Token(token) => Some(token),
+            Node::Rule(_, _) => None,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        self.token().map(|token| token.text.as_str()).unwrap_or("")
+    }
+
+    pub fn is(&self, text: &str) -> bool {
+        self.token().is_some_and(|token| token.is(text))
+    }
+
+    pub fn rule(&self) -> Option<&'static str> {
+        match self {
+            Node::Rule(rule, _) => Some(rule),
+            Node::Token(_) => None,
+        }
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Rule(_, children) => children,
+            Node::Token(_) => &[],
+        }
+    }
+
+    // first token of the node, for diagnostics
+    pub fn location(&self) -> Location {
+        match self {
+            Node::Token(token) => token.location,
+            Node::Rule(_, children) => children
+                .first()
+                .map(|child| child.location())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        self.write_xml(&mut xml, 0);
+        xml
+    }
+
+    fn write_xml(&self, xml: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Node::Token(token) => {
+                xml.push_str(&format!("{}{}\n", indent, token.to_xml()));
+            }
+            Node::Rule(rule, children) => {
+                xml.push_str(&format!("{}<{}>\n", indent, rule));
+                for child in children {
+                    child.write_xml(xml, depth + 1);
+                }
+                xml.push_str(&format!("{}</{}>\n", indent, rule));
+            }
+        }
+    }
+}
+
+pub fn parse(tokens: Vec<Token>) -> Result<Node, Diagnostic> {
+    let mut parser = Parser { tokens, current: 0 };
+    let class = parser.class()?;
+
+    if let Some(token) = parser.peek() {
+        return Err(Diagnostic::error(
+            token.location,
+            token.text.len() as u32,
+            format!("unexpected `{}` after the class", token.text),
+        ));
+    }
+    Ok(class)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek().is_some_and(|token| token.is(text))
+    }
+
+    fn peek_any(&self, texts: &[&str]) -> bool {
+        texts.iter().any(|text| self.peek_is(text))
+    }
+
+    fn error(&self, expected: &str) -> Diagnostic {
+        match self.peek() {
+            Some(token) => Diagnostic::error(
+                token.location,
+                token.text.len() as u32,
+                format!("expected {}, found `{}`", expected, token.text),
+            ),
+            None => Diagnostic::error(
+                self.tokens
+                    .last()
+                    .map(|token| token.location)
+                    .unwrap_or_default(),
+                1,
+                format!("expected {}, found end of file", expected),
+            ),
+        }
+    }
+
+    fn next(&mut self) -> Node {
+        let token = self.tokens[self.current].clone();
+        self.current += 1;
+        Node::Token(token)
+    }
+
+    // a keyword or symbol
+    fn expect(&mut self, text: &str) -> Result<Node, Diagnostic> {
+        if self.peek_is(text) {
+            Ok(self.next())
+        } else {
+            Err(self.error(&format!("`{}`", text)))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<Node, Diagnostic> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Identifier => Ok(self.next()),
+            _ => Err(self.error("an identifier")),
+        }
+    }
+
+    // int | char | boolean | className
+    fn type_name(&mut self, or_void: bool) -> Result<Node, Diagnostic> {
+        if self.peek_any(&["int", "char", "boolean"]) || (or_void && self.peek_is("void")) {
+            Ok(self.next())
+        } else {
+            self.identifier().map_err(|_| self.error("a type"))
+        }
+    }
+
+    // 'class' className '{' classVarDec* subroutineDec* '}'
+    fn class(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.expect("class")?, self.identifier()?, self.expect("{")?];
+
+        while self.peek_any(&["static", "field"]) {
+            children.push(self.var_dec("classVarDec")?);
+        }
+        while self.peek_any(&["constructor", "function", "method"]) {
+            children.push(self.subroutine_dec()?);
+        }
+
+        children.push(self.expect("}")?);
+        Ok(Node::Rule("class", children))
+    }
+
+    // ('static' | 'field' | 'var') type varName (',' varName)* ';'
+    fn var_dec(&mut self, rule: &'static str) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.next(), self.type_name(false)?, self.identifier()?];
+
+        while self.peek_is(",") {
+            children.push(self.next());
+            children.push(self.identifier()?);
+        }
+
+        children.push(self.expect(";")?);
+        Ok(Node::Rule(rule, children))
+    }
+
+    fn subroutine_dec(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![
+            self.next(),
+            self.type_name(true)?,
+            self.identifier()?,
+            self.expect("(")?,
+        ];
+
+        // ((type varName) (',' type varName)*)?
+        let mut parameters = vec![];
+        if !self.peek_is(")") {
+            parameters.push(self.type_name(false)?);
+            parameters.push(self.identifier()?);
+            while self.peek_is(",") {
+                parameters.push(self.next());
+                parameters.push(self.type_name(false)?);
+                parameters.push(self.identifier()?);
+            }
+        }
+        children.push(Node::Rule("parameterList", parameters));
+        children.push(self.expect(")")?);
+
+        // '{' varDec* statements '}'
+        let mut body = vec![self.expect("{")?];
+        while self.peek_is("var") {
+            body.push(self.var_dec("varDec")?);
+        }
+        body.push(self.statements()?);
+        body.push(self.expect("}")?);
+        children.push(Node::Rule("subroutineBody", body));
+
+        Ok(Node::Rule("subroutineDec", children))
+    }
+
+    fn statements(&mut self) -> Result<Node, Diagnostic> {
+        let mut statements = vec![];
+
+        loop {
+            let keyword = self
+                .peek()
+                .filter(|token| token.kind == TokenKind::Keyword)
+                .map(|token| token.text.as_str());
+            let statement = match keyword {
+                Some("let") => self.let_statement()?,
+                Some("if") => self.if_statement()?,
+                Some("while") => self.while_statement()?,
+                Some("do") => self.do_statement()?,
+                Some("return") => self.return_statement()?,
+                _ => break,
+            };
+            statements.push(statement);
+        }
+
+        Ok(Node::Rule("statements", statements))
+    }
+
+    // 'let' varName ('[' expression ']')? '=' expression ';'
+    fn let_statement(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.next(), self.identifier()?];
+
+        if self.peek_is("[") {
+            children.push(self.next());
+            children.push(self.expression()?);
+            children.push(self.expect("]")?);
+        }
+
+        children.push(self.expect("=")?);
+        children.push(self.expression()?);
+        children.push(self.expect(";")?);
+        Ok(Node::Rule("letStatement", children))
+    }
+
+    // '{' statements '}'
+    fn block(&mut self, children: &mut Vec<Node>) -> Result<(), Diagnostic> {
+        children.push(self.expect("{")?);
+        children.push(self.statements()?);
+        children.push(self.expect("}")?);
+        Ok(())
+    }
+
+    // '(' expression ')'
+    fn condition(&mut self, children: &mut Vec<Node>) -> Result<(), Diagnostic> {
+        children.push(self.expect("(")?);
+        children.push(self.expression()?);
+        children.push(self.expect(")")?);
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.next()];
+        self.condition(&mut children)?;
+        self.block(&mut children)?;
+
+        if self.peek_is("else") {
+            children.push(self.next());
+            self.block(&mut children)?;
+        }
+
+        Ok(Node::Rule("ifStatement", children))
+    }
+
+    fn while_statement(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.next()];
+        self.condition(&mut children)?;
+        self.block(&mut children)?;
+        Ok(Node::Rule("whileStatement", children))
+    }
+
+    fn do_statement(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.next(), self.identifier()?];
+        self.subroutine_call(&mut children)?;
+        children.push(self.expect(";")?);
+        Ok(Node::Rule("doStatement", children))
+    }
+
+    fn return_statement(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.next()];
+        if !self.peek_is(";") {
+            children.push(self.expression()?);
+        }
+        children.push(self.expect(";")?);
+        Ok(Node::Rule("returnStatement", children))
+    }
+
+    // the rest of a call after its first identifier:
+    // ('.' subroutineName)? '(' expressionList ')'
+    fn subroutine_call(&mut self, children: &mut Vec<Node>) -> Result<(), Diagnostic> {
+        if self.peek_is(".") {
+            children.push(self.next());
+            children.push(self.identifier()?);
+        }
+
+        children.push(self.expect("(")?);
+        let mut expressions = vec![];
+        if !self.peek_is(")") {
+            expressions.push(self.expression()?);
+            while self.peek_is(",") {
+                expressions.push(self.next());
+                expressions.push(self.expression()?);
+            }
+        }
+        children.push(Node::Rule("expressionList", expressions));
+        children.push(self.expect(")")?);
+        Ok(())
+    }
+
+    // term (op term)*
+    fn expression(&mut self) -> Result<Node, Diagnostic> {
+        let mut children = vec![self.term()?];
+
+        while self.peek_any(&["+", "-", "*", "/", "&", "|", "<", ">", "="]) {
+            children.push(self.next());
+            children.push(self.term()?);
+        }
+
+        Ok(Node::Rule("expression", children))
+    }
+
+    fn term(&mut self) -> Result<Node, Diagnostic> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("a term"))?;
+        let mut children = vec![];
+
+        match token.kind {
+            TokenKind::IntegerConstant | TokenKind::StringConstant => children.push(self.next()),
+            TokenKind::Keyword if ["true", "false", "null", "this"].contains(&&*token.text) => {
+                children.push(self.next())
+            }
+            TokenKind::Symbol if token.text == "(" => {
+                self.condition(&mut children)?;
+            }
+            TokenKind::Symbol if token.text == "-" || token.text == "~" => {
+                children.push(self.next());
+                children.push(self.term()?);
+            }
+            TokenKind::Identifier => {
+                children.push(self.next());
+                if self.peek_is("[") {
+                    children.push(self.next());
+                    children.push(self.expression()?);
+                    children.push(self.expect("]")?);
+                } else if self.peek_any(&[".", "("]) {
+                    self.subroutine_call(&mut children)?;
+                }
+            }
+            _ => return Err(self.error("a term")),
+        }
+
+        Ok(Node::Rule("term", children))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jack_tokenizer::tokenize;
+
+    fn tree(contents: &str) -> Result<Node, String> {
+        parse(tokenize(contents).unwrap()).map_err(|diagnostic| diagnostic.to_string())
+    }
+
+    #[test]
+    fn class_xml() {
+        let xml = tree("class Main { field int x, y; function void main() { return; } }")
+            .unwrap()
+            .to_xml();
+        assert_eq!(
+            "<class>
+  <keyword> class </keyword>
+  <identifier> Main </identifier>
+  <symbol> { </symbol>
+  <classVarDec>
+    <keyword> field </keyword>
+    <keyword> int </keyword>
+    <identifier> x </identifier>
+    <symbol> , </symbol>
+    <identifier> y </identifier>
+    <symbol> ; </symbol>
+  </classVarDec>
+  <subroutineDec>
+    <keyword> function </keyword>
+    <keyword> void </keyword>
+    <identifier> main </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <statements>
+        <returnStatement>
+          <keyword> return </keyword>
+          <symbol> ; </symbol>
+        </returnStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <symbol> } </symbol>
+</class>
+",
+            xml
+        );
+    }
+
+    #[test]
+    fn expressions() {
+        let class = tree(
+            "class A { method int f(int a, Array b) { \
+             let b[a] = -a + (b[1] * A.g(a, 2)) & ~x.h(); return this; } }",
+        )
+        .unwrap();
+        let statement = &class.children()[3].children()[6].children()[1].children()[0];
+        assert_eq!(Some("letStatement"), statement.rule());
+        let rules: Vec<&str> = statement.children()[6]
+            .children()
+            .iter()
+            .map(|node| node.rule().unwrap_or(node.text()))
+            .collect();
+        assert_eq!(vec!["term", "+", "term", "&", "term"], rules);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err(String::from(
+                "error: expected `;`, found `}` (line 2, column 1)"
+            )),
+            tree("class A { function void f() { do g()\n} }")
+        );
+        assert_eq!(
+            Err(String::from(
+                "error: expected a term, found `;` (line 1, column 39)"
+            )),
+            tree("class A { function void f() { let x = ; } }")
+        );
+        assert_eq!(
+            Err(String::from(
+                "error: expected `}`, found end of file (line 1, column 9)"
+            )),
+            tree("class A { ")
+        );
+        assert_eq!(
+            Err(String::from(
+                "error: expected an identifier, found `;` (line 1, column 20)"
+            )),
+            tree("class A { field int; }")
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::code;
+use crate::diagnostic::Diagnostic;
+use crate::parser::Location;
+
+const KEYWORDS: [&str; 21] = [
+    "class",
+    "constructor",
+    "function",
+    "method",
+    "field",
+    "static",
+    "var",
+    "int",
+    "char",
+    "boolean",
+    "void",
+    "true",
+    "false",
+    "null",
+    "this",
+    "let",
+    "do",
+    "if",
+    "else",
+    "while",
+    "return",
+];
+
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Keyword,
+    Symbol,
+    Identifier,
+    IntegerConstant,
+    StringConstant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub location: Location,
+}
+
+impl Token {
+    pub fn is(&self, text: &str) -> bool {
+        self.text == text && matches!(self.kind, TokenKind::Keyword | TokenKind::Symbol)
+    }
+
+    // <keyword> class </keyword>
+    pub fn to_xml(&self) -> String {
+        let tag = match self.kind {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Symbol => "symbol",
+            TokenKind::Identifier => "identifier",
+            TokenKind::IntegerConstant => "integerConstant",
+            TokenKind::StringConstant => "stringConstant",
+        };
+        format!("<{}> {} </{}>", tag, escape(&self.text), tag)
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// FooT.xml
+pub fn tokens_xml(tokens: &[Token]) -> String {
+    let mut xml = String::from("<tokens>\n");
+    for token in tokens {
+        xml.push_str(&token.to_xml());
+        xml.push('\n');
+    }
+    xml.push_str("</tokens>\n");
+    xml
+}
+
+pub fn tokenize(contents: &str) -> Result<Vec<Token>, Diagnostic> {
+    let chars: Vec<char> = contents.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    let mut location = Location { line: 1, column: 1 };
+
+    // moves past `count` characters keeping track of lines
+    let advance = |i: &mut usize, location: &mut Location, count: usize| {
+        for _ in 0..count {
+            if chars[*i] == '\n' {
+                location.line += 1;
+                location.column = 1;
+            } else {
+                location.column += 1;
+            }
+            *i += 1;
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = location;
+
+        if c.is_whitespace() {
+            advance(&mut i, &mut location, 1);
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                advance(&mut i, &mut location, 1);
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            advance(&mut i, &mut location, 2);
+            loop {
+                if i >= chars.len() {
+                    return Err(Diagnostic::error(
+                        start,
+                        2,
+                        String::from("unterminated comment"),
+                    ));
+                }
+                if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    advance(&mut i, &mut location, 2);
+                    break;
+                }
+                advance(&mut i, &mut location, 1);
+            }
+        } else if c == '"' {
+            advance(&mut i, &mut location, 1);
+            let mut text = String::new();
+            loop {
+                match chars.get(i) {
+                    Some('"') => break,
+                    Some(c) if *c != '\n' => {
+                        // strings are made of Hack characters
+                        if code::key(&c.to_string()).is_none() {
+                            return Err(Diagnostic::error(
+                                location,
+                                1,
+                                format!("invalid character `{}` in string", c),
+                            ));
+                        }
+                        text.push(*c);
+                        advance(&mut i, &mut location, 1);
+                    }
+                    _ => {
+                        return Err(Diagnostic::error(
+                            start,
+                            text.len() as u32 + 1,
+                            String::from("unterminated string"),
+                        ))
+                    }
+                }
+            }
+            advance(&mut i, &mut location, 1);
+            tokens.push(Token {
+                kind: TokenKind::StringConstant,
+                text,
+                location: start,
+            });
+        } else if SYMBOLS.contains(c) {
+            advance(&mut i, &mut location, 1);
+            tokens.push(Token {
+                kind: TokenKind::Symbol,
+                text: c.to_string(),
+                location: start,
+            });
+        } else if c.is_ascii_digit() {
+            let mut text = String::new();
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                text.push(chars[i]);
+                advance(&mut i, &mut location, 1);
+            }
+            if !text
+                .parse::<u32>()
+                .is_ok_and(|value| value <= code::MAX_CONSTANT)
+            {
+                return Err(Diagnostic::error(
+                    start,
+                    text.len() as u32,
+                    format!("integer `{}` is larger than 32767", text),
+                ));
+            }
+            tokens.push(Token {
+                kind: TokenKind::IntegerConstant,
+                text,
+                location: start,
+            });
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut text = String::new();
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                text.push(chars[i]);
+                advance(&mut i, &mut location, 1);
+            }
+            let kind = if KEYWORDS.contains(&text.as_str()) {
+                TokenKind::Keyword
+            } else {
+                TokenKind::Identifier
+            };
+            tokens.push(Token {
+                kind,
+                text,
+                location: start,
+            });
+        } else {
+            return Err(Diagnostic::error(
+                start,
+                1,
+                format!("unexpected character `{}`", c),
+            ));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(contents: &str) -> Vec<(TokenKind, String)> {
+        tokenize(contents)
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.kind, token.text))
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            vec![
+                (TokenKind::Keyword, String::from("let")),
+                (TokenKind::Identifier, String::from("x_1")),
+                (TokenKind::Symbol, String::from("=")),
+                (TokenKind::IntegerConstant, String::from("32767")),
+                (TokenKind::Symbol, String::from("+")),
+                (TokenKind::StringConstant, String::from("a // b")),
+                (TokenKind::Symbol, String::from(";")),
+            ],
+            kinds("/** doc */ let x_1 = 32767 + \"a // b\"; // done\n/* trailing\n*/")
+        );
+    }
+
+    #[test]
+    fn locations() {
+        let tokens = tokenize("class Main {\n  /* x\n */ field int x;").unwrap();
+        assert_eq!(Location { line: 1, column: 7 }, tokens[1].location);
+        assert_eq!(Location { line: 3, column: 5 }, tokens[3].location);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |contents| tokenize(contents).unwrap_err().to_string();
+        assert_eq!(
+            "error: unterminated string (line 1, column 5)",
+            error("let \"abc\nx")
+        );
+        assert_eq!(
+            "error: integer `32768` is larger than 32767 (line 1, column 1)",
+            error("32768")
+        );
+        assert_eq!(
+            "error: unexpected character `#` (line 2, column 3)",
+            error("x\n  #")
+        );
+        assert_eq!(
+            "error: unterminated comment (line 1, column 3)",
+            error("x /* y")
+        );
+    }
+
+    #[test]
+    fn xml() {
+        let tokens = tokenize("if (x < \"&\")").unwrap();
+        assert_eq!(
+            "<tokens>\n<keyword> if </keyword>\n<symbol> ( </symbol>\n\
+             <identifier> x </identifier>\n<symbol> &lt; </symbol>\n\
+             <stringConstant> &amp; </stringConstant>\n<symbol> ) </symbol>\n</tokens>\n",
+            tokens_xml(&tokens)
+        );
+    }
+}
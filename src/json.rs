@@ -0,0 +1,413 @@
+use std::fmt;
+
+// Minimal JSON value, objects keep the order their members were added in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object() -> Value {
+        Value::Object(vec![])
+    }
+
+    // adds a member to an object, ignored for any other value
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Value {
+        if let Value::Object(members) = &mut self {
+            members.push((String::from(key), value.into()));
+        }
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+// Numbers with a fraction or exponent are truncated, nothing this crate
+// reads needs more than integers.
+pub fn parse(text: &str) -> Result<Value, &'static str> {
+    let mut reader = Reader {
+        chars: text.chars().collect(),
+        position: 0,
+    };
+
+    let value = reader.value()?;
+    reader.skip_whitespace();
+    if reader.position < reader.chars.len() {
+        return Err("trailing characters after JSON value");
+    }
+
+    Ok(value)
+}
+
+struct Reader {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), &'static str> {
+        self.skip_whitespace();
+        if self.next() != Some(expected) {
+            return Err("unexpected character in JSON");
+        }
+        Ok(())
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, &'static str> {
+        for expected in keyword.chars() {
+            if self.next() != Some(expected) {
+                return Err("invalid JSON literal");
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, &'static str> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('n') => self.keyword("null", Value::Null),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err("unexpected character in JSON"),
+            None => Err("unexpected end of JSON"),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, &'static str> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                break;
+            }
+            self.position += 1;
+        }
+
+        let text: String = self.chars[start..self.position].iter().collect();
+        if let Ok(value) = text.parse::<i64>() {
+            return Ok(Value::Number(value));
+        }
+        match text.parse::<f64>() {
+            Ok(value) => Ok(Value::Number(value as i64)),
+            Err(_) => Err("invalid JSON number"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, &'static str> {
+        self.expect('"')?;
+        let mut result = String::new();
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(result),
+                Some('\\') => match self.next() {
+                    Some('"') => result.push('"'),
+                    Some('\\') => result.push('\\'),
+                    Some('/') => result.push('/'),
+                    Some('b') => result.push('\u{8}'),
+                    Some('f') => result.push('\u{c}'),
+                    Some('n') => result.push('\n'),
+                    Some('r') => result.push('\r'),
+                    Some('t') => result.push('\t'),
+                    Some('u') => {
+                        let mut code = self.hex4()?;
+                        // surrogate pair
+                        if (0xD800..0xDC00).contains(&code)
+                            && self.peek() == Some('\\')
+                            && self.chars.get(self.position + 1) == Some(&'u')
+                        {
+                            self.position += 2;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00));
+                        }
+                        result.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    _ => return Err("invalid escape in JSON string"),
+                },
+                Some(c) => result.push(c),
+                None => return Err("unterminated JSON string"),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, &'static str> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next().and_then(|c| c.to_digit(16));
+            match digit {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err("invalid unicode escape in JSON string"),
+            }
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Value, &'static str> {
+        self.expect('[')?;
+        let mut values = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err("expected `,` or `]` in JSON array"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, &'static str> {
+        self.expect('{')?;
+        let mut members = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => return Err("expected `,` or `}` in JSON object"),
+            }
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Number(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Value {
+        Value::Number(value as i64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Number(value as i64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(String::from(value))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Value {
+        Value::Array(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::String(value) => write_string(f, value),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_scalars() {
+        assert_eq!("null", Value::Null.to_string());
+        assert_eq!("true", Value::from(true).to_string());
+        assert_eq!("-42", Value::from(-42i64).to_string());
+    }
+
+    #[test]
+    fn write_string_escapes() {
+        let value = Value::from("a \"b\"\\\n\u{1}");
+        assert_eq!("\"a \\\"b\\\"\\\\\\n\\u0001\"", value.to_string());
+    }
+
+    #[test]
+    fn write_array() {
+        let value = Value::from(vec![Value::from(1u32), Value::from("x")]);
+        assert_eq!("[1,\"x\"]", value.to_string());
+    }
+
+    #[test]
+    fn write_object_in_order() {
+        let value = Value::object().with("b", 1u32).with("a", vec![]);
+        assert_eq!("{\"b\":1,\"a\":[]}", value.to_string());
+    }
+
+    #[test]
+    fn parse_scalars() {
+        assert_eq!(Ok(Value::Null), parse("null"));
+        assert_eq!(Ok(Value::Bool(false)), parse(" false "));
+        assert_eq!(Ok(Value::Number(-12)), parse("-12"));
+        assert_eq!(Ok(Value::Number(2)), parse("2.5"));
+    }
+
+    #[test]
+    fn parse_string_escapes() {
+        assert_eq!(
+            Ok(Value::from("a\"\\/\n\u{e9}\u{1f600}")),
+            parse("\"a\\\"\\\\\\/\\n\\u00e9\\ud83d\\ude00\"")
+        );
+    }
+
+    #[test]
+    fn parse_nested() {
+        let value = parse("{\"id\": 1, \"params\": {\"items\": [true, null, \"x\"]}}").unwrap();
+        assert_eq!(Some(1), value.get("id").and_then(|v| v.as_i64()));
+        let items = value.get("params").and_then(|v| v.get("items")).unwrap();
+        assert_eq!(3, items.as_array().unwrap().len());
+        assert_eq!(Some(true), items.as_array().unwrap()[0].as_bool());
+    }
+
+    #[test]
+    fn parse_roundtrip() {
+        let value = Value::object()
+            .with("text", "line\n\"quoted\"")
+            .with("list", vec![Value::Null, Value::from(3u32)]);
+        assert_eq!(Ok(value.clone()), parse(&value.to_string()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("{\"a\" 1}").is_err());
+        assert!(parse("\"open").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse("nul").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::code;
+use crate::diagnostic::Diagnostic;
+use crate::expression;
+use crate::json::Value;
+use crate::parser::Location;
+use crate::symbol_table::SymbolTable;
+
+// The assembled program as one JSON document for `--format json`, so that
+// tools do not have to read the .hack file and the report:
+//
+//     {"version":1,"file":"Max.asm","instructions":[...],
+//      "symbols":{"labels":[...],"variables":[...]},"diagnostics":[...]}
+//
+// `instructions` and `symbols` are null when the program did not assemble.
+pub fn document(file: &str, program: Option<(Value, Value)>, diagnostics: &[Diagnostic]) -> Value {
+    let (instructions, symbols) = program.unwrap_or((Value::Null, Value::Null));
+    let diagnostics: Vec<Value> = diagnostics.iter().map(diagnostic).collect();
+    Value::object()
+        .with("version", 1u32)
+        .with("file", file)
+        .with("instructions", instructions)
+        .with("symbols", symbols)
+        .with("diagnostics", diagnostics)
+}
+
+// A word of the program, decoded, with where it came from and the
+// addresses of the symbols in `expression`, the A-instruction it was
+// assembled from:
+//
+//     {"address":2,"word":60039,"binary":"1110101010000111","file":"Max.asm",
+//      "line":5,"column":1,"type":"C","dest":null,"comp":"0","jump":"JMP",
+//      "symbols":[]}
+pub fn instruction(
+    address: usize,
+    word: u16,
+    file: &str,
+    location: Location,
+    expression: Option<&str>,
+    resolve: &dyn Fn(&str) -> Option<u32>,
+) -> Value {
+    let instruction = Value::object()
+        .with("address", address)
+        .with("word", word as u32)
+        .with("binary", format!("{:016b}", word))
+        .with("file", file)
+        .with("line", location.line)
+        .with("column", location.column);
+
+    let instruction = if word & 0x8000 == 0 {
+        instruction.with("type", "A").with("value", word as u32)
+    } else {
+        let binary = format!("{:016b}", word);
+        // null rather than "null" when there is no destination or jump
+        let field = |table: &[(&'static str, &'static str)], bits: &str| match code::mnemonic(
+            table, bits,
+        ) {
+            Some("null") | None => Value::Null,
+            Some(mnemonic) => Value::from(mnemonic),
+        };
+        instruction
+            .with("type", "C")
+            .with("dest", field(&code::DEST, &binary[10..13]))
+            .with("comp", field(&code::COMP, &binary[3..10]))
+            .with("jump", field(&code::JUMP, &binary[13..16]))
+    };
+
+    let parsed = expression.and_then(|expression| expression::parse(expression).ok());
+    let symbols: Vec<Value> = match &parsed {
+        Some(parsed) => parsed
+            .symbols()
+            .iter()
+            .map(|(name, _)| {
+                let address = resolve(name).map(Value::from).unwrap_or(Value::Null);
+                Value::object().with("name", *name).with("address", address)
+            })
+            .collect(),
+        None => vec![],
+    };
+    instruction.with("symbols", symbols)
+}
+
+// The labels and variables, private ones with the file they belong to.
+// Predefined symbols are left out.
+pub fn symbols(symbol_table: &SymbolTable) -> Value {
+    let symbol = |name: &str, address: u32, file: Option<&str>| {
+        let symbol = Value::object().with("name", name).with("address", address);
+        let symbol = match file {
+            Some(file) => symbol.with("file", file),
+            None => symbol,
+        };
+        (address, String::from(name), symbol)
+    };
+
+    let mut labels = vec![];
+    let mut variables = vec![];
+    for label in &symbol_table.labels {
+        if let Some(address) = symbol_table.get_address(label) {
+            labels.push(symbol(label, address, None));
+        }
+    }
+    for variable in &symbol_table.variables {
+        if let Some(address) = symbol_table.get_address(variable) {
+            variables.push(symbol(variable, address, None));
+        }
+    }
+    for locals in &symbol_table.locals {
+        for (name, address) in &locals.symbols {
+            let entry = symbol(name, *address, Some(&locals.file));
+            if locals.variables.contains(name) {
+                variables.push(entry);
+            } else {
+                labels.push(entry);
+            }
+        }
+    }
+
+    // by address then name, as the locals are kept in a map
+    let sorted = |mut symbols: Vec<(u32, String, Value)>| {
+        symbols.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        symbols
+            .into_iter()
+            .map(|(_, _, symbol)| symbol)
+            .collect::<Vec<Value>>()
+    };
+    Value::object()
+        .with("labels", sorted(labels))
+        .with("variables", sorted(variables))
+}
+
+fn diagnostic(diagnostic: &Diagnostic) -> Value {
+    let severity = diagnostic.severity.name();
+    let file = diagnostic
+        .file
+        .as_deref()
+        .map(Value::from)
+        .unwrap_or(Value::Null);
+    let notes: Vec<Value> = diagnostic.notes.iter().map(self::diagnostic).collect();
+    Value::object()
+        .with("severity", severity)
+        .with("message", diagnostic.message.as_str())
+        .with("file", file)
+        .with("line", diagnostic.location.line)
+        .with("column", diagnostic.location.column)
+        .with("length", diagnostic.length)
+        .with("notes", notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions() {
+        let at = Location { line: 3, column: 1 };
+        assert_eq!(
+            "{\"address\":0,\"word\":16,\"binary\":\"0000000000010000\",\"file\":\"Max.asm\",\"line\":3,\"column\":1,\"type\":\"A\",\"value\":16,\"symbols\":[{\"name\":\"i\",\"address\":16}]}",
+            instruction(0, 16, "Max.asm", at, Some("i"), &|_| Some(16)).to_string()
+        );
+        let none = |_: &str| None;
+        let jump = instruction(1, 0xea87, "Max.asm", at, None, &none);
+        assert_eq!(Some(&Value::Null), jump.get("dest"));
+        assert_eq!(Some("0"), jump.get("comp").and_then(Value::as_str));
+        assert_eq!(Some("JMP"), jump.get("jump").and_then(Value::as_str));
+        let store = instruction(2, 0xe308, "Max.asm", at, None, &none);
+        assert_eq!(Some("M"), store.get("dest").and_then(Value::as_str));
+        assert_eq!(Some("D"), store.get("comp").and_then(Value::as_str));
+    }
+
+    #[test]
+    fn symbols_and_diagnostics() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.add_predefined_symbols();
+        symbol_table.add_label(String::from("LOOP"), 4).unwrap();
+        symbol_table.add_variable(String::from("i")).unwrap();
+        assert_eq!(
+            "{\"labels\":[{\"name\":\"LOOP\",\"address\":4}],\"variables\":[{\"name\":\"i\",\"address\":16}]}",
+            symbols(&symbol_table).to_string()
+        );
+
+        let error = Diagnostic::error(Location { line: 2, column: 3 }, 1, String::from("bad"));
+        assert_eq!(
+            "{\"version\":1,\"file\":\"Max.asm\",\"instructions\":null,\"symbols\":null,\"diagnostics\":[{\"severity\":\"error\",\"message\":\"bad\",\"file\":\"Max.asm\",\"line\":2,\"column\":3,\"length\":1,\"notes\":[]}]}",
+            document("Max.asm", None, &[error.in_file("Max.asm")]).to_string()
+        );
+
+        let twice = Diagnostic::error(Location { line: 2, column: 2 }, 1, String::from("twice"))
+            .with_note(Location { line: 1, column: 2 }, 1, String::from("first"));
+        let notes = document("Max.asm", None, &[twice])
+            .get("diagnostics")
+            .unwrap()
+            .as_array()
+            .unwrap()[0]
+            .get("notes")
+            .unwrap()
+            .to_string();
+        assert!(notes.contains("\"severity\":\"note\""));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::diagnostic::Diagnostic;
+use crate::jack_tokenizer::escape;
+
+// A test case of a JUnit XML report, for `--format junit`: a file that
+// was assembled, or the test script that was run on the program.
+pub struct Case {
+    pub class: &'static str,
+    pub name: String,
+    // what went wrong, and the details, one per line
+    pub failure: Option<(String, String)>,
+    pub output: String,
+}
+
+impl Case {
+    // failed when the file has errors, with the warnings as its output
+    pub fn assembled(file: &str, diagnostics: &[&Diagnostic]) -> Case {
+        let lines = |errors: bool| {
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.is_error() == errors)
+                .map(|diagnostic| diagnostic.to_gcc(file) + "\n")
+                .collect::<String>()
+        };
+        let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+        Case {
+            class: "assemble",
+            name: String::from(file),
+            failure: (errors > 0).then(|| (format!("{} errors", errors), lines(true))),
+            output: lines(false),
+        }
+    }
+}
+
+//     <?xml version="1.0" encoding="UTF-8"?>
+//     <testsuite name="Max.asm" tests="2" failures="1">
+//       <testcase classname="assemble" name="Max.asm"/>
+//       <testcase classname="test" name="Max.tst">
+//         <failure message="Comparison failure at line 3"></failure>
+//       </testcase>
+//     </testsuite>
+pub fn document(suite: &str, cases: &[Case]) -> String {
+    let failures = cases.iter().filter(|case| case.failure.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+        escape(suite),
+        cases.len(),
+        failures
+    ));
+
+    for case in cases {
+        let start = format!(
+            "  <testcase classname=\"{}\" name=\"{}\"",
+            case.class,
+            escape(&case.name)
+        );
+        if case.failure.is_none() && case.output.is_empty() {
+            xml.push_str(&start);
+            xml.push_str("/>\n");
+            continue;
+        }
+        xml.push_str(&start);
+        xml.push_str(">\n");
+        if let Some((message, details)) = &case.failure {
+            xml.push_str(&format!(
+                "    <failure message=\"{}\">{}</failure>\n",
+                escape(message),
+                escape(details)
+            ));
+        }
+        if !case.output.is_empty() {
+            xml.push_str(&format!(
+                "    <system-out>{}</system-out>\n",
+                escape(&case.output)
+            ));
+        }
+        xml.push_str("  </testcase>\n");
+    }
+
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Location;
+
+    #[test]
+    fn cases() {
+        let error = Diagnostic::error(
+            Location { line: 2, column: 3 },
+            1,
+            String::from("invalid comp `X`"),
+        );
+        let cases = [
+            Case::assembled("Max.asm", &[&error]),
+            Case {
+                class: "test",
+                name: String::from("Max.tst"),
+                failure: None,
+                output: String::new(),
+            },
+        ];
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"Max.asm\" tests=\"2\" failures=\"1\">\n  \
+             <testcase classname=\"assemble\" name=\"Max.asm\">\n    \
+             <failure message=\"1 errors\">Max.asm:2:3: error: invalid comp `X`\n</failure>\n  \
+             </testcase>\n  \
+             <testcase classname=\"test\" name=\"Max.tst\"/>\n\
+             </testsuite>\n",
+            document("Max.asm", &cases)
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::code;
+
+// Scripted keyboard input, every event sets the key held down from its
+// cycle on until the next one:
+//
+//     # cycle key
+//     1000 A
+//     2000 newline
+//     3000 release
+pub struct Timeline {
+    events: Vec<(u64, u16)>,
+    next: usize,
+}
+
+impl Timeline {
+    pub fn parse(contents: &str) -> Result<Timeline, String> {
+        let mut events = vec![];
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {}", i + 1, message);
+
+            let (cycle, key) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error("expected `cycle key`"))?;
+            let cycle = cycle
+                .parse::<u64>()
+                .map_err(|_| error(&format!("invalid cycle `{}`", cycle)))?;
+            let key = key.trim();
+            let code = match key {
+                "release" | "none" => 0,
+                _ => match key.parse::<u16>() {
+                    // more than one digit is a key code rather than a digit key
+                    Ok(code) if key.len() > 1 => code,
+                    _ => code::key(key).ok_or_else(|| error(&format!("unknown key `{}`", key)))?,
+                },
+            };
+
+            events.push((cycle, code));
+        }
+
+        // stable, events at the same cycle keep their order and the last wins
+        events.sort_by_key(|(cycle, _)| *cycle);
+
+        Ok(Timeline { events, next: 0 })
+    }
+
+    // The key that changes at `cycle`, if any.
+    pub fn key_at(&mut self, cycle: u64) -> Option<u16> {
+        let mut key = None;
+        while let Some((at, code)) = self.events.get(self.next) {
+            if *at > cycle {
+                break;
+            }
+            key = Some(*code);
+            self.next += 1;
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keys() {
+        let mut timeline =
+            Timeline::parse("# cycle key\n10 A\n\n20 newline\n30 release\n40 7\n50 65\n").unwrap();
+        assert_eq!(
+            vec![(10, 65), (20, 128), (30, 0), (40, 55), (50, 65)],
+            timeline.events
+        );
+        assert_eq!(None, timeline.key_at(9));
+    }
+
+    #[test]
+    fn key_at_cycles() {
+        let mut timeline = Timeline::parse("20 B\n10 A\n10 up\n").unwrap();
+        assert_eq!(None, timeline.key_at(0));
+        assert_eq!(Some(131), timeline.key_at(10));
+        assert_eq!(None, timeline.key_at(11));
+        // a skipped event still applies
+        assert_eq!(Some(66), timeline.key_at(100));
+        assert_eq!(None, timeline.key_at(200));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Err(String::from("line 1: expected `cycle key`")),
+            Timeline::parse("10").map(|_| ())
+        );
+        assert_eq!(
+            Err(String::from("line 2: invalid cycle `x`")),
+            Timeline::parse("1 A\nx A").map(|_| ())
+        );
+        assert_eq!(
+            Err(String::from("line 1: unknown key `enter`")),
+            Timeline::parse("1 enter").map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,930 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::archive;
+use crate::assembler::{self, Output};
+use crate::cache::{self, Cache};
+use crate::code::MAX_CONSTANT;
+use crate::config::{Config, Format, LinkConfig};
+use crate::data::{self, Directive};
+use crate::diagnostic::Diagnostic;
+use crate::expression;
+use crate::gc::{self, Removed};
+use crate::json::Value;
+use crate::json_output;
+use crate::object::{self, Object, Word};
+use crate::parser::Location;
+use crate::symbol_table::{Layout, SymbolTable};
+use crate::vm;
+
+// A file of the program with what it assembled to
+pub struct Module {
+    pub file: String,
+    pub object: Object,
+}
+
+pub struct Program {
+    pub rom: Vec<u16>,
+    pub symbol_table: SymbolTable,
+    pub ram: Vec<(u16, u16)>,
+}
+
+fn offset(location: Location, columns: u32) -> Location {
+    Location {
+        line: location.line,
+        column: location.column + columns,
+    }
+}
+
+// with a note at the first definition, in the file that has it
+fn defined_twice(
+    location: Location,
+    length: u32,
+    message: String,
+    first: Option<&(&str, Location)>,
+) -> Diagnostic {
+    let mut diagnostic = Diagnostic::error(location, length, message);
+    if let Some((file, first)) = first {
+        diagnostic = diagnostic.with_note(*first, length, String::from("first defined here"));
+        diagnostic.notes[0].file = Some(String::from(*file));
+    }
+    diagnostic
+}
+
+// Puts the objects one after the other in ROM, in order, and resolves
+// their imports against the labels and variables the others export.
+// Variables are private to the object that uses them, unless an object
+// declares them `.global` or pins them with `.var`.
+pub fn link(
+    modules: &[Module],
+    layout: &Layout,
+    rom_size: u32,
+) -> Result<Program, Vec<Diagnostic>> {
+    let mut symbol_table = SymbolTable::with_layout(layout);
+    symbol_table.add_predefined_symbols();
+    let mut diagnostics = vec![];
+
+    // where the global symbols are defined, to point at the first
+    // definition of a symbol defined twice
+    let mut defined: HashMap<&str, (&str, Location)> = HashMap::new();
+
+    let size: u32 = modules.iter().map(|m| m.object.code.len() as u32).sum();
+    let mut base = 0;
+
+    for module in modules {
+        let file = symbol_table.add_file(&module.file);
+        let mut error = |diagnostic: Diagnostic| diagnostics.push(diagnostic.in_file(&module.file));
+
+        for (label, at, location) in &module.object.labels {
+            let address = base + at;
+            let location = offset(*location, 1);
+            let length = label.len() as u32;
+
+            if address >= rom_size {
+                error(Diagnostic::error(
+                    location,
+                    length,
+                    format!(
+                        "label `{}` is at ROM[{}], past the end of the {} word ROM",
+                        label, address, rom_size
+                    ),
+                ));
+            }
+            if object::is_local(label) {
+                symbol_table.add_local(file, label.clone(), address);
+            } else if let Err(message) = symbol_table.add_label(label.clone(), address) {
+                error(defined_twice(
+                    location,
+                    length,
+                    message,
+                    defined.get(label.as_str()),
+                ));
+            } else {
+                defined.insert(label, (&module.file, location));
+            }
+        }
+
+        // the first word that does not fit
+        if base <= rom_size && rom_size < base + module.object.code.len() as u32 {
+            let (_, location) = &module.object.code[(rom_size - base) as usize];
+            error(Diagnostic::error(
+                *location,
+                1,
+                format!(
+                    "the program needs {} words but the ROM holds {}",
+                    size, rom_size
+                ),
+            ));
+        }
+
+        base += module.object.code.len() as u32;
+    }
+
+    // pinned variables first, so that the shared ones go around them
+    let mut shared = vec![];
+    let mut entries = vec![];
+    for module in modules {
+        for (directive, location) in &module.object.directives {
+            let (name, address) = match directive {
+                Directive::Var(name, address) => (name, address),
+                Directive::Global(name) => {
+                    shared.push((name, &module.file, *location));
+                    continue;
+                }
+                Directive::Entry(label) => {
+                    entries.push((label, module.file.as_str(), *location));
+                    continue;
+                }
+                _ => continue,
+            };
+            let pinned = match assembler::value(address, &symbol_table) {
+                Ok(address) if address >= 0 => {
+                    symbol_table.pin_variable(name.clone(), address as u32)
+                }
+                Ok(address) => Err(format!(
+                    "RAM[{}] is outside of the variable memory",
+                    address
+                )),
+                Err(error) => Err(error.message),
+            };
+            match pinned {
+                Ok(()) => {
+                    defined.insert(name, (&module.file, *location));
+                }
+                Err(message) => {
+                    diagnostics.push(Diagnostic::error(*location, 4, message).in_file(&module.file))
+                }
+            }
+        }
+    }
+
+    // one entry point, at a label, see start()
+    let mut entry: Option<(&str, Location)> = None;
+    for (label, file, location) in entries {
+        let length = ".entry".len() as u32;
+        if let Some((first, _)) = entry {
+            let message = format!("the entry point is also set by {}", first);
+            let diagnostic = defined_twice(location, length, message, entry.as_ref());
+            diagnostics.push(diagnostic.in_file(file));
+        } else if !symbol_table.labels.contains(label) {
+            let message = format!("`{}` is not a label of the program", label);
+            diagnostics.push(Diagnostic::error(location, length, message).in_file(file));
+        }
+        entry = entry.or(Some((file, location)));
+    }
+
+    if layout.alphabetical {
+        shared.sort_by(|a, b| a.0.cmp(b.0));
+    }
+    for (name, file, location) in shared {
+        let length = ".global".len() as u32;
+        match defined.get(name.as_str()) {
+            // a label or pinned variable of the same object is exported
+            // already
+            Some((owner, _)) if *owner == file.as_str() => {}
+            first @ Some((owner, _)) => {
+                let message = format!("`{}` is also exported by {}", name, owner);
+                let diagnostic = defined_twice(location, length, message, first);
+                diagnostics.push(diagnostic.in_file(file));
+            }
+            None => match symbol_table.add_variable(name.clone()) {
+                Ok(_) => {
+                    defined.insert(name, (file, location));
+                }
+                Err(message) => {
+                    diagnostics.push(Diagnostic::error(location, length, message).in_file(file));
+                }
+            },
+        }
+    }
+
+    // the imports nothing exports
+    'files: for (file, module) in modules.iter().enumerate() {
+        let mut variables: Vec<&(String, Location)> = module
+            .object
+            .imports
+            .iter()
+            .filter(|(name, _)| {
+                // local labels that do not exist are reported below
+                symbol_table.get_local_address(file, name).is_none() && !object::is_local(name)
+            })
+            .collect();
+        if layout.alphabetical {
+            variables.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        for (name, location) in variables {
+            if let Err(message) = symbol_table.add_local_variable(file, name.clone()) {
+                // the variables after it do not fit either
+                let diagnostic = Diagnostic::error(*location, name.len() as u32, message);
+                diagnostics.push(diagnostic.in_file(&module.file));
+                break 'files;
+            }
+        }
+    }
+
+    let mut rom = vec![];
+    let mut ram = vec![];
+    for (file, module) in modules.iter().enumerate() {
+        let resolve = |name: &str| {
+            symbol_table
+                .get_local_address(file, name)
+                .map(|address| address as i64)
+        };
+
+        for (word, location) in &module.object.code {
+            let symbol = match word {
+                Word::Fixed(word) => {
+                    rom.push(*word);
+                    continue;
+                }
+                Word::Symbol(symbol) => symbol,
+            };
+            let location = offset(*location, 1);
+            let length = symbol.len() as u32;
+
+            let diagnostic = match expression::parse(symbol).and_then(|e| e.evaluate(&resolve)) {
+                Ok(value) if (0..=MAX_CONSTANT as i64).contains(&value) => {
+                    rom.push(value as u16);
+                    continue;
+                }
+                Ok(value) => Diagnostic::error(
+                    location,
+                    length,
+                    format!(
+                        "`{}` evaluates to {}, which does not fit in 15 bits",
+                        symbol, value
+                    ),
+                ),
+                Err(error) => {
+                    Diagnostic::error(offset(location, error.offset), error.length, error.message)
+                }
+            };
+            diagnostics.push(diagnostic.in_file(&module.file));
+        }
+
+        match data::image(&module.object.directives, &symbol_table) {
+            Ok(image) => ram.extend(image),
+            Err(errors) => diagnostics.extend(errors.into_iter().map(|d| d.in_file(&module.file))),
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    ram.sort();
+    Ok(Program {
+        rom,
+        symbol_table,
+        ram,
+    })
+}
+
+// Writes Foo.hack, and Foo.ram when there are data directives. Returns the
+// report to print, the RAM map when asked for and the size summary.
+fn write(
+    program: &Program,
+    hack: &Path,
+    ram_map: bool,
+    rom_size: u32,
+) -> Result<String, Box<dyn Error>> {
+    let mut report = String::new();
+    if ram_map {
+        report.push_str(&program.symbol_table.ram_map());
+    }
+    if !program.ram.is_empty() {
+        fs::write(hack.with_extension("ram"), data::to_ram(&program.ram))?;
+    }
+
+    let mut file = BufWriter::new(fs::File::create(hack)?);
+    for word in &program.rom {
+        writeln!(file, "{:016b}", word)?;
+    }
+    file.flush()?;
+
+    report.push_str(&format!(
+        "{}: {} of {} words, {} labels, {} variables\n",
+        hack.display(),
+        program.rom.len(),
+        rom_size,
+        program.symbol_table.labels.len(),
+        program.symbol_table.variable_count()
+    ));
+    Ok(report)
+}
+
+// The code put before the first module when the program has an entry
+// point: a jump to it, or a bootstrap that sets SP to 256 and calls it,
+// Sys.init unless told otherwise. The entry point is given on the command
+// line or by a `.entry` directive, which link() checks.
+fn start(
+    modules: &[Module],
+    entry: Option<&str>,
+    bootstrap: bool,
+) -> Result<Option<Module>, Box<dyn Error>> {
+    let directive = modules
+        .iter()
+        .flat_map(|module| &module.object.directives)
+        .find_map(|(directive, _)| match directive {
+            Directive::Entry(label) => Some(label.as_str()),
+            _ => None,
+        });
+    let label = match entry.or(directive) {
+        Some(label) => label,
+        None if bootstrap => "Sys.init",
+        None => return Ok(None),
+    };
+
+    let defined = modules.iter().any(|module| {
+        module
+            .object
+            .labels
+            .iter()
+            .any(|(name, _, _)| name == label && !object::is_local(name))
+    });
+    if !defined && (entry.is_some() || directive.is_none()) {
+        return Err(format!("the entry point `{}` is not a label of the program", label).into());
+    }
+
+    let contents = match bootstrap {
+        true => vm::bootstrap(label),
+        false => format!("@{}\n0;JMP\n", label),
+    };
+    let object = object::generated(&contents).ok_or("invalid entry point")?;
+    Ok(Some(Module {
+        file: String::from("Start"),
+        object,
+    }))
+}
+
+// Foo.map, where the linker put everything:
+//
+//     ROM
+//         0  Main.asm  (12 words)
+//        12  Std.lib(Math.obj)  (40 words)
+//     Labels
+//        12  MULT  (Std.lib(Math.obj))
+//     RAM map
+//        16  i  (Main.asm)
+//     Removed
+//       DIV  (Std.lib(Math.obj), 21 words)
+fn map(modules: &[Module], program: &Program, removed: &[Removed]) -> String {
+    let mut map = String::from("ROM\n");
+    let mut labels = vec![];
+    let mut base = 0;
+    for module in modules {
+        let words = module.object.code.len() as u32;
+        map.push_str(&format!(
+            "{:>5}  {}  ({} words)\n",
+            base, module.file, words
+        ));
+        for (label, offset, _) in &module.object.labels {
+            labels.push((base + offset, label, &module.file));
+        }
+        base += words;
+    }
+
+    map.push_str("Labels\n");
+    labels.sort();
+    for (address, label, file) in labels {
+        map.push_str(&format!("{:>5}  {}  ({})\n", address, label, file));
+    }
+    map.push_str(&program.symbol_table.ram_map());
+
+    if !removed.is_empty() {
+        map.push_str("Removed\n");
+        for routine in removed {
+            map.push_str(&format!(
+                "  {}  ({}, {} words)\n",
+                routine.label, routine.file, routine.words
+            ));
+        }
+    }
+    map
+}
+
+// The map when asked for, and a line for the routines removed
+fn write_map(
+    map: bool,
+    gc_sections: bool,
+    modules: &[Module],
+    program: &Program,
+    removed: &[Removed],
+    hack: &Path,
+) -> Result<String, Box<dyn Error>> {
+    if map {
+        fs::write(
+            hack.with_extension("map"),
+            self::map(modules, program, removed),
+        )?;
+    }
+    if !gc_sections {
+        return Ok(String::new());
+    }
+    Ok(removed_summary(removed))
+}
+
+// removed 1 unreferenced routine, 4 words
+fn removed_summary(removed: &[Removed]) -> String {
+    let words: usize = removed.iter().map(|routine| routine.words).sum();
+    format!(
+        "removed {} unreferenced {}, {} {}\n",
+        removed.len(),
+        if removed.len() == 1 {
+            "routine"
+        } else {
+            "routines"
+        },
+        words,
+        if words == 1 { "word" } else { "words" }
+    )
+}
+
+// Writes Foo.obj for every file, to be linked later.
+pub fn compile(
+    config: &Config,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Output, Box<dyn Error>> {
+    let mut report = String::new();
+
+    for file in &config.files {
+        let contents = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+        let mut found = vec![];
+        let object = object::compile(&contents, config, &mut found);
+        diagnostics.extend(found.into_iter().map(|d| d.in_file(file)));
+
+        if let Some(object) = object {
+            let path = assembler::output_filename(file, "obj");
+            fs::write(&path, object.to_string())?;
+            let exports = object
+                .labels
+                .iter()
+                .filter(|(label, _, _)| !object::is_local(label));
+            report.push_str(&format!(
+                "{}: {} words, {} exports, {} imports\n",
+                path.display(),
+                object.code.len(),
+                exports.count(),
+                object.imports.len()
+            ));
+        }
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        return Err(format!(
+            "could not assemble {} due to {} errors",
+            config.filename, errors
+        )
+        .into());
+    }
+    Ok(Output {
+        report,
+        program: None,
+    })
+}
+
+// The members of the archives, as Std.lib(Math.obj)
+fn libraries(files: &[String]) -> Result<Vec<Module>, Box<dyn Error>> {
+    let mut modules = vec![];
+    for file in files {
+        let contents = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+        let archive = archive::read(&contents)
+            .ok_or_else(|| format!("{} is not an archive written by this assembler", file))?;
+        for (name, object) in archive.members {
+            modules.push(Module {
+                file: format!("{}({})", file, name),
+                object,
+            });
+        }
+    }
+    Ok(modules)
+}
+
+// Adds the library members that export a symbol the program imports and
+// does not define itself, then the members those need, and so on. They
+// come after the program, in the order of the archives. Returns how many
+// members were added.
+pub fn select(modules: &mut Vec<Module>, libraries: Vec<Module>) -> usize {
+    let mut exported: HashSet<String> = HashSet::new();
+    let mut imported: HashSet<String> = HashSet::new();
+    for module in modules.iter() {
+        add_symbols(&module.object, &mut exported, &mut imported);
+    }
+
+    let mut selected = vec![false; libraries.len()];
+    loop {
+        let needed = |library: &Module| {
+            library
+                .object
+                .exports()
+                .iter()
+                .any(|name| !exported.contains(*name) && imported.contains(*name))
+        };
+        match (0..libraries.len()).find(|i| !selected[*i] && needed(&libraries[*i])) {
+            Some(i) => {
+                selected[i] = true;
+                add_symbols(&libraries[i].object, &mut exported, &mut imported);
+            }
+            None => break,
+        }
+    }
+
+    let count = modules.len();
+    modules.extend(
+        libraries
+            .into_iter()
+            .zip(selected)
+            .filter(|(_, selected)| *selected)
+            .map(|(library, _)| library),
+    );
+    modules.len() - count
+}
+
+fn add_symbols(object: &Object, exported: &mut HashSet<String>, imported: &mut HashSet<String>) {
+    exported.extend(object.exports().into_iter().map(String::from));
+    imported.extend(
+        object
+            .imports
+            .iter()
+            .map(|(name, _)| name.clone())
+            .filter(|name| !object::is_local(name)),
+    );
+}
+
+// assembler link Main.obj Mult.obj Std.lib
+pub fn run(config: LinkConfig) -> Result<(), Box<dyn Error>> {
+    let mut modules = vec![];
+    for file in &config.files {
+        let contents = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+        let object = object::read(&contents)
+            .ok_or_else(|| format!("{} is not an object written by this assembler", file))?;
+        modules.push(Module {
+            file: file.clone(),
+            object,
+        });
+    }
+    let libraries = libraries(&config.libraries)?;
+    let members = libraries.len();
+    let selected = select(&mut modules, libraries);
+    if let Some(start) = start(&modules, config.entry.as_deref(), config.bootstrap)? {
+        modules.insert(0, start);
+    }
+    let removed = match config.gc_sections {
+        true => gc::collect(&mut modules),
+        false => vec![],
+    };
+
+    let program = link(&modules, &config.layout, config.rom_size).map_err(|errors| {
+        for error in &errors {
+            println!("{}", error);
+        }
+        format!(
+            "could not link {} due to {} errors",
+            config.output,
+            errors.len()
+        )
+    })?;
+
+    let hack = Path::new(&config.output);
+    let report = write(&program, hack, config.ram_map, config.rom_size)?;
+    print!("{}", report);
+    print!(
+        "{}",
+        write_map(
+            config.map,
+            config.gc_sections,
+            &modules,
+            &program,
+            &removed,
+            hack
+        )?
+    );
+    if members > 0 {
+        println!("{} of {} library members linked", selected, members);
+    }
+    Ok(())
+}
+
+// Assembles a program of several .asm files into a .hack file named after
+// the first one. Files are taken from the cache when they have not changed
+// since they were last assembled.
+pub fn build(config: &Config, diagnostics: &mut Vec<Diagnostic>) -> Result<Output, Box<dyn Error>> {
+    let cache = Cache::new(&config.filename);
+    let mut modules = vec![];
+    let mut cached = 0;
+
+    for file in &config.files {
+        let contents = fs::read_to_string(file).map_err(|error| format!("{}: {}", file, error))?;
+        // the options change what a file assembles to
+        let key = cache::hash(
+            format!(
+                "{}{}\n{}",
+                config.optimize as u8, config.warn_case as u8, contents
+            )
+            .as_bytes(),
+        );
+
+        let object = match cache.get(key) {
+            Some(object) => {
+                cached += 1;
+                Some(object)
+            }
+            None => {
+                let mut found = vec![];
+                let object = object::compile(&contents, config, &mut found);
+                // files with warnings are assembled again, to show them
+                if let (Some(object), true) = (&object, found.is_empty()) {
+                    let _ = cache.put(key, object);
+                }
+                diagnostics.extend(found.into_iter().map(|d| d.in_file(file)));
+                object
+            }
+        };
+        if let Some(object) = object {
+            modules.push(Module {
+                file: file.clone(),
+                object,
+            });
+        }
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        return Err(format!(
+            "could not assemble {} due to {} errors",
+            config.filename, errors
+        )
+        .into());
+    }
+    let libraries = libraries(&config.libraries)?;
+    let members = libraries.len();
+    let selected = select(&mut modules, libraries);
+    if let Some(start) = start(&modules, config.entry.as_deref(), config.bootstrap)? {
+        modules.insert(0, start);
+    }
+    let removed = match config.gc_sections {
+        true => gc::collect(&mut modules),
+        false => vec![],
+    };
+
+    let program = match link(&modules, &config.layout, config.rom_size) {
+        Ok(program) => program,
+        Err(errors) => {
+            let count = errors.len();
+            diagnostics.extend(errors);
+            return Err(
+                format!("could not link {} due to {} errors", config.filename, count).into(),
+            );
+        }
+    };
+
+    let hack = assembler::output_filename(&config.filename, "hack");
+    let mut report = write(&program, &hack, config.ram_map, config.rom_size)?;
+    report.push_str(&write_map(
+        config.map,
+        config.gc_sections,
+        &modules,
+        &program,
+        &removed,
+        &hack,
+    )?);
+    if cached > 0 {
+        report.push_str(&format!(
+            "{} of {} files were unchanged\n",
+            cached,
+            config.files.len()
+        ));
+    }
+    if members > 0 {
+        report.push_str(&format!(
+            "{} of {} library members linked\n",
+            selected, members
+        ));
+    }
+
+    let program = (config.format == Format::Json).then(|| {
+        (
+            Value::from(instructions(&modules, &program)),
+            json_output::symbols(&program.symbol_table),
+        )
+    });
+    Ok(Output { report, program })
+}
+
+// every word of the program for --format json, see json_output.rs
+fn instructions(modules: &[Module], program: &Program) -> Vec<Value> {
+    let mut instructions = vec![];
+    for (file, module) in modules.iter().enumerate() {
+        let resolve = |name: &str| program.symbol_table.get_local_address(file, name);
+        for (word, location) in &module.object.code {
+            let address = instructions.len();
+            let expression = match word {
+                Word::Symbol(symbol) => Some(symbol.as_str()),
+                Word::Fixed(_) => None,
+            };
+            instructions.push(json_output::instruction(
+                address,
+                program.rom[address],
+                &module.file,
+                *location,
+                expression,
+                &resolve,
+            ));
+        }
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modules(files: &[(&str, &str)]) -> Vec<Module> {
+        let config = Config::new(&[String::from("assembler"), String::from("Main.asm")]).unwrap();
+        files
+            .iter()
+            .map(|(file, contents)| Module {
+                file: String::from(*file),
+                object: object::compile(contents, &config, &mut vec![]).unwrap(),
+            })
+            .collect()
+    }
+
+    fn linked(files: &[(&str, &str)]) -> Result<Vec<u16>, Vec<String>> {
+        link(&modules(files), &Layout::new(), 32768)
+            .map(|program| program.rom)
+            .map_err(|diagnostics| diagnostics.iter().map(|d| d.to_string()).collect())
+    }
+
+    #[test]
+    fn labels_across_files() {
+        assert_eq!(
+            Ok(vec![4, 0xea87, 16, 17, 8, 0xea87, 6, 0xea87, 18, 19]),
+            linked(&[
+                ("Main.asm", "@MULT\n0;JMP\n@y\n@x\n"),
+                (
+                    "Mult.asm",
+                    "(MULT)\n@+\n0;JMP\n(.loop)\n@.loop+0\n0;JMP\n()\n@x\n@y\n"
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn removed_routines() {
+        let routine = |words| Removed {
+            file: String::from("Main.asm"),
+            label: String::from("UNUSED"),
+            words,
+        };
+        assert_eq!(
+            "removed 1 unreferenced routine, 4 words\n",
+            removed_summary(&[routine(4)])
+        );
+        assert_eq!(
+            "removed 2 unreferenced routines, 3 words\n",
+            removed_summary(&[routine(1), routine(2)])
+        );
+    }
+
+    #[test]
+    fn halt_in_two_files() {
+        assert_eq!(
+            Ok(vec![0xea90, 1, 0xea87, 0xea90, 4, 0xea87]),
+            linked(&[("A.asm", "D=0\nHALT\n"), ("B.asm", "D=0\nHALT\n")])
+        );
+    }
+
+    #[test]
+    fn shared_variables() {
+        assert_eq!(
+            Ok(vec![16, 17, 18, 16, 100]),
+            linked(&[
+                ("A.asm", ".global count\n@count\n@tmp\n"),
+                ("B.asm", ".var pinned 100\n@tmp\n@count\n@pinned\n"),
+            ])
+        );
+        assert_eq!(
+            Err(vec![String::from(
+                "B.asm: error: `LOOP` is also exported by A.asm (line 1, column 1)\n  note: first defined here (A.asm, line 1, column 2)"
+            )]),
+            linked(&[("A.asm", "(LOOP)\n@LOOP\n"), ("B.asm", ".global LOOP\n@1\n")])
+        );
+    }
+
+    #[test]
+    fn select_library_members() {
+        let mut program = modules(&[("Main.asm", "@MULT\n0;JMP\n@count\n")]);
+        let library = modules(&[
+            ("Std.lib(Div.obj)", "(DIV)\n@MULT\n"),
+            ("Std.lib(Mult.obj)", "(MULT)\n@ADD\n"),
+            ("Std.lib(Add.obj)", "(ADD)\n@count\n"),
+            ("Std.lib(Count.obj)", ".global count\n@count\n"),
+            ("Std.lib(Screen.obj)", "(CLEAR)\n@SCREEN\n"),
+        ]);
+
+        assert_eq!(3, select(&mut program, library));
+        let files: Vec<&str> = program.iter().map(|m| m.file.as_str()).collect();
+        assert_eq!(
+            vec![
+                "Main.asm",
+                "Std.lib(Mult.obj)",
+                "Std.lib(Add.obj)",
+                "Std.lib(Count.obj)"
+            ],
+            files
+        );
+    }
+
+    #[test]
+    fn local_labels_stay_in_their_file() {
+        assert_eq!(
+            Ok(vec![0, 0xea87, 2, 0xea87]),
+            linked(&[("A.asm", "()\n@-\n0;JMP\n"), ("B.asm", "()\n@-\n0;JMP\n")])
+        );
+    }
+
+    #[test]
+    fn entry_point() {
+        let mut program = modules(&[
+            ("Lib.asm", "(HELPER)\nD=1\n0;JMP\n"),
+            ("Main.asm", ".entry MAIN\n(MAIN)\n@HELPER\n0;JMP\n"),
+        ]);
+        let jump = start(&program, None, false).unwrap().unwrap();
+        program.insert(0, jump);
+        let rom = link(&program, &Layout::new(), 32768).ok().unwrap().rom;
+        assert_eq!(vec![4, 0xea87, 0xefd0, 0xea87, 2, 0xea87], rom);
+
+        // the bootstrap's call returns to the first module
+        let bootstrap = start(&program, Some("HELPER"), true).unwrap().unwrap();
+        let code = &bootstrap.object.code;
+        assert_eq!(Word::Symbol(String::from("HELPER")), code[code.len() - 2].0);
+        assert_eq!(
+            Some(String::from(
+                "the entry point `Sys.init` is not a label of the program"
+            )),
+            start(&modules(&[("Main.asm", "@1\n")]), None, true)
+                .err()
+                .map(|error| error.to_string())
+        );
+
+        assert_eq!(
+            Err(vec![
+                String::from("A.asm: error: `NOPE` is not a label of the program (line 1, column 1)"),
+                String::from("B.asm: error: the entry point is also set by A.asm (line 1, column 1)\n  note: first defined here (A.asm, line 1, column 1)"),
+            ]),
+            linked(&[("A.asm", ".entry NOPE\n@1\n"), ("B.asm", ".entry B\n(B)\n@2\n")])
+        );
+    }
+
+    #[test]
+    fn map_file() {
+        let mut modules = modules(&[
+            ("Main.asm", "@MULT\n0;JMP\n(UNUSED)\n@i\n0;JMP\n"),
+            ("Mult.asm", "(MULT)\n@i\nM=0\n"),
+        ]);
+        let removed = gc::collect(&mut modules);
+        let program = link(&modules, &Layout::new(), 32768).ok().unwrap();
+        assert_eq!(
+            "ROM\n    0  Main.asm  (2 words)\n    2  Mult.asm  (2 words)\nLabels\n    2  MULT  (Mult.asm)\nRAM map\n   16  i  (Mult.asm)\nRemoved\n  UNUSED  (Main.asm, 2 words)\n",
+            map(&modules, &program, &removed)
+        );
+    }
+
+    #[test]
+    fn link_errors() {
+        assert_eq!(
+            Err(vec![String::from(
+                "B.asm: error: label `LOOP` is defined twice (line 2, column 2)\n  note: first defined here (A.asm, line 1, column 2)"
+            )]),
+            linked(&[("A.asm", "(LOOP)\n@LOOP\n"), ("B.asm", "@1\n(LOOP)\n@2\n")])
+        );
+        // fits while B.asm takes END for a variable at 16
+        let a = format!("{}(END)\n", "@1\n".repeat(17));
+        assert_eq!(
+            Err(vec![String::from(
+                "B.asm: error: `END*2047` evaluates to 34799, which does not fit in 15 bits (line 1, column 2)"
+            )]),
+            linked(&[("A.asm", &a), ("B.asm", "@END*2047\n")])
+        );
+
+        let modules = modules(&[("A.asm", "@1\n@2\n"), ("B.asm", "@3\n(END)\n")]);
+        let errors: Vec<String> = link(&modules, &Layout::new(), 2)
+            .err()
+            .unwrap()
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                String::from("B.asm: error: label `END` is at ROM[3], past the end of the 2 word ROM (line 2, column 2)"),
+                String::from("B.asm: error: the program needs 3 words but the ROM holds 2 (line 1, column 1)"),
+            ],
+            errors
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+
+use crate::parser::{Location, Statement};
+
+// Side by side view of the source and the code assembled from it, written
+// as Foo.lst. Lines that expand to other instructions, like pseudo-ops,
+// are followed by their expansion.
+pub struct Listing<'a> {
+    pub source: Vec<&'a str>,
+    pub entries: Vec<Entry>,
+}
+
+// an instruction with its address and word, labels have neither
+pub struct Entry {
+    pub location: Location,
+    pub text: String,
+    pub word: Option<(u32, String)>,
+}
+
+impl<'a> Listing<'a> {
+    pub fn new(contents: &'a str) -> Listing<'a> {
+        Listing {
+            source: contents.lines().collect(),
+            entries: vec![],
+        }
+    }
+
+    // statements have to be added in ROM order, labels without a word
+    pub fn add(&mut self, statement: &Statement, word: Option<(u32, &str)>) {
+        self.entries.push(Entry {
+            location: statement.location,
+            text: statement.instruction.to_string(),
+            word: word.map(|(address, word)| (address, String::from(word))),
+        });
+    }
+}
+
+// 00002  0000000000000010      4  @LOOP
+fn row(
+    f: &mut fmt::Formatter,
+    word: &Option<(u32, String)>,
+    line: &str,
+    text: &str,
+) -> fmt::Result {
+    let (address, word) = match word {
+        Some((address, word)) => (format!("{:05}", address), word.as_str()),
+        None => (String::new(), ""),
+    };
+    writeln!(f, "{:<5}  {:<16}  {:>5}  {}", address, word, line, text)
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut i = 0;
+
+        while i < self.entries.len() {
+            let location = self.entries[i].location;
+            let group: Vec<_> = self.entries[i..]
+                .iter()
+                .take_while(|entry| entry.location.line == location.line)
+                .collect();
+            i += group.len();
+
+            let source = self
+                .source
+                .get(location.line as usize - 1)
+                .map(|line| line.trim())
+                .unwrap_or("");
+            let line = location.line.to_string();
+
+            match group.as_slice() {
+                [entry] if entry.text == source => row(f, &entry.word, &line, &entry.text)?,
+                _ => {
+                    row(f, &None, &line, source)?;
+                    for entry in group {
+                        row(f, &entry.word, "", &format!("    {}", entry.text))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn listing(contents: &str) -> String {
+        let (statements, _) = assembler::parse(contents);
+        let symbol_table = assembler::resolve(&statements);
+        let mut listing = Listing::new(contents);
+        let mut address = 0;
+
+        for statement in &statements {
+            match assembler::encode(&statement.instruction, &symbol_table) {
+                Some(word) => {
+                    listing.add(statement, Some((address, &word)));
+                    address += 1;
+                }
+                None => listing.add(statement, None),
+            }
+        }
+
+        listing.to_string()
+    }
+
+    #[test]
+    fn plain_lines() {
+        assert_eq!(
+            "00000  0000000000010000      1  @i\n\
+             \x20                            2  (LOOP)\n\
+             00001  1110101010000111      3  0;JMP\n",
+            listing("@i\n(LOOP)\n0;JMP")
+        );
+    }
+
+    #[test]
+    fn pseudo_op_with_expansion() {
+        assert_eq!(
+            "00000  0000000000000011      1  @3\n\
+             \x20                            2  GOTO END\n\
+             00001  0000000000000011             @END\n\
+             00002  1110101010000111             0;JMP\n\
+             \x20                            3  (END)\n",
+            listing("@3\nGOTO END\n(END)")
+        );
+    }
+}
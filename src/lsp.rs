@@ -0,0 +1,1159 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use crate::assembler;
+use crate::code;
+use crate::data::{self, Directive};
+use crate::diagnostic::Diagnostic;
+use crate::expression;
+use crate::json::{self, Value};
+use crate::parser::{Instruction, Statement};
+use crate::pseudo;
+use crate::symbol_table::{Layout, SymbolTable};
+
+// JSON-RPC error codes used by the Language Server Protocol
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+// CompletionItemKind values
+const KIND_VARIABLE: i64 = 6;
+const KIND_KEYWORD: i64 = 14;
+const KIND_REFERENCE: i64 = 18;
+const KIND_CONSTANT: i64 = 21;
+
+// Language server for .asm files, talking JSON-RPC over the given streams
+// (stdin and stdout when started with `assembler lsp`). Documents are always
+// synced in full and re-analyzed on every change.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> Result<(), Box<dyn Error>> {
+    let mut server = Server::new();
+
+    while let Some(body) = read_message(&mut input)? {
+        let replies = match json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![error_response(Value::Null, PARSE_ERROR, e)],
+        };
+
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+
+        if server.exited {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>, Box<dyn Error>> {
+    let mut length: Option<usize> = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let length = length.ok_or("missing Content-Length header")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(String::from_utf8(body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<(), Box<dyn Error>> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn response(id: Value, result: Value) -> Value {
+    Value::object()
+        .with("jsonrpc", "2.0")
+        .with("id", id)
+        .with("result", result)
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    let error = Value::object().with("code", code).with("message", message);
+
+    Value::object()
+        .with("jsonrpc", "2.0")
+        .with("id", id)
+        .with("error", error)
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    Value::object()
+        .with("jsonrpc", "2.0")
+        .with("method", method)
+        .with("params", params)
+}
+
+// A symbol written in the source, `line` and the columns are 0-based as in
+// LSP positions. `end` is exclusive.
+struct Occurrence {
+    name: String,
+    line: u32,
+    start: u32,
+    end: u32,
+    definition: bool,
+}
+
+struct Document {
+    lines: Vec<String>,
+    statements: Vec<Statement<'static>>,
+    occurrences: Vec<Occurrence>,
+    symbol_table: SymbolTable,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    fn new(text: &str) -> Document {
+        let (statements, directives) = assembler::parse(text);
+        let (symbol_table, layout) = assembler::allocate(&statements, &directives, &Layout::new());
+        let mut diagnostics = assembler::check(&statements, &symbol_table);
+        diagnostics.extend(layout);
+        let occurrences = occurrences(text, &statements);
+        // the document outlives the text of the request
+        let statements = statements
+            .into_iter()
+            .map(|statement| Statement {
+                instruction: statement.instruction.into_owned(),
+                location: statement.location,
+            })
+            .collect();
+
+        Document {
+            lines: text.lines().map(String::from).collect(),
+            statements,
+            occurrences,
+            symbol_table,
+            diagnostics,
+        }
+    }
+
+    fn occurrences(&self) -> &[Occurrence] {
+        &self.occurrences
+    }
+
+    fn occurrence_at(&self, line: u32, character: u32) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|o| o.line == line && o.start <= character && character <= o.end)
+    }
+
+    fn statement_at(&self, line: u32) -> Option<&Statement<'static>> {
+        self.statements.iter().find(|s| s.location.line == line + 1)
+    }
+
+    fn is_label(&self, name: &str) -> bool {
+        self.symbol_table.labels.iter().any(|l| l == name)
+    }
+
+    fn is_variable(&self, name: &str) -> bool {
+        self.symbol_table.variables.iter().any(|v| v == name)
+    }
+
+    fn has_error_on(&self, line: u32) -> bool {
+        self.diagnostics.iter().any(|d| d.location.line == line + 1)
+    }
+}
+
+// The symbols as they are written, `parsed` gives their full names, so
+// that `.loop` after `(MAIN)` matches `MAIN.loop` but keeps its spelling
+// and column.
+fn occurrences(text: &str, parsed: &[Statement]) -> Vec<Occurrence> {
+    let source: Vec<&str> = text.lines().collect();
+    let mut lines: HashMap<u32, Vec<&Instruction>> = HashMap::new();
+    for statement in parsed {
+        lines
+            .entry(statement.location.line)
+            .or_default()
+            .push(&statement.instruction);
+    }
+
+    let mut occurrences = vec![];
+    for statement in assembler::read(text) {
+        let line = statement.location.line;
+        let column = statement.location.column;
+
+        // directives leave nothing in `parsed`, the names in them are
+        // global
+        if let Some(Ok(directive)) = data::directive(&statement.instruction) {
+            let text = source.get(line as usize - 1).copied().unwrap_or("");
+            for (value, start, definition) in directive_operands(text, column, &directive) {
+                let pairs = match definition {
+                    true => vec![(value, 0)],
+                    false => symbols(&value),
+                };
+                for (name, offset) in pairs {
+                    occurrences.push(Occurrence {
+                        end: start + offset + name.len() as u32,
+                        name,
+                        line: line - 1,
+                        start: start + offset,
+                        definition,
+                    });
+                }
+            }
+            continue;
+        }
+
+        let named = match lines.get(&line) {
+            Some(named) => named,
+            None => continue,
+        };
+
+        // the symbols start right after `@` or `(`, the operands of a
+        // pseudo-op are found in the line, its expansion is not in the
+        // source
+        let written: Vec<(String, String, u32, bool)> = match (&statement.instruction, named[0]) {
+            (Instruction::A(written), Instruction::A(name)) => {
+                vec![(written.to_string(), name.to_string(), column, false)]
+            }
+            (Instruction::L(written), Instruction::L(name)) => {
+                vec![(written.to_string(), name.to_string(), column, true)]
+            }
+            (instruction, _) => match pseudo::expand(instruction, line) {
+                Some(Ok(expansion)) => {
+                    let text = source.get(line as usize - 1).copied().unwrap_or("");
+                    operands(text, column)
+                        .into_iter()
+                        .filter_map(|(operand, start)| {
+                            let name = expanded_name(operand, &expansion, named)?;
+                            Some((String::from(operand), name, start, false))
+                        })
+                        .collect()
+                }
+                _ => continue,
+            },
+        };
+
+        for (written, name, column, definition) in written {
+            let pairs = match definition {
+                true => vec![((written, 0), (name, 0))],
+                false => symbols(&written).into_iter().zip(symbols(&name)).collect(),
+            };
+            for ((spelling, offset), (name, _)) in pairs {
+                // anonymous labels have no name to rename
+                if spelling.is_empty() || name.starts_with(':') {
+                    continue;
+                }
+                let start = column + offset;
+                occurrences.push(Occurrence {
+                    name,
+                    line: line - 1,
+                    start,
+                    end: start + spelling.len() as u32,
+                    definition,
+                });
+            }
+        }
+    }
+
+    occurrences
+}
+
+// The names and values of a directive with their 0-based columns, found
+// in order after the name of the directive. Strings have no symbols.
+// .var x 100 -> [("x", 5, true), ("100", 7, false)]
+fn directive_operands(line: &str, column: u32, directive: &Directive) -> Vec<(String, u32, bool)> {
+    let values: Vec<(&String, bool)> = match directive {
+        Directive::Data(address) => vec![(address, false)],
+        Directive::Word(words) => words.iter().map(|word| (word, false)).collect(),
+        Directive::Var(name, address) => vec![(name, true), (address, false)],
+        Directive::Global(name) | Directive::Entry(name) => vec![(name, false)],
+    };
+
+    let start = column as usize - 1;
+    let text = line.get(start..).unwrap_or("");
+    let mut offset = text.find(char::is_whitespace).unwrap_or(text.len());
+    let mut operands = vec![];
+    for (value, definition) in values {
+        let found = match text[offset..].find(value.as_str()) {
+            Some(found) => found,
+            None => continue,
+        };
+        offset += found;
+        if !value.starts_with('"') {
+            operands.push((value.clone(), (start + offset) as u32, definition));
+        }
+        offset += value.len();
+    }
+
+    operands
+}
+
+// The full name of an operand, from the instruction of the expansion that
+// loads it, `parsed` is the same expansion after parse().
+fn expanded_name(
+    operand: &str,
+    expansion: &[Instruction],
+    parsed: &[&Instruction],
+) -> Option<String> {
+    expansion.iter().zip(parsed).find_map(|pair| match pair {
+        (Instruction::A(symbol), Instruction::A(name)) if symbol == operand => {
+            Some(name.to_string())
+        }
+        _ => None,
+    })
+}
+
+// The operands of a pseudo-op with their 0-based columns, the pseudo-op
+// starts at the 1-based `column` of the line.
+// JEQ D, END -> [("D", 4), ("END", 7)]
+fn operands(line: &str, column: u32) -> Vec<(&str, u32)> {
+    let start = column as usize - 1;
+    let text = line.get(start..).unwrap_or("");
+    let text = text.split("//").next().unwrap_or("");
+    let mut offset = match text.find(char::is_whitespace) {
+        Some(offset) => offset,
+        None => return vec![],
+    };
+
+    let mut operands = vec![];
+    for operand in text[offset..].split(',') {
+        let trimmed = operand.trim();
+        let leading = operand.len() - operand.trim_start().len();
+        if !trimmed.is_empty() {
+            operands.push((trimmed, (start + offset + leading) as u32));
+        }
+        offset += operand.len() + 1;
+    }
+
+    operands
+}
+
+fn symbols(symbol: &str) -> Vec<(String, u32)> {
+    match expression::parse(symbol) {
+        Ok(parsed) => parsed
+            .symbols()
+            .into_iter()
+            .map(|(name, offset)| (String::from(name), offset))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl Server {
+    fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+            exited: false,
+        }
+    }
+
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = match message.get("method").and_then(|m| m.as_str()) {
+            Some(method) => method,
+            None => return vec![], // a response from the client
+        };
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, &params),
+        };
+
+        if self.shutdown {
+            return vec![error_response(id, INVALID_REQUEST, "server is shut down")];
+        }
+
+        match self.request(method, &params) {
+            Ok(result) => vec![response(id, result)],
+            Err((code, message)) => vec![error_response(id, code, &message)],
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|d| d.get("uri"))
+            .and_then(|u| u.as_str())
+            .map(String::from);
+
+        match (method, uri) {
+            ("exit", _) => {
+                self.exited = true;
+                vec![]
+            }
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|d| d.get("text"))
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("");
+                self.open(uri, text)
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                let text = params
+                    .get("contentChanges")
+                    .and_then(|c| c.as_array())
+                    .and_then(|c| c.last())
+                    .and_then(|c| c.get("text"))
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("");
+                self.open(uri, text)
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, &[])]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn open(&mut self, uri: String, text: &str) -> Vec<Value> {
+        let document = Document::new(text);
+        let diagnostics = publish_diagnostics(&uri, &document.diagnostics);
+        self.documents.insert(uri, document);
+        vec![diagnostics]
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(initialize()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/rename" => self.rename(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        }
+    }
+
+    fn position(&self, params: &Value) -> Result<(String, &Document, u32, u32), (i64, String)> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|d| d.get("uri"))
+            .and_then(|u| u.as_str())
+            .ok_or((INVALID_PARAMS, String::from("missing textDocument")))?;
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or((INVALID_PARAMS, format!("unknown document `{}`", uri)))?;
+
+        let position = params.get("position");
+        let line = position
+            .and_then(|p| p.get("line"))
+            .and_then(|l| l.as_i64());
+        let character = position
+            .and_then(|p| p.get("character"))
+            .and_then(|c| c.as_i64());
+
+        match (line, character) {
+            (Some(line), Some(character)) => {
+                Ok((String::from(uri), document, line as u32, character as u32))
+            }
+            _ => Err((INVALID_PARAMS, String::from("missing position"))),
+        }
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, line, character) = self.position(params)?;
+
+        let definition = document.occurrence_at(line, character).and_then(|o| {
+            document
+                .occurrences()
+                .iter()
+                .find(|d| d.definition && d.name == o.name)
+        });
+
+        Ok(match definition {
+            Some(definition) => location(&uri, definition),
+            None => Value::Null,
+        })
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, line, character) = self.position(params)?;
+        let include_declaration = params
+            .get("context")
+            .and_then(|c| c.get("includeDeclaration"))
+            .and_then(|i| i.as_bool())
+            .unwrap_or(true);
+
+        let name = match document.occurrence_at(line, character) {
+            Some(occurrence) => occurrence.name.clone(),
+            None => return Ok(Value::Null),
+        };
+
+        let references = document
+            .occurrences()
+            .iter()
+            .filter(|o| o.name == name && (include_declaration || !o.definition))
+            .map(|o| location(&uri, o))
+            .collect::<Vec<Value>>();
+
+        Ok(Value::from(references))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document, line, character) = self.position(params)?;
+
+        if document.has_error_on(line) {
+            return Ok(Value::Null);
+        }
+
+        let statement = match document.statement_at(line) {
+            Some(statement) => statement,
+            None => return Ok(Value::Null),
+        };
+
+        let occurrence = document
+            .occurrence_at(line, character)
+            .and_then(|occurrence| {
+                let address = document.symbol_table.get_address(&occurrence.name)?;
+                Some((occurrence, address))
+            });
+        let text = match occurrence {
+            Some((occurrence, address)) => {
+                let description = if document.is_label(&occurrence.name) {
+                    format!("label, ROM[{}]", address)
+                } else if document.is_variable(&occurrence.name) {
+                    format!("variable, RAM[{}]", address)
+                } else {
+                    format!("predefined, {}", address)
+                };
+                format!(
+                    "`{}` {}\n\n`{:016b}`",
+                    occurrence.name, description, address
+                )
+            }
+            None => match assembler::encode(&statement.instruction, &document.symbol_table) {
+                Some(word) => format!("`{}`\n\n`{}`", statement.instruction, word),
+                None => return Ok(Value::Null),
+            },
+        };
+
+        let contents = Value::object().with("kind", "markdown").with("value", text);
+
+        Ok(Value::object().with("contents", contents))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document, line, character) = self.position(params)?;
+
+        let text = document.lines.get(line as usize).map_or("", |l| l.as_str());
+        let prefix = text.get(..character as usize).unwrap_or(text).trim_start();
+
+        let mut items = vec![];
+
+        if prefix.starts_with('@') {
+            let mut names: Vec<(&String, &u32)> = document.symbol_table.symbols.iter().collect();
+            names.sort();
+
+            for (name, address) in names {
+                let kind = if document.is_label(name) {
+                    KIND_REFERENCE
+                } else if document.is_variable(name) {
+                    KIND_VARIABLE
+                } else {
+                    KIND_CONSTANT
+                };
+                items.push(completion_item(name, kind, &address.to_string()));
+            }
+        } else if prefix.contains(';') {
+            for (mnemonic, binary) in code::JUMP.iter() {
+                items.push(completion_item(mnemonic, KIND_KEYWORD, binary));
+            }
+        } else if prefix.contains('=') {
+            for (mnemonic, binary) in code::COMP.iter() {
+                items.push(completion_item(mnemonic, KIND_KEYWORD, binary));
+            }
+        } else {
+            for (mnemonic, binary) in code::DEST.iter().skip(1) {
+                items.push(completion_item(mnemonic, KIND_KEYWORD, binary));
+            }
+            for (mnemonic, binary) in code::COMP.iter() {
+                items.push(completion_item(mnemonic, KIND_KEYWORD, binary));
+            }
+        }
+
+        Ok(Value::from(items))
+    }
+
+    fn rename(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, line, character) = self.position(params)?;
+        let new_name = params
+            .get("newName")
+            .and_then(|n| n.as_str())
+            .ok_or((INVALID_PARAMS, String::from("missing newName")))?;
+
+        let name = match document.occurrence_at(line, character) {
+            Some(occurrence) => occurrence.name.clone(),
+            None => return Ok(Value::Null),
+        };
+
+        if !document.is_label(&name) && !document.is_variable(&name) {
+            return Err((
+                REQUEST_FAILED,
+                format!("predefined symbol `{}` cannot be renamed", name),
+            ));
+        }
+        if !expression::is_symbol(new_name) {
+            return Err((
+                REQUEST_FAILED,
+                format!("`{}` is not a valid symbol", new_name),
+            ));
+        }
+        if document.symbol_table.contains(new_name) {
+            return Err((REQUEST_FAILED, format!("`{}` is already defined", new_name)));
+        }
+
+        let edits = document
+            .occurrences()
+            .iter()
+            .filter(|o| o.name == name)
+            .map(|o| {
+                Value::object()
+                    .with("range", range(o.line, o.start, o.end))
+                    .with("newText", new_name)
+            })
+            .collect::<Vec<Value>>();
+
+        let changes = Value::object().with(&uri, edits);
+        Ok(Value::object().with("changes", changes))
+    }
+}
+
+fn initialize() -> Value {
+    let completion = Value::object().with(
+        "triggerCharacters",
+        vec![Value::from("@"), Value::from("="), Value::from(";")],
+    );
+
+    let capabilities = Value::object()
+        .with("textDocumentSync", 1u32)
+        .with("definitionProvider", true)
+        .with("referencesProvider", true)
+        .with("hoverProvider", true)
+        .with("completionProvider", completion)
+        .with("renameProvider", true);
+
+    let server_info = Value::object()
+        .with("name", "assembler")
+        .with("version", env!("CARGO_PKG_VERSION"));
+
+    Value::object()
+        .with("capabilities", capabilities)
+        .with("serverInfo", server_info)
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: &[Diagnostic]) -> Value {
+    let diagnostics = diagnostics
+        .iter()
+        .map(|d| {
+            let diagnostic = Value::object()
+                .with("range", diagnostic_range(d))
+                .with("severity", if d.is_error() { 1u32 } else { 2u32 })
+                .with("source", "assembler")
+                .with("message", d.message.as_str());
+            if d.notes.is_empty() {
+                return diagnostic;
+            }
+
+            let related = d
+                .notes
+                .iter()
+                .map(|note| {
+                    let location = Value::object()
+                        .with("uri", uri)
+                        .with("range", diagnostic_range(note));
+                    Value::object()
+                        .with("location", location)
+                        .with("message", note.message.as_str())
+                })
+                .collect::<Vec<Value>>();
+            diagnostic.with("relatedInformation", related)
+        })
+        .collect::<Vec<Value>>();
+
+    let params = Value::object()
+        .with("uri", uri)
+        .with("diagnostics", diagnostics);
+
+    notification("textDocument/publishDiagnostics", params)
+}
+
+fn diagnostic_range(diagnostic: &Diagnostic) -> Value {
+    let line = diagnostic.location.line - 1;
+    let start = diagnostic.location.column - 1;
+    range(line, start, start + diagnostic.length)
+}
+
+fn completion_item(label: &str, kind: i64, detail: &str) -> Value {
+    Value::object()
+        .with("label", label)
+        .with("kind", kind)
+        .with("detail", detail)
+}
+
+fn location(uri: &str, occurrence: &Occurrence) -> Value {
+    Value::object().with("uri", uri).with(
+        "range",
+        range(occurrence.line, occurrence.start, occurrence.end),
+    )
+}
+
+fn range(line: u32, start: u32, end: u32) -> Value {
+    let position = |character: u32| {
+        Value::object()
+            .with("line", line)
+            .with("character", character)
+    };
+
+    Value::object()
+        .with("start", position(start))
+        .with("end", position(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///Max.asm";
+    const SOURCE: &str = "// max\n@i\nM=1\n(LOOP)\n  @i\n  D=M\n@LOOP\nD;JGT\n";
+
+    fn frame(message: &Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn request(id: i64, method: &str, params: Value) -> String {
+        frame(
+            &Value::object()
+                .with("jsonrpc", "2.0")
+                .with("id", id)
+                .with("method", method)
+                .with("params", params),
+        )
+    }
+
+    fn notify(method: &str, params: Value) -> String {
+        frame(&notification(method, params))
+    }
+
+    fn open(text: &str) -> String {
+        let document = Value::object()
+            .with("uri", URI)
+            .with("languageId", "hack")
+            .with("version", 1i64)
+            .with("text", text);
+        notify(
+            "textDocument/didOpen",
+            Value::object().with("textDocument", document),
+        )
+    }
+
+    fn at(line: i64, character: i64) -> Value {
+        let position = Value::object()
+            .with("line", line)
+            .with("character", character);
+        Value::object()
+            .with("textDocument", Value::object().with("uri", URI))
+            .with("position", position)
+    }
+
+    // runs a scripted session and returns every message the server sent
+    fn session(script: &[String]) -> Vec<Value> {
+        let input = Cursor::new(script.concat().into_bytes());
+        let mut output: Vec<u8> = vec![];
+        run(input, &mut output).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let mut messages = vec![];
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            messages.push(json::parse(&body).unwrap());
+        }
+        messages
+    }
+
+    // (line, start, end) of every edit
+    fn ranges(edits: &Value) -> Vec<(i64, i64, i64)> {
+        edits
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edit| {
+                let range = edit.get("range").unwrap();
+                let get = |position, key| {
+                    range
+                        .get(position)
+                        .and_then(|p| p.get(key))
+                        .and_then(|v| v.as_i64())
+                        .unwrap()
+                };
+                (
+                    get("start", "line"),
+                    get("start", "character"),
+                    get("end", "character"),
+                )
+            })
+            .collect()
+    }
+
+    fn result(messages: &[Value], id: i64) -> Value {
+        messages
+            .iter()
+            .find(|m| m.get("id").and_then(|i| i.as_i64()) == Some(id))
+            .and_then(|m| m.get("result"))
+            .cloned()
+            .unwrap()
+    }
+
+    #[test]
+    fn initialize_and_exit() {
+        let messages = session(&[
+            request(1, "initialize", Value::object()),
+            request(2, "shutdown", Value::Null),
+            notify("exit", Value::Null),
+            request(3, "initialize", Value::object()),
+        ]);
+
+        assert_eq!(2, messages.len());
+        let capabilities = result(&messages, 1).get("capabilities").cloned().unwrap();
+        assert_eq!(
+            Some(true),
+            capabilities.get("hoverProvider").and_then(|h| h.as_bool())
+        );
+        assert_eq!(Value::Null, result(&messages, 2));
+    }
+
+    #[test]
+    fn diagnostics_on_open_and_change() {
+        let change = Value::object()
+            .with("textDocument", Value::object().with("uri", URI))
+            .with(
+                "contentChanges",
+                vec![Value::object().with("text", "@1\nD=M")],
+            );
+        let messages = session(&[
+            open("@1\n  D=D+2\n"),
+            notify("textDocument/didChange", change),
+        ]);
+
+        assert_eq!(2, messages.len());
+        assert_eq!(
+            "{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"file:///Max.asm\",\"diagnostics\":[{\"range\":{\"start\":{\"line\":1,\"character\":4},\"end\":{\"line\":1,\"character\":7}},\"severity\":1,\"source\":\"assembler\",\"message\":\"invalid comp `D+2`\"}]}}",
+            messages[0].to_string()
+        );
+        let params = messages[1].get("params").unwrap();
+        assert_eq!(
+            Some(0),
+            params
+                .get("diagnostics")
+                .and_then(|d| d.as_array())
+                .map(|d| d.len())
+        );
+    }
+
+    #[test]
+    fn related_information() {
+        let messages = session(&[open("(LOOP)\n(LOOP)\n")]);
+
+        let diagnostic = messages[0]
+            .get("params")
+            .and_then(|p| p.get("diagnostics"))
+            .and_then(|d| d.as_array())
+            .map(|d| d[0].clone())
+            .unwrap();
+        assert_eq!(
+            "[{\"location\":{\"uri\":\"file:///Max.asm\",\"range\":{\"start\":{\"line\":0,\"character\":1},\"end\":{\"line\":0,\"character\":5}}},\"message\":\"first defined here\"}]",
+            diagnostic.get("relatedInformation").unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn definition_of_label() {
+        let messages = session(&[
+            open(SOURCE),
+            request(1, "textDocument/definition", at(6, 3)),
+            request(2, "textDocument/definition", at(2, 0)),
+        ]);
+
+        assert_eq!(
+            "{\"uri\":\"file:///Max.asm\",\"range\":{\"start\":{\"line\":3,\"character\":1},\"end\":{\"line\":3,\"character\":5}}}",
+            result(&messages, 1).to_string()
+        );
+        assert_eq!(Value::Null, result(&messages, 2));
+    }
+
+    #[test]
+    fn definition_inside_expression() {
+        let messages = session(&[
+            open(
+                "@END-1
+(END)
+",
+            ),
+            request(1, "textDocument/definition", at(0, 2)),
+            request(2, "textDocument/definition", at(0, 5)),
+        ]);
+
+        assert_eq!(
+            "{\"uri\":\"file:///Max.asm\",\"range\":{\"start\":{\"line\":1,\"character\":1},\"end\":{\"line\":1,\"character\":4}}}",
+            result(&messages, 1).to_string()
+        );
+        assert_eq!(Value::Null, result(&messages, 2));
+    }
+
+    #[test]
+    fn references_to_variable() {
+        let messages = session(&[
+            open(SOURCE),
+            request(1, "textDocument/references", at(4, 3)),
+        ]);
+
+        let references = result(&messages, 1);
+        let lines: Vec<i64> = references
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                r.get("range")
+                    .and_then(|r| r.get("start"))
+                    .and_then(|s| s.get("line"))
+                    .and_then(|l| l.as_i64())
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(vec![1, 4], lines);
+    }
+
+    #[test]
+    fn hover_shows_address_and_binary() {
+        let messages = session(&[
+            open(SOURCE),
+            request(1, "textDocument/hover", at(4, 3)),
+            request(2, "textDocument/hover", at(7, 0)),
+            request(3, "textDocument/hover", at(6, 2)),
+        ]);
+
+        let value = |id| {
+            result(&messages, id)
+                .get("contents")
+                .and_then(|c| c.get("value"))
+                .and_then(|v| v.as_str())
+                .map(String::from)
+                .unwrap()
+        };
+        assert_eq!("`i` variable, RAM[16]\n\n`0000000000010000`", value(1));
+        assert_eq!("`D;JGT`\n\n`1110001100000001`", value(2));
+        assert_eq!("`LOOP` label, ROM[2]\n\n`0000000000000010`", value(3));
+    }
+
+    #[test]
+    fn completion_by_field() {
+        let messages = session(&[
+            open("D=\n0;\n@\n"),
+            request(1, "textDocument/completion", at(0, 2)),
+            request(2, "textDocument/completion", at(1, 2)),
+            request(3, "textDocument/completion", at(2, 1)),
+        ]);
+
+        let labels = |id| -> Vec<String> {
+            result(&messages, id)
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| String::from(i.get("label").and_then(|l| l.as_str()).unwrap()))
+                .collect()
+        };
+        assert_eq!(code::COMP.len(), labels(1).len());
+        assert!(labels(1).contains(&String::from("D+1")));
+        assert_eq!(code::JUMP.len(), labels(2).len());
+        assert!(labels(3).contains(&String::from("SCREEN")));
+    }
+
+    #[test]
+    fn rename_label() {
+        let mut params = at(3, 2);
+        if let Value::Object(members) = &mut params {
+            members.push((String::from("newName"), Value::from("AGAIN")));
+        }
+        let messages = session(&[open(SOURCE), request(1, "textDocument/rename", params)]);
+
+        let edits = result(&messages, 1)
+            .get("changes")
+            .and_then(|c| c.get(URI))
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            "[{\"range\":{\"start\":{\"line\":3,\"character\":1},\"end\":{\"line\":3,\"character\":5}},\"newText\":\"AGAIN\"},{\"range\":{\"start\":{\"line\":6,\"character\":1},\"end\":{\"line\":6,\"character\":5}},\"newText\":\"AGAIN\"}]",
+            edits.to_string()
+        );
+    }
+
+    #[test]
+    fn rename_local_label() {
+        let mut params = at(1, 2);
+        if let Value::Object(members) = &mut params {
+            members.push((String::from("newName"), Value::from(".again")));
+        }
+        let messages = session(&[
+            open("(MAIN)\n(.loop)\n@.loop+1\n(OTHER)\n(.loop)\n@.loop\n"),
+            request(1, "textDocument/rename", params),
+        ]);
+
+        let edits = result(&messages, 1)
+            .get("changes")
+            .and_then(|c| c.get(URI))
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            "[{\"range\":{\"start\":{\"line\":1,\"character\":1},\"end\":{\"line\":1,\"character\":6}},\"newText\":\".again\"},{\"range\":{\"start\":{\"line\":2,\"character\":1},\"end\":{\"line\":2,\"character\":6}},\"newText\":\".again\"}]",
+            edits.to_string()
+        );
+    }
+
+    #[test]
+    fn rename_pseudo_op_operand() {
+        let mut params = at(0, 6);
+        if let Value::Object(members) = &mut params {
+            members.push((String::from("newName"), Value::from("FIN")));
+        }
+        let messages = session(&[
+            open("GOTO END\nJEQ  D,END\nMOV R1, END\n(END)\nHALT\n"),
+            request(1, "textDocument/rename", params),
+        ]);
+
+        let edits = result(&messages, 1)
+            .get("changes")
+            .and_then(|c| c.get(URI))
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            vec![(0, 5, 8), (1, 7, 10), (2, 8, 11), (3, 1, 4)],
+            ranges(&edits)
+        );
+    }
+
+    #[test]
+    fn halt_label_is_not_an_occurrence() {
+        let messages = session(&[
+            open("HALT\n"),
+            request(1, "textDocument/definition", at(0, 2)),
+        ]);
+
+        assert_eq!(Value::Null, result(&messages, 1));
+    }
+
+    #[test]
+    fn rename_in_directives() {
+        let mut params = at(4, 1);
+        if let Value::Object(members) = &mut params {
+            members.push((String::from("newName"), Value::from("y")));
+        }
+        let messages = session(&[
+            open(".var x 100\n.global x\n.word \"x\", x+1\n.entry MAIN\n@x\n(MAIN)\n"),
+            request(1, "textDocument/rename", params),
+            request(2, "textDocument/definition", at(4, 1)),
+            request(3, "textDocument/references", at(3, 8)),
+        ]);
+
+        let edits = result(&messages, 1)
+            .get("changes")
+            .and_then(|c| c.get(URI))
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            vec![(0, 5, 6), (1, 8, 9), (2, 11, 12), (4, 1, 2)],
+            ranges(&edits)
+        );
+        assert_eq!(
+            "{\"uri\":\"file:///Max.asm\",\"range\":{\"start\":{\"line\":0,\"character\":5},\"end\":{\"line\":0,\"character\":6}}}",
+            result(&messages, 2).to_string()
+        );
+        assert_eq!(2, result(&messages, 3).as_array().unwrap().len());
+    }
+
+    #[test]
+    fn rename_predefined_fails() {
+        let mut params = at(0, 2);
+        if let Value::Object(members) = &mut params {
+            members.push((String::from("newName"), Value::from("X")));
+        }
+        let messages = session(&[open("@SP\n"), request(1, "textDocument/rename", params)]);
+
+        assert_eq!(
+            Some(REQUEST_FAILED),
+            messages[1]
+                .get("error")
+                .and_then(|e| e.get("code"))
+                .and_then(|c| c.as_i64())
+        );
+    }
+
+    #[test]
+    fn rename_to_invalid_symbol_fails() {
+        for new_name in ["a b", "x(y", "foo//", "1x", ""] {
+            let mut params = at(3, 2);
+            if let Value::Object(members) = &mut params {
+                members.push((String::from("newName"), Value::from(new_name)));
+            }
+            let messages = session(&[open(SOURCE), request(1, "textDocument/rename", params)]);
+
+            assert_eq!(
+                Some(format!("`{}` is not a valid symbol", new_name).as_str()),
+                messages[1]
+                    .get("error")
+                    .and_then(|e| e.get("message"))
+                    .and_then(|m| m.as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_method() {
+        let messages = session(&[request(7, "workspace/symbol", Value::object())]);
+
+        assert_eq!(
+            Some(METHOD_NOT_FOUND),
+            messages[0]
+                .get("error")
+                .and_then(|e| e.get("code"))
+                .and_then(|c| c.as_i64())
+        );
+    }
+
+    #[test]
+    fn malformed_message() {
+        let messages = session(&[String::from("Content-Length: 5\r\n\r\n{oops")]);
+
+        assert_eq!(
+            Some(PARSE_ERROR),
+            messages[0]
+                .get("error")
+                .and_then(|e| e.get("code"))
+                .and_then(|c| c.as_i64())
+        );
+    }
+}
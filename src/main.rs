@@ -1,13 +1,103 @@
 use std::env;
+use std::io;
 use std::process;
 
+mod archive;
 mod assembler;
+mod cache;
 mod code;
 mod config;
+mod data;
+mod debugger;
+mod diagnostic;
+mod expression;
+mod gc;
+mod jack_compiler;
+mod jack_parser;
+mod jack_tokenizer;
+mod json;
+mod json_output;
+mod junit;
+mod keyboard;
+mod linker;
+mod listing;
+mod lsp;
+mod object;
+mod optimizer;
 mod parser;
+mod pseudo;
+mod sarif;
+mod screen;
+mod simulator;
+mod source_map;
+mod symbol_table;
+mod test_script;
+mod vm;
+mod watch;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+
+    if args.len() > 1 && args[1] == "lsp" {
+        if let Err(e) = lsp::run(io::stdin().lock(), io::stdout()) {
+            eprintln!("Language server error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "debug" {
+        if args.len() < 3 {
+            println!("Problem parsing arguments: missing file to debug");
+            process::exit(1);
+        }
+        let program = simulator::Program::load(&args[2]).unwrap_or_else(|err| {
+            println!("Problem loading {}: {}", args[2], err);
+            process::exit(1);
+        });
+        if let Err(e) = debugger::run(program, io::stdin().lock(), io::stdout()) {
+            println!("Application error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "link" {
+        let config = config::LinkConfig::new(&args).unwrap_or_else(|err| {
+            println!("Problem parsing arguments: {}", err);
+            process::exit(1);
+        });
+        if let Err(e) = linker::run(config) {
+            println!("Application error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "archive" {
+        let config = config::ArchiveConfig::new(&args).unwrap_or_else(|err| {
+            println!("Problem parsing arguments: {}", err);
+            process::exit(1);
+        });
+        if let Err(e) = archive::run(config) {
+            println!("Application error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.len() > 1 && args[1] == "run" {
+        let config = config::RunConfig::new(&args).unwrap_or_else(|err| {
+            println!("Problem parsing arguments: {}", err);
+            process::exit(1);
+        });
+        if let Err(e) = simulator::run(config) {
+            println!("Application error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     let config = config::Config::new(&args).unwrap_or_else(|err| {
         println!("Problem parsing arguments: {}", err);
         process::exit(1);
@@ -15,8 +105,29 @@ fn main() {
 
     // println!("Input file: {}", config.filename);
 
+    if config.watch {
+        watch::run(config);
+    }
+
+    let filename = config.filename.clone();
+    let test = config.test;
+    let format = config.format;
     if let Err(e) = assembler::assembly(config) {
-        println!("Application error: {}", e);
+        // the documents for tools have the error already
+        if matches!(format, config::Format::Text | config::Format::Gcc) {
+            println!("Application error: {}", e);
+        }
         process::exit(1);
     }
+
+    // the JUnit report has the test script's result
+    if test && format != config::Format::Junit {
+        match test_script::run_for(&filename) {
+            Ok(message) => println!("{}", message),
+            Err(e) => {
+                println!("Test failed: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 }
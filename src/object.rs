@@ -0,0 +1,294 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::assembler;
+use crate::code::MAX_CONSTANT;
+use crate::config::Config;
+use crate::data::{self, Directive};
+use crate::diagnostic::Diagnostic;
+use crate::expression;
+use crate::optimizer;
+use crate::parser::{Instruction, Location, Parser, Statement};
+use crate::symbol_table::SymbolTable;
+
+// A file assembled on its own, written to Foo.obj by `-c` and kept in the
+// cache for multi-file programs. Labels are at offsets from the start of
+// the file, and the A-instructions that refer to labels or variables are
+// kept as written until the objects are linked together, see linker.rs.
+//
+// Labels are exported, except the local and anonymous ones. The symbols
+// the file uses but does not define are its imports, those that no other
+// object exports become its private variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub code: Vec<(Word, Location)>,
+    pub labels: Vec<(String, u32, Location)>,
+    pub imports: Vec<(String, Location)>,
+    pub directives: Vec<(Directive, Location)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Word {
+    Fixed(u16),
+    // the symbol or expression of an A-instruction
+    Symbol(String),
+}
+
+impl Object {
+    // the labels other objects can use, and the variables it shares
+    pub fn exports(&self) -> Vec<&str> {
+        let labels = self
+            .labels
+            .iter()
+            .map(|(label, _, _)| label.as_str())
+            .filter(|label| !is_local(label));
+        let variables = self
+            .directives
+            .iter()
+            .filter_map(|(directive, _)| match directive {
+                Directive::Global(name) | Directive::Var(name, _) => Some(name.as_str()),
+                _ => None,
+            });
+        labels.chain(variables).collect()
+    }
+}
+
+// `.loop` before any global label, and anonymous labels
+pub fn is_local(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with(':')
+}
+
+// Returns None when the file has errors, which are added to `diagnostics`
+// along with any warnings.
+pub fn compile(
+    contents: &str,
+    config: &Config,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Object> {
+    let (mut statements, directives) = assembler::parse(contents);
+
+    // symbols of other files are taken for variables here, the linker
+    // checks them
+    let mut found = assembler::check(&statements, &assembler::resolve(&statements));
+    if config.warn_case {
+        found.extend(assembler::case_warnings(&statements));
+        found.sort_by_key(|diagnostic| diagnostic.location.line);
+    }
+    let failed = found.iter().any(|diagnostic| diagnostic.is_error());
+    diagnostics.extend(found);
+    if failed {
+        return None;
+    }
+
+    if config.optimize {
+        optimizer::optimize(&mut statements);
+    }
+    lower(&statements, directives)
+}
+
+// Code the assembler writes itself, like the bootstrap, which needs no
+// checking.
+pub fn generated(contents: &str) -> Option<Object> {
+    let (statements, directives) = assembler::parse(contents);
+    lower(&statements, directives)
+}
+
+fn lower(statements: &[Statement], directives: Vec<(Directive, Location)>) -> Option<Object> {
+    // numbers and predefined symbols are known without the other files
+    let mut predefined = SymbolTable::new();
+    predefined.add_predefined_symbols();
+
+    let mut object = Object {
+        code: vec![],
+        labels: vec![],
+        imports: vec![],
+        directives,
+    };
+    for statement in statements {
+        let location = statement.location;
+        let word = match &statement.instruction {
+            Instruction::L(label) => {
+                let offset = object.code.len() as u32;
+                object.labels.push((label.to_string(), offset, location));
+                continue;
+            }
+            Instruction::A(symbol) => match assembler::value(symbol, &predefined) {
+                // check() reports the values that do not fit
+                Ok(value) => Word::Fixed((value & MAX_CONSTANT as i64) as u16),
+                Err(_) => Word::Symbol(symbol.to_string()),
+            },
+            instruction => {
+                let word = assembler::encode(instruction, &predefined)?;
+                Word::Fixed(u16::from_str_radix(&word, 2).ok()?)
+            }
+        };
+        object.code.push((word, location));
+    }
+
+    object.imports = imports(&object.code, &object.labels);
+    Some(object)
+}
+
+// The symbols the code uses but the labels do not define, in order of
+// first use.
+pub fn imports(
+    code: &[(Word, Location)],
+    labels: &[(String, u32, Location)],
+) -> Vec<(String, Location)> {
+    let mut predefined = SymbolTable::new();
+    predefined.add_predefined_symbols();
+    let mut imports = vec![];
+
+    let mut seen: HashSet<String> = labels.iter().map(|(label, _, _)| label.clone()).collect();
+    for (word, location) in code {
+        let parsed = match word {
+            Word::Symbol(symbol) => match expression::parse(symbol) {
+                Ok(parsed) => parsed,
+                Err(_) => continue,
+            },
+            Word::Fixed(_) => continue,
+        };
+        for (name, at) in parsed.symbols() {
+            if !predefined.contains(name) && seen.insert(String::from(name)) {
+                let location = Location {
+                    line: location.line,
+                    column: location.column + 1 + at,
+                };
+                imports.push((String::from(name), location));
+            }
+        }
+    }
+
+    imports
+}
+
+const HEADER: &str = "hack-object 2";
+
+// One entry per line, with the location of what it came from:
+//
+//     export 7:1 4 LOOP          LOOP is at offset 4
+//     local 9:1 6 :1             only this object sees it
+//     import 3:2 MULT
+//     word 8:1 1110101010000111
+//     symbol 9:1 END-1           resolved when linking
+//     directive 2:1 .word 1, 2
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        let at = |location: &Location| format!("{}:{}", location.line, location.column);
+
+        for (label, offset, location) in &self.labels {
+            let kind = if is_local(label) { "local" } else { "export" };
+            writeln!(f, "{} {} {} {}", kind, at(location), offset, label)?;
+        }
+        for (import, location) in &self.imports {
+            writeln!(f, "import {} {}", at(location), import)?;
+        }
+        for (word, location) in &self.code {
+            match word {
+                Word::Fixed(word) => writeln!(f, "word {} {:016b}", at(location), word)?,
+                Word::Symbol(symbol) => writeln!(f, "symbol {} {}", at(location), symbol)?,
+            }
+        }
+        for (directive, location) in &self.directives {
+            writeln!(f, "directive {} {}", at(location), directive)?;
+        }
+        Ok(())
+    }
+}
+
+// None when the text is not an object written by this version
+pub fn read(text: &str) -> Option<Object> {
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return None;
+    }
+
+    let mut object = Object {
+        code: vec![],
+        labels: vec![],
+        imports: vec![],
+        directives: vec![],
+    };
+    for line in lines {
+        let mut fields = line.splitn(3, ' ');
+        let kind = fields.next()?;
+        let (line, column) = fields.next()?.split_once(':')?;
+        let location = Location {
+            line: line.parse().ok()?,
+            column: column.parse().ok()?,
+        };
+        let rest = fields.next()?;
+
+        match kind {
+            "export" | "local" => {
+                let (offset, label) = rest.split_once(' ')?;
+                object
+                    .labels
+                    .push((String::from(label), offset.parse().ok()?, location));
+            }
+            "import" => object.imports.push((String::from(rest), location)),
+            "word" => {
+                let word = u16::from_str_radix(rest, 2).ok()?;
+                object.code.push((Word::Fixed(word), location));
+            }
+            "symbol" => object
+                .code
+                .push((Word::Symbol(String::from(rest)), location)),
+            "directive" => {
+                let directive = data::directive(&Parser::new(rest).instruction())?.ok()?;
+                object.directives.push((directive, location));
+            }
+            _ => return None,
+        }
+    }
+
+    Some(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config::new(&[String::from("assembler"), String::from("Main.asm")]).unwrap()
+    }
+
+    #[test]
+    fn compile_and_read() {
+        let contents = ".data 1024\n.word \"a, b\", LOOP\n(LOOP)\n@SCREEN\nD=A\n@LOOP\n@END-1\n0;JMP\n(.done)\n@.done\n";
+        let object = compile(contents, &config(), &mut vec![]).unwrap();
+
+        let at = |line, column| Location { line, column };
+        assert_eq!(
+            vec![
+                (Word::Fixed(16384), at(4, 1)),
+                (Word::Fixed(0xec10), at(5, 1)),
+                (Word::Symbol(String::from("LOOP")), at(6, 1)),
+                (Word::Symbol(String::from("END-1")), at(7, 1)),
+                (Word::Fixed(0xea87), at(8, 1)),
+                (Word::Symbol(String::from("LOOP.done")), at(10, 1)),
+            ],
+            object.code
+        );
+        assert_eq!(
+            vec![
+                (String::from("LOOP"), 0, at(3, 1)),
+                (String::from("LOOP.done"), 5, at(9, 1))
+            ],
+            object.labels
+        );
+        assert_eq!(vec![(String::from("END"), at(7, 2))], object.imports);
+        assert_eq!(vec!["LOOP", "LOOP.done"], object.exports());
+        assert_eq!(2, object.directives.len());
+        assert_eq!(Some(object.clone()), read(&object.to_string()));
+        assert_eq!(None, read("hack-object 0\n"));
+    }
+
+    #[test]
+    fn compile_errors() {
+        let mut diagnostics = vec![];
+        assert_eq!(None, compile("@1\nD=X\n", &config(), &mut diagnostics));
+        assert_eq!(1, diagnostics.len());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::code::{self, MAX_CONSTANT};
+use crate::expression;
+use crate::parser::{Instruction, Statement};
+use crate::symbol_table::SymbolTable;
+
+// Runs every pass until none of them can shrink the program any further.
+// Labels are kept in place, their addresses are resolved afterwards.
+pub fn optimize(statements: &mut Vec<Statement>) {
+    loop {
+        let length = statements.len();
+
+        remove_jumps_to_next(statements);
+        merge_push_pop(statements);
+        fold_constants(statements);
+        remove_redundant_loads(statements);
+        remove_dead_stores(statements);
+
+        if statements.len() == length {
+            break;
+        }
+    }
+}
+
+// @X is redundant when A already holds X. Nothing is known about A after a
+// label, since it may be reached from anywhere, or after a C-instruction
+// assigns to A.
+fn remove_redundant_loads(statements: &mut Vec<Statement>) {
+    let mut a_register: Option<String> = None;
+
+    statements.retain(|statement| match &statement.instruction {
+        Instruction::A(symbol) => {
+            let value = normalize(symbol);
+            if a_register.as_ref() == Some(&value) {
+                false
+            } else {
+                a_register = Some(value);
+                true
+            }
+        }
+        Instruction::L(_) => {
+            a_register = None;
+            true
+        }
+        Instruction::C { dest, .. } => {
+            if dest.contains('A') {
+                a_register = None;
+            }
+            true
+        }
+    });
+}
+
+// The VM translator pushes D and pops it straight back for most commands,
+// `push constant 7` followed by `add`, or by `pop static 0`.
+// @SP, AM=M+1, A=A-1, M=D, @SP, AM=M-1, D=M -> @SP, A=M, M=D
+// SP is back where it started, the value is still stored above the top of
+// the stack and A and D hold the same values.
+// A push also leaves A at the top of the stack, for `neg` and `not`:
+// @SP, AM=M+1, A=A-1, M=D, @SP, A=M-1 -> @SP, AM=M+1, A=A-1, M=D
+fn merge_push_pop(statements: &mut Vec<Statement>) {
+    let mut i = 0;
+
+    while i + 5 < statements.len() {
+        if is_push(&statements[i..i + 4]) && is_top(&statements[i + 4..i + 6]) {
+            statements.drain(i + 4..i + 6);
+        } else if i + 6 < statements.len() && is_push_pop(&statements[i..i + 7]) {
+            statements[i + 1].instruction = Instruction::C {
+                dest: "A".into(),
+                comp: "M".into(),
+                jump: "".into(),
+            };
+            statements[i + 2].instruction = statements[i + 3].instruction.clone();
+            statements.drain(i + 3..i + 7);
+        }
+        i += 1;
+    }
+}
+
+fn is_push_pop(window: &[Statement]) -> bool {
+    is_push(&window[..4])
+        && is_stack_pointer(&window[4])
+        && is_assignment(&window[5].instruction, "AM", "M-1")
+        && is_assignment(&window[6].instruction, "D", "M")
+}
+
+fn is_push(window: &[Statement]) -> bool {
+    is_stack_pointer(&window[0])
+        && is_assignment(&window[1].instruction, "AM", "M+1")
+        && is_assignment(&window[2].instruction, "A", "A-1")
+        && is_assignment(&window[3].instruction, "M", "D")
+}
+
+fn is_top(window: &[Statement]) -> bool {
+    is_stack_pointer(&window[0]) && is_assignment(&window[1].instruction, "A", "M-1")
+}
+
+fn is_stack_pointer(statement: &Statement) -> bool {
+    matches!(&statement.instruction, Instruction::A(symbol) if symbol == "SP")
+}
+
+// @X, D=A, @Y, D=D+A -> @X+Y, D=A, @Y
+// @X, D=A, @Y, D=D-A -> @X-Y, D=A, @Y
+// both registers end up with the same values, the result has to fit in an
+// A-instruction
+fn fold_constants(statements: &mut Vec<Statement>) {
+    let mut i = 0;
+
+    while i + 3 < statements.len() {
+        if let Some(value) = folded_constant(&statements[i..i + 4]) {
+            statements[i].instruction = Instruction::A(value.to_string().into());
+            statements.remove(i + 3);
+        }
+        i += 1;
+    }
+}
+
+fn folded_constant(window: &[Statement]) -> Option<u32> {
+    let (x, y) = match (&window[0].instruction, &window[2].instruction) {
+        (Instruction::A(x), Instruction::A(y)) => (constant(x)?, constant(y)?),
+        _ => return None,
+    };
+
+    if !is_assignment(&window[1].instruction, "D", "A") {
+        return None;
+    }
+
+    let value = if is_assignment(&window[3].instruction, "D", "D+A")
+        || is_assignment(&window[3].instruction, "D", "A+D")
+    {
+        x + y
+    } else if is_assignment(&window[3].instruction, "D", "D-A") {
+        x.checked_sub(y)?
+    } else {
+        return None;
+    };
+
+    if value > MAX_CONSTANT {
+        return None;
+    }
+
+    Some(value)
+}
+
+// A store is dead when the register is overwritten before it is read.
+// D is followed along the straight-line path until the next jump, a label
+// does not end the path since falling through to it is still the only way
+// forward. A is only dead when the next instruction loads a new address,
+// and the load stays when it is the first use of a variable, so variables
+// get the same addresses as without optimizing.
+fn remove_dead_stores(statements: &mut Vec<Statement>) {
+    let mut dead = vec![false; statements.len()];
+    let mut known = SymbolTable::new();
+    known.add_predefined_symbols();
+    for statement in statements.iter() {
+        if let Instruction::L(label) = &statement.instruction {
+            known.add_entry(label.to_string(), 0);
+        }
+    }
+
+    for (i, statement) in statements.iter().enumerate() {
+        let rest = &statements[i + 1..];
+
+        dead[i] = match &statement.instruction {
+            Instruction::A(symbol) => loads_a(rest) && !adds_variable(symbol, &mut known),
+            Instruction::C { dest, jump, .. } if jump.is_empty() && dest == "A" => loads_a(rest),
+            Instruction::C { dest, jump, .. } if jump.is_empty() && dest == "D" => {
+                overwrites_d(rest)
+            }
+            _ => false,
+        };
+    }
+
+    let mut index = 0;
+    statements.retain(|_| {
+        index += 1;
+        !dead[index - 1]
+    });
+}
+
+// Marks the symbols of an A-instruction as known, returns whether one of
+// them is a variable used for the first time.
+fn adds_variable(symbol: &str, known: &mut SymbolTable) -> bool {
+    let parsed = match expression::parse(symbol) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+    let mut added = false;
+    for (name, _) in parsed.symbols() {
+        if !known.contains(name) && !name.starts_with('.') {
+            known.add_entry(String::from(name), 0);
+            added = true;
+        }
+    }
+
+    added
+}
+
+fn loads_a(rest: &[Statement]) -> bool {
+    matches!(
+        rest.first().map(|s| &s.instruction),
+        Some(Instruction::A(_))
+    )
+}
+
+fn overwrites_d(rest: &[Statement]) -> bool {
+    for statement in rest {
+        if let Instruction::C { dest, comp, jump } = &statement.instruction {
+            if comp.contains('D') {
+                return false;
+            }
+            if dest.contains('D') {
+                return true;
+            }
+            if !jump.is_empty() {
+                return false;
+            }
+        }
+    }
+
+    false
+}
+
+// @L, <comp>;<jump>, (L) -> (L)
+// the jump also leaves L in A, so it can only go when the code after the
+// label loads a new address
+fn remove_jumps_to_next(statements: &mut Vec<Statement>) {
+    let mut i = 0;
+
+    while i + 1 < statements.len() {
+        if let (Instruction::A(target), Instruction::C { dest, jump, .. }) =
+            (&statements[i].instruction, &statements[i + 1].instruction)
+        {
+            if dest.is_empty() && !jump.is_empty() && falls_through_to(target, &statements[i + 2..])
+            {
+                statements.drain(i..i + 2);
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+fn falls_through_to(label: &str, rest: &[Statement]) -> bool {
+    let mut found = false;
+
+    for statement in rest {
+        match &statement.instruction {
+            Instruction::L(name) => found = found || name == label,
+            Instruction::A(_) => return found,
+            Instruction::C { .. } => return false,
+        }
+    }
+
+    false
+}
+
+fn is_assignment(instruction: &Instruction, register: &str, expression: &str) -> bool {
+    match instruction {
+        Instruction::C { dest, comp, jump } => {
+            dest == register && comp == expression && jump.is_empty()
+        }
+        _ => false,
+    }
+}
+
+fn constant(symbol: &str) -> Option<u32> {
+    code::literal(symbol)
+}
+
+// @007 and @7 load the same value
+fn normalize(symbol: &str) -> String {
+    match constant(symbol) {
+        Some(value) => value.to_string(),
+        None => String::from(symbol),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{parse, resolve};
+
+    fn optimized(contents: &str) -> Vec<String> {
+        let (mut statements, _) = parse(contents);
+        optimize(&mut statements);
+        statements
+            .iter()
+            .map(|s| s.instruction.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn redundant_load() {
+        let contents = "@5\nD=M\n@5\nM=D+1";
+        assert_eq!(vec!["@5", "D=M", "M=D+1"], optimized(contents));
+    }
+
+    #[test]
+    fn redundant_load_numeric() {
+        let contents = "@005\nD=M\n@5\nM=D+1";
+        assert_eq!(vec!["@005", "D=M", "M=D+1"], optimized(contents));
+    }
+
+    #[test]
+    fn redundant_load_after_label() {
+        let contents = "@5\nD=M\n(LOOP)\n@5\nM=D+1";
+        assert_eq!(
+            vec!["@5", "D=M", "(LOOP)", "@5", "M=D+1"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn redundant_load_after_a_assignment() {
+        let contents = "@SP\nA=M\nD=M\n@SP\nM=D";
+        assert_eq!(vec!["@SP", "A=M", "D=M", "@SP", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn fold_addition() {
+        let contents = "@3\nD=A\n@4\nD=D+A\n@SP\nA=M\nM=D";
+        assert_eq!(vec!["@7", "D=A", "@SP", "A=M", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn fold_subtraction() {
+        let contents = "@9\nD=A\n@4\nD=D-A\n@R13\nM=D";
+        assert_eq!(vec!["@5", "D=A", "@R13", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn fold_keeps_a() {
+        let contents = "@3\nD=A\n@4\nD=D+A\nM=D";
+        assert_eq!(vec!["@7", "D=A", "@4", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn fold_negative() {
+        let contents = "@3\nD=A\n@4\nD=D-A\nM=D";
+        assert_eq!(vec!["@3", "D=A", "@4", "D=D-A", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn fold_out_of_range() {
+        let contents = "@32767\nD=A\n@1\nD=D+A\nM=D";
+        assert_eq!(
+            vec!["@32767", "D=A", "@1", "D=D+A", "M=D"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn fold_symbols() {
+        let contents = "@foo\nD=A\n@1\nD=D+A\nM=D";
+        assert_eq!(
+            vec!["@foo", "D=A", "@1", "D=D+A", "M=D"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn fold_keeps_location() {
+        let (mut statements, _) = parse("@3\nD=A\n@4\nD=D+A\nM=D");
+
+        optimize(&mut statements);
+        assert_eq!(1, statements[0].location.line);
+        assert_eq!(5, statements[3].location.line);
+    }
+
+    #[test]
+    fn dead_store_d() {
+        let contents = "@2\nD=M\n@1\nD=A\n@3\nM=D";
+        assert_eq!(vec!["@1", "D=A", "@3", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn dead_store_d_across_label() {
+        let contents = "@2\nD=M\n(FOO)\nD=0\n@3\nM=D";
+        assert_eq!(vec!["@2", "(FOO)", "D=0", "@3", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn dead_store_d_read() {
+        let contents = "@2\nD=M\n@1\nD=D+A\n@3\nM=D";
+        assert_eq!(
+            vec!["@2", "D=M", "@1", "D=D+A", "@3", "M=D"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn dead_store_d_before_jump() {
+        let contents = "@2\nD=M\n@END\n0;JMP\nD=0\n(END)\n@END\n0;JMP";
+        assert_eq!(
+            vec!["@2", "D=M", "@END", "0;JMP", "D=0", "(END)", "@END", "0;JMP"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn dead_store_a() {
+        let contents = "@1\n@2\nD=A";
+        assert_eq!(vec!["@2", "D=A"], optimized(contents));
+    }
+
+    #[test]
+    fn dead_store_a_first_use() {
+        let contents = "@x\n@y\nM=1\n@x\n@y\nM=0";
+        assert_eq!(vec!["@x", "@y", "M=1", "M=0"], optimized(contents));
+    }
+
+    #[test]
+    fn dead_store_a_keeps_variable_order() {
+        let contents = "@x\n@y\nM=1\n@x\nM=0";
+        let (mut statements, _) = parse(contents);
+        let plain = resolve(&statements);
+
+        optimize(&mut statements);
+        let optimized = resolve(&statements);
+        assert_eq!(plain.variables, optimized.variables);
+        assert_eq!(Some(17), optimized.get_address("y"));
+    }
+
+    #[test]
+    fn push_pop() {
+        // push constant 7, pop static 0
+        let contents = "@7\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n@SP\nAM=M-1\nD=M\n@Foo.0\nM=D";
+        assert_eq!(
+            vec!["@7", "D=A", "@SP", "A=M", "M=D", "@Foo.0", "M=D"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn push_add() {
+        // push constant 7, add
+        let contents = "@7\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n@SP\nAM=M-1\nD=M\nA=A-1\nM=D+M";
+        assert_eq!(
+            vec!["@7", "D=A", "@SP", "A=M", "M=D", "A=A-1", "M=D+M"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn push_neg() {
+        let contents = "@SP\nAM=M+1\nA=A-1\nM=D\n@SP\nA=M-1\nM=-M";
+        assert_eq!(
+            vec!["@SP", "AM=M+1", "A=A-1", "M=D", "M=-M"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn push_pop_across_label() {
+        let contents = "@SP\nAM=M+1\nA=A-1\nM=D\n(L)\n@SP\nAM=M-1\nD=M";
+        assert_eq!(
+            vec!["@SP", "AM=M+1", "A=A-1", "M=D", "(L)", "@SP", "AM=M-1", "D=M"],
+            optimized(contents)
+        );
+    }
+
+    #[test]
+    fn jump_to_next() {
+        let contents = "@END\n0;JMP\n(END)\n@END\n0;JMP";
+        assert_eq!(vec!["(END)", "@END", "0;JMP"], optimized(contents));
+    }
+
+    #[test]
+    fn conditional_jump_to_next() {
+        let contents = "@NEXT\nD;JGT\n(NEXT)\n@5\nM=D";
+        assert_eq!(vec!["(NEXT)", "@5", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn jump_to_next_a_used() {
+        let contents = "@NEXT\nD;JGT\n(NEXT)\nM=D";
+        assert_eq!(vec!["@NEXT", "D;JGT", "(NEXT)", "M=D"], optimized(contents));
+    }
+
+    #[test]
+    fn jump_to_other_label() {
+        let contents = "@LOOP\n0;JMP\n(NEXT)\n@5\nM=D";
+        assert_eq!(
+            vec!["@LOOP", "0;JMP", "(NEXT)", "@5", "M=D"],
+            optimized(contents)
+        );
+    }
+}
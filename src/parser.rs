@@ -1,8 +1,22 @@
-pub struct Parser {
-    pub lines: Vec<String>,
+use std::borrow::Cow;
+use std::fmt;
+
+// Lines are slices of the source, which the parser borrows rather than
+// copies, so that large generated files are only held once.
+pub struct Parser<'a> {
+    pub lines: Vec<&'a str>,
+    pub locations: Vec<Location>,
     pub line_number: u32,
 }
 
+// 1-based position of an instruction in the source file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Location {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq)]
 pub enum InstructionType {
     AINSTRUCTION,
@@ -10,29 +24,91 @@ pub enum InstructionType {
     LINSTRUCTION,
 }
 
-impl Parser {
-    pub fn new(contents: String) -> Parser {
-        let mut lines: Vec<String> = vec![];
+// Decoded form of a single line, used by passes that rewrite the program
+// before it is encoded (see optimizer.rs). The fields borrow from the
+// source, only the instructions that passes rewrite own their text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction<'a> {
+    A(Cow<'a, str>),
+    C {
+        dest: Cow<'a, str>,
+        comp: Cow<'a, str>,
+        jump: Cow<'a, str>,
+    },
+    L(Cow<'a, str>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement<'a> {
+    pub instruction: Instruction<'a>,
+    pub location: Location,
+}
+
+impl Instruction<'_> {
+    // for keeping statements around after the source is gone
+    pub fn into_owned(self) -> Instruction<'static> {
+        let owned = |text: Cow<str>| Cow::Owned(text.into_owned());
+        match self {
+            Instruction::A(symbol) => Instruction::A(owned(symbol)),
+            Instruction::L(label) => Instruction::L(owned(label)),
+            Instruction::C { dest, comp, jump } => Instruction::C {
+                dest: owned(dest),
+                comp: owned(comp),
+                jump: owned(jump),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::A(symbol) => write!(f, "@{}", symbol),
+            Instruction::L(label) => write!(f, "({})", label),
+            Instruction::C { dest, comp, jump } => {
+                if !dest.is_empty() {
+                    write!(f, "{}=", dest)?;
+                }
+                write!(f, "{}", comp)?;
+                if !jump.is_empty() {
+                    write!(f, ";{}", jump)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
 
-        for line in contents.lines() {
-            let trimmed_line = line.trim();
-            if should_ignore(&trimmed_line) == false {
-                lines.push(String::from(trimmed_line));
+impl<'a> Parser<'a> {
+    pub fn new(contents: &'a str) -> Parser<'a> {
+        let mut lines: Vec<&'a str> = vec![];
+        let mut locations: Vec<Location> = vec![];
+
+        for (index, line) in contents.lines().enumerate() {
+            let trimmed_line = strip_comment(line).trim();
+            if !should_ignore(trimmed_line) {
+                lines.push(trimmed_line);
+                locations.push(Location {
+                    line: index as u32 + 1,
+                    column: (line.len() - line.trim_start().len()) as u32 + 1,
+                });
             }
         }
 
         Parser {
             lines,
+            locations,
             line_number: 0,
         }
     }
 
     pub fn instruction_type(&self) -> InstructionType {
-        let line = &self.lines[self.line_number as usize];
+        let line = self.lines[self.line_number as usize];
 
+        // `(LOOP` without `)` is left for check() to report
         if line.as_bytes()[0] == b'@' {
             return InstructionType::AINSTRUCTION;
-        } else if line.as_bytes()[0] == b'(' {
+        } else if line.starts_with('(') && line.ends_with(')') {
             return InstructionType::LINSTRUCTION;
         }
 
@@ -40,11 +116,11 @@ impl Parser {
     }
 
     pub fn has_more_lines(&self) -> bool {
-        return self.line_number + 1 < self.lines.len() as u32;
+        self.line_number + 1 < (self.lines.len() as u32)
     }
 
     pub fn advance(&mut self) {
-        if self.has_more_lines() == false {
+        if !self.has_more_lines() {
             return;
         }
 
@@ -54,84 +130,112 @@ impl Parser {
     // @17 -> 17
     // @sum -> sum
     // (LOOP) -> LOOP
-    pub fn symbol(&self) -> String {
-        let line = &self.lines[self.line_number as usize];
+    pub fn symbol(&self) -> &'a str {
+        let line = self.lines[self.line_number as usize];
 
         if line.as_bytes()[0] == b'@' {
-            return String::from(line.get(1..).unwrap());
+            return line.get(1..).unwrap();
         }
 
         if line.as_bytes()[0] == b'(' {
             let length = line.len();
-            return String::from(line.get(1..length - 1).unwrap());
+            return line.get(1..length - 1).unwrap();
         }
 
-        String::from("") // TODO: return error
+        "" // TODO: return error
     }
 
-    pub fn dest(&self) -> String {
-        let line = &self.lines[self.line_number as usize];
+    // the fields are split off without collecting the parts, as this
+    // runs for every line
+    pub fn dest(&self) -> &'a str {
+        let line = self.lines[self.line_number as usize];
 
-        let mut symbols: Vec<&str> = vec![];
-
-        if line.find("=").is_some() {
-            symbols = line.split("=").collect();
-        }
-
-        if symbols.is_empty() {
-            return String::from(""); // TODO: return error
+        match line.split('=').next() {
+            Some(dest) if line.contains('=') => dest,
+            _ => "", // TODO: return error
         }
-
-        String::from(symbols[0])
     }
 
-    pub fn comp(&self) -> String {
-        let line = &self.lines[self.line_number as usize];
+    pub fn comp(&self) -> &'a str {
+        let line = self.lines[self.line_number as usize];
 
-        let mut result = String::from("");
+        // D
+        let mut result = line;
 
         let dest = line.find("=");
         let jump = line.find(";");
 
         if dest.is_some() && jump.is_some() {
             // D=D+1;JLE
-            let symbols_dest: Vec<&str> = line.split("=").collect();
-            let tmp = String::from(symbols_dest[1]); // D+1;JLE
-            let symbols_jump: Vec<&str> = tmp.split(";").collect();
-            result = String::from(symbols_jump[0]);
+            let after_dest = line.split('=').nth(1).unwrap_or_default(); // D+1;JLE
+            result = after_dest.split(';').next().unwrap_or_default();
         } else if dest.is_some() {
             //D=D-M
-            let symbols: Vec<&str> = line.split("=").collect();
-            result = String::from(symbols[1]);
+            result = line.split('=').nth(1).unwrap_or_default();
         } else if jump.is_some() {
             // D;JGT
-            let symbols: Vec<&str> = line.split(";").collect();
-            result = String::from(symbols[0]);
+            result = line.split(';').next().unwrap_or_default();
         }
 
         result
     }
 
-    pub fn jump(&self) -> String {
-        let line = &self.lines[self.line_number as usize];
+    pub fn jump(&self) -> &'a str {
+        let line = self.lines[self.line_number as usize];
 
-        let mut symbols: Vec<&str> = vec![];
+        match line.split(';').next_back() {
+            Some(jump) if line.contains(';') => jump,
+            _ => "", // TODO: return error
+        }
+    }
 
-        if line.find(";").is_some() {
-            symbols = line.split(";").collect();
+    pub fn location(&self) -> Location {
+        self.locations[self.line_number as usize]
+    }
+
+    pub fn statement(&self) -> Statement<'a> {
+        Statement {
+            instruction: self.instruction(),
+            location: self.location(),
         }
+    }
 
-        if symbols.is_empty() {
-            return String::from(""); // TODO: return error
+    pub fn instruction(&self) -> Instruction<'a> {
+        match self.instruction_type() {
+            InstructionType::AINSTRUCTION => Instruction::A(self.symbol().into()),
+            InstructionType::LINSTRUCTION => Instruction::L(self.symbol().into()),
+            InstructionType::CINSTRUCTION => Instruction::C {
+                dest: self.dest().into(),
+                comp: self.comp().into(),
+                jump: self.jump().into(),
+            },
         }
+    }
+}
 
-        String::from(symbols[symbols.len() - 1])
+// D=M // comment -> D=M, `//` inside strings and characters is kept
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if line[i..].starts_with("//") => return &line[..i],
+            None => {}
+        }
     }
+
+    line
 }
 
 fn should_ignore(line: &str) -> bool {
     // ignore empty lines
-    if line.len() < 1 {
+    if line.is_empty() {
         return true;
     }
 
@@ -150,7 +254,7 @@ mod tests {
     #[test]
     fn ignore_empty_file() {
         let input = String::from("");
-        let parser = Parser::new(input);
+        let parser = Parser::new(&input);
         assert_eq!(0, parser.lines.len());
     }
 
@@ -163,14 +267,14 @@ mod tests {
 
 ",
         );
-        let parser = Parser::new(input);
+        let parser = Parser::new(&input);
         assert_eq!(0, parser.lines.len());
     }
 
     #[test]
     fn remove_whitespaces() {
         let input = String::from("  D=M ");
-        let parser = Parser::new(input);
+        let parser = Parser::new(&input);
         assert_eq!(1, parser.lines.len());
         assert_eq!("D=M", parser.lines[0]);
     }
@@ -178,7 +282,7 @@ mod tests {
     #[test]
     fn remove_comment() {
         let input = String::from("// Comment");
-        let parser = Parser::new(input);
+        let parser = Parser::new(&input);
         assert_eq!(0, parser.lines.len());
     }
 
@@ -190,7 +294,7 @@ mod tests {
 
 @8",
         );
-        let parser = Parser::new(input);
+        let parser = Parser::new(&input);
         assert_eq!(2, parser.lines.len());
         assert_eq!("D=M", parser.lines[0]);
         assert_eq!("@8", parser.lines[1]);
@@ -204,38 +308,52 @@ mod tests {
 @8
 // Another comment",
         );
-        let parser = Parser::new(input);
+        let parser = Parser::new(&input);
         assert_eq!(2, parser.lines.len());
         assert_eq!("D=M", parser.lines[0]);
         assert_eq!("@8", parser.lines[1]);
     }
 
+    #[test]
+    fn remove_inline_comments() {
+        let input = String::from("@R0 // first\n  D=M//x\n.word \"a // b\" // c\n@'/' // slash");
+        let parser = Parser::new(&input);
+        assert_eq!(vec!["@R0", "D=M", ".word \"a // b\"", "@'/'"], parser.lines);
+        assert_eq!(3, parser.locations[1].column);
+    }
+
+    #[test]
+    fn unclosed_label() {
+        let parser = Parser::new("(LOOP");
+        assert_eq!(InstructionType::CINSTRUCTION, parser.instruction_type());
+    }
+
     #[test]
     fn symbol_number() {
         let contents = String::from("@8");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("8", parser.symbol());
     }
 
     #[test]
     fn symbol_text() {
         let contents = String::from("@sum");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("sum", parser.symbol());
     }
 
     #[test]
     fn symbol_label() {
         let contents = String::from("(LOOP)");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("LOOP", parser.symbol());
     }
 
     #[test]
     fn has_more_lines_one_line() {
         let contents = String::from("@foo");
-        let parser = Parser::new(contents);
-        assert_eq!(false, parser.has_more_lines());
+        let parser = Parser::new(&contents);
+        assert!(!parser.has_more_lines());
     }
 
     #[test]
@@ -244,8 +362,8 @@ mod tests {
             "@foo
 @bar",
         );
-        let parser = Parser::new(contents);
-        assert_eq!(true, parser.has_more_lines());
+        let parser = Parser::new(&contents);
+        assert!(parser.has_more_lines());
     }
 
     #[test]
@@ -254,65 +372,65 @@ mod tests {
             "@foo
 @bar",
         );
-        let mut parser = Parser::new(contents);
-        assert_eq!(true, parser.has_more_lines());
+        let mut parser = Parser::new(&contents);
+        assert!(parser.has_more_lines());
         parser.advance();
-        assert_eq!(false, parser.has_more_lines());
+        assert!(!parser.has_more_lines());
     }
 
     #[test]
     fn ignore_non_symbols() {
         let contents = String::from("D=M");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("", parser.symbol());
     }
 
     #[test]
     fn return_ainstruction_number() {
         let contents = String::from("@8");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!(InstructionType::AINSTRUCTION, parser.instruction_type());
     }
 
     #[test]
     fn return_ainstruction_symbol() {
         let contents = String::from("@foo");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!(InstructionType::AINSTRUCTION, parser.instruction_type());
     }
 
     #[test]
     fn return_linstruction() {
         let contents = String::from("(FOO)");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!(InstructionType::LINSTRUCTION, parser.instruction_type());
     }
 
     #[test]
     fn return_cinstruction_() {
         let contents = String::from("D=M");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!(InstructionType::CINSTRUCTION, parser.instruction_type());
     }
 
     #[test]
     fn dest_01() {
         let contents = String::from("D=M");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("D", parser.dest());
     }
 
     #[test]
     fn dest_02() {
         let contents = String::from("0;JMP");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("", parser.dest());
     }
 
     #[test]
     fn dest_03() {
         let contents = String::from("@foo");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("", parser.dest());
     }
 
@@ -324,7 +442,7 @@ mod tests {
 @foo
 M=M+D",
         );
-        let mut parser = Parser::new(contents);
+        let mut parser = Parser::new(&contents);
         assert_eq!("D", parser.dest());
         parser.advance(); // @foo
         assert_eq!("", parser.dest()); // wrong instruction
@@ -335,63 +453,107 @@ M=M+D",
     #[test]
     fn comp_01() {
         let contents = String::from("D=M");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("M", parser.comp());
     }
 
     #[test]
     fn comp_02() {
         let contents = String::from("D=D-M");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("D-M", parser.comp());
     }
 
     #[test]
     fn comp_03() {
         let contents = String::from("0;JMP");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("0", parser.comp());
     }
 
     #[test]
     fn comp_04() {
         let contents = String::from("D=M;JMP");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("M", parser.comp());
     }
 
     #[test]
     fn comp_05() {
         let contents = String::from("D=D+1;JLE");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("D+1", parser.comp());
     }
 
+    #[test]
+    fn comp_06() {
+        let contents = String::from("D");
+        let parser = Parser::new(&contents);
+        assert_eq!("D", parser.comp());
+    }
+
     #[test]
     fn jump_01() {
         let contents = String::from("@foo");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("", parser.jump());
     }
 
     #[test]
     fn jump_02() {
         let contents = String::from("D=M");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("", parser.jump());
     }
 
     #[test]
     fn jump_03() {
         let contents = String::from("0;JEQ");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("JEQ", parser.jump());
     }
 
+    #[test]
+    fn instruction_c() {
+        let contents = String::from("D=D+1;JLE");
+        let parser = Parser::new(&contents);
+        let instruction = parser.instruction();
+        assert_eq!(
+            Instruction::C {
+                dest: "D".into(),
+                comp: "D+1".into(),
+                jump: "JLE".into(),
+            },
+            instruction
+        );
+        assert_eq!("D=D+1;JLE", instruction.to_string());
+    }
+
+    #[test]
+    fn instruction_label() {
+        let contents = String::from("(LOOP)");
+        let parser = Parser::new(&contents);
+        assert_eq!(Instruction::L("LOOP".into()), parser.instruction());
+    }
+
+    #[test]
+    fn location() {
+        let contents = String::from(
+            "// Comment
+
+   @foo
+	D=M",
+        );
+        let mut parser = Parser::new(&contents);
+        assert_eq!(Location { line: 3, column: 4 }, parser.location());
+        parser.advance();
+        assert_eq!(Location { line: 4, column: 2 }, parser.location());
+    }
+
     #[test]
     fn dest_and_jmp() {
         let contents = String::from("D=D+1;JLE");
-        let parser = Parser::new(contents);
+        let parser = Parser::new(&contents);
         assert_eq!("D", parser.dest());
         assert_eq!("JLE", parser.jump());
     }
@@ -0,0 +1,230 @@
+use crate::code;
+use crate::parser::Instruction;
+
+pub const MNEMONICS: [&str; 7] = ["GOTO", "LOAD", "MOV", "PUSH", "POP", "HALT", ".jumptable"];
+
+// The parser reads a pseudo-op as a C-instruction without dest and jump,
+// `GOTO LOOP` has the comp `GOTO LOOP`.
+// JEQ D, LOOP -> Some(("JEQ", ["D", "LOOP"]))
+fn split<'a>(instruction: &'a Instruction) -> Option<(&'a str, Vec<&'a str>)> {
+    let comp = match instruction {
+        Instruction::C { dest, comp, jump } if dest.is_empty() && jump.is_empty() => comp,
+        _ => return None,
+    };
+
+    let (mnemonic, operands) = match comp.split_once(char::is_whitespace) {
+        Some((mnemonic, operands)) => (mnemonic, operands),
+        None => (comp.as_ref(), ""),
+    };
+    if !MNEMONICS.contains(&mnemonic) && !is_jump(mnemonic) {
+        return None;
+    }
+
+    let operands = operands
+        .split(',')
+        .map(|operand| operand.trim())
+        .filter(|operand| !operand.is_empty())
+        .collect();
+    Some((mnemonic, operands))
+}
+
+fn is_jump(mnemonic: &str) -> bool {
+    mnemonic != "JMP" && code::lookup(&code::JUMP, mnemonic).is_some()
+}
+
+// expansions own their text, the operands only live as long as the
+// pseudo-op
+fn a(symbol: &str) -> Instruction<'static> {
+    Instruction::A(String::from(symbol).into())
+}
+
+fn c(dest: &str, comp: &str, jump: &str) -> Instruction<'static> {
+    Instruction::C {
+        dest: String::from(dest).into(),
+        comp: String::from(comp).into(),
+        jump: String::from(jump).into(),
+    }
+}
+
+// a register or memory operand other than A, which the expansions use
+fn is_symbol(operand: &str) -> bool {
+    !operand.is_empty() && operand != "A" && operand != "D" && operand != "M"
+}
+
+// Returns the real instructions for a pseudo-op, None for anything else.
+// `line` makes the label HALT loops on unique, it starts with `:` to stay
+// local to the file like the anonymous labels.
+pub fn expand(
+    instruction: &Instruction,
+    line: u32,
+) -> Option<Result<Vec<Instruction<'static>>, String>> {
+    let (mnemonic, operands) = split(instruction)?;
+
+    let expansion = match (mnemonic, operands.as_slice()) {
+        // GOTO LOOP -> @LOOP, 0;JMP
+        ("GOTO", [label]) if is_symbol(label) => Ok(vec![a(label), c("", "0", "JMP")]),
+        ("GOTO", _) => Err(String::from("`GOTO` expects a label")),
+
+        // JEQ D, LOOP -> @LOOP, D;JEQ, the comp must not read A or M as
+        // the label replaces A
+        (jump, [comp, label]) if is_jump(jump) => {
+            if code::lookup(&code::COMP, comp).is_none() || comp.contains(['A', 'M']) {
+                Err(format!("`{}` cannot test `{}`", jump, comp))
+            } else if !is_symbol(label) {
+                Err(format!("`{}` expects a label", jump))
+            } else {
+                Ok(vec![a(label), c("", comp, jump)])
+            }
+        }
+        (jump, _) if is_jump(jump) => Err(format!("`{}` expects a comp and a label", jump)),
+
+        // LOAD D, 123 -> @123, D=A
+        ("LOAD", ["A", value]) if is_symbol(value) => Ok(vec![a(value)]),
+        ("LOAD", ["D", value]) if is_symbol(value) => Ok(vec![a(value), c("D", "A", "")]),
+        ("LOAD", _) => Err(String::from("`LOAD` expects `A` or `D` and a value")),
+
+        // MOV R1, R2 -> @R2, D=M, @R1, M=D
+        ("MOV", ["D", source]) if is_symbol(source) => Ok(vec![a(source), c("D", "M", "")]),
+        ("MOV", [target, "D"]) if is_symbol(target) => Ok(vec![a(target), c("M", "D", "")]),
+        ("MOV", [target, source]) if is_symbol(target) && is_symbol(source) => {
+            Ok(vec![a(source), c("D", "M", ""), a(target), c("M", "D", "")])
+        }
+        ("MOV", _) => Err(String::from("`MOV` expects a target and a source")),
+
+        ("PUSH", ["D"]) => Ok(vec![
+            a("SP"),
+            c("AM", "M+1", ""),
+            c("A", "A-1", ""),
+            c("M", "D", ""),
+        ]),
+        ("PUSH", _) => Err(String::from("`PUSH` expects `D`")),
+        ("POP", ["D"]) => Ok(vec![a("SP"), c("AM", "M-1", ""), c("D", "M", "")]),
+        ("POP", _) => Err(String::from("`POP` expects `D`")),
+
+        // (:halt.7), @:halt.7, 0;JMP
+        ("HALT", []) => {
+            let label = format!(":halt.{}", line);
+            Ok(vec![
+                Instruction::L(label.clone().into()),
+                a(&label),
+                c("", "0", "JMP"),
+            ])
+        }
+        ("HALT", _) => Err(String::from("`HALT` takes no operands")),
+
+        // .jumptable A, B -> @A, 0;JMP, @B, 0;JMP, two words per entry so
+        // that jumping to TABLE+2*i reaches the i-th target
+        (".jumptable", targets) if !targets.is_empty() && targets.iter().all(|t| is_symbol(t)) => {
+            Ok(targets
+                .iter()
+                .flat_map(|target| [a(target), c("", "0", "JMP")])
+                .collect())
+        }
+        (".jumptable", _) => Err(String::from("`.jumptable` expects labels")),
+
+        _ => return None,
+    };
+
+    Some(expansion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn expanded(line: &str) -> Option<Result<Vec<String>, String>> {
+        let instruction = Parser::new(line).instruction();
+        expand(&instruction, 7)
+            .map(|expansion| expansion.map(|list| list.iter().map(|i| i.to_string()).collect()))
+    }
+
+    #[test]
+    fn goto() {
+        assert_eq!(
+            Some(Ok(vec![s("@LOOP"), s("0;JMP")])),
+            expanded("GOTO LOOP")
+        );
+    }
+
+    #[test]
+    fn conditional_jumps() {
+        assert_eq!(
+            Some(Ok(vec![s("@END"), s("D;JEQ")])),
+            expanded("JEQ D, END")
+        );
+        assert_eq!(
+            Some(Ok(vec![s("@END"), s("D-1;JLE")])),
+            expanded("JLE D-1,END")
+        );
+        assert_eq!(
+            Some(Err(s("`JGT` cannot test `M`"))),
+            expanded("JGT M, END")
+        );
+        assert_eq!(
+            Some(Err(s("`JNE` expects a comp and a label"))),
+            expanded("JNE END")
+        );
+    }
+
+    #[test]
+    fn load_and_mov() {
+        assert_eq!(Some(Ok(vec![s("@123"), s("D=A")])), expanded("LOAD D, 123"));
+        assert_eq!(Some(Ok(vec![s("@SCREEN")])), expanded("LOAD A, SCREEN"));
+        assert_eq!(
+            Some(Ok(vec![s("@R2"), s("D=M"), s("@R1"), s("M=D")])),
+            expanded("MOV R1, R2")
+        );
+        assert_eq!(Some(Ok(vec![s("@i"), s("D=M")])), expanded("MOV D, i"));
+        assert_eq!(Some(Ok(vec![s("@i"), s("M=D")])), expanded("MOV i, D"));
+        assert_eq!(
+            Some(Err(s("`MOV` expects a target and a source"))),
+            expanded("MOV A, R1")
+        );
+        assert_eq!(
+            Some(Err(s("`LOAD` expects `A` or `D` and a value"))),
+            expanded("LOAD M, 1")
+        );
+    }
+
+    #[test]
+    fn stack_and_halt() {
+        assert_eq!(
+            Some(Ok(vec![s("@SP"), s("AM=M+1"), s("A=A-1"), s("M=D")])),
+            expanded("PUSH D")
+        );
+        assert_eq!(
+            Some(Ok(vec![s("@SP"), s("AM=M-1"), s("D=M")])),
+            expanded("POP D")
+        );
+        assert_eq!(
+            Some(Ok(vec![s("(:halt.7)"), s("@:halt.7"), s("0;JMP")])),
+            expanded("HALT")
+        );
+        assert_eq!(Some(Err(s("`PUSH` expects `D`"))), expanded("PUSH A"));
+    }
+
+    #[test]
+    fn jump_table() {
+        assert_eq!(
+            Some(Ok(vec![s("@ADD"), s("0;JMP"), s("@SUB"), s("0;JMP")])),
+            expanded(".jumptable ADD, SUB")
+        );
+        assert_eq!(
+            Some(Err(s("`.jumptable` expects labels"))),
+            expanded(".jumptable")
+        );
+    }
+
+    #[test]
+    fn not_pseudo() {
+        assert_eq!(None, expanded("D=M"));
+        assert_eq!(None, expanded("0;JMP"));
+        assert_eq!(None, expanded("@GOTO"));
+        assert_eq!(None, expanded("FOO BAR"));
+    }
+
+    fn s(text: &str) -> String {
+        String::from(text)
+    }
+}
@@ -0,0 +1,106 @@
+use crate::diagnostic::Diagnostic;
+use crate::json::Value;
+
+// Diagnostics as a SARIF 2.1.0 log, for `--format sarif`, which code
+// scanning dashboards read. The error that stopped the build, if any, is
+// a notification of the invocation.
+pub fn document(file: &str, diagnostics: &[Diagnostic], error: Option<&str>) -> Value {
+    let results: Vec<Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let notes: Vec<Value> = diagnostic
+                .notes
+                .iter()
+                .map(|note| {
+                    let note_file = note.file.as_deref();
+                    location(note_file.unwrap_or(file_of(diagnostic, file)), note)
+                        .with("message", message(&note.message))
+                })
+                .collect();
+            Value::object()
+                .with("level", diagnostic.severity.name())
+                .with("message", message(&diagnostic.message))
+                .with(
+                    "locations",
+                    vec![location(file_of(diagnostic, file), diagnostic)],
+                )
+                .with("relatedLocations", notes)
+        })
+        .collect();
+
+    let notifications: Vec<Value> = error
+        .iter()
+        .map(|error| {
+            Value::object()
+                .with("level", "error")
+                .with("message", message(error))
+        })
+        .collect();
+    let invocation = Value::object()
+        .with("executionSuccessful", error.is_none())
+        .with("toolExecutionNotifications", notifications);
+
+    let driver = Value::object()
+        .with("name", "assembler")
+        .with("version", env!("CARGO_PKG_VERSION"));
+    let run = Value::object()
+        .with("tool", Value::object().with("driver", driver))
+        .with("results", results)
+        .with("invocations", vec![invocation]);
+    Value::object()
+        .with("version", "2.1.0")
+        .with("$schema", "https://json.schemastore.org/sarif-2.1.0.json")
+        .with("runs", vec![run])
+}
+
+fn file_of<'a>(diagnostic: &'a Diagnostic, file: &'a str) -> &'a str {
+    diagnostic.file.as_deref().unwrap_or(file)
+}
+
+fn message(text: &str) -> Value {
+    Value::object().with("text", text)
+}
+
+// SARIF columns count from 1 like ours, the region ends after the
+// underlined part
+fn location(file: &str, diagnostic: &Diagnostic) -> Value {
+    let region = Value::object()
+        .with("startLine", diagnostic.location.line)
+        .with("startColumn", diagnostic.location.column)
+        .with(
+            "endColumn",
+            diagnostic.location.column + diagnostic.length.max(1),
+        );
+    let physical = Value::object()
+        .with("artifactLocation", Value::object().with("uri", file))
+        .with("region", region);
+    Value::object().with("physicalLocation", physical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Location;
+
+    #[test]
+    fn results() {
+        let at = |line, column| Location { line, column };
+        let error = Diagnostic::error(at(2, 3), 3, String::from("label `A` is defined twice"))
+            .with_note(at(1, 2), 1, String::from("first defined here"));
+        let document = document("Max.asm", &[error], Some("could not assemble"));
+
+        let run = &document.get("runs").unwrap().as_array().unwrap()[0];
+        let result = &run.get("results").unwrap().as_array().unwrap()[0];
+        assert_eq!(
+            "{\"level\":\"error\",\"message\":{\"text\":\"label `A` is defined twice\"},\"locations\":[{\"physicalLocation\":{\"artifactLocation\":{\"uri\":\"Max.asm\"},\"region\":{\"startLine\":2,\"startColumn\":3,\"endColumn\":6}}}],\"relatedLocations\":[{\"physicalLocation\":{\"artifactLocation\":{\"uri\":\"Max.asm\"},\"region\":{\"startLine\":1,\"startColumn\":2,\"endColumn\":3}},\"message\":{\"text\":\"first defined here\"}}]}",
+            result.to_string()
+        );
+        let invocation = &run.get("invocations").unwrap().as_array().unwrap()[0];
+        assert_eq!(
+            Some(false),
+            invocation
+                .get("executionSuccessful")
+                .and_then(Value::as_bool)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub const SCREEN: usize = 16384;
+pub const KBD: usize = 24576;
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+// every row of the screen is 32 words, bit 0 of a word is its leftmost pixel
+const WORDS_PER_ROW: usize = WIDTH / 16;
+
+// Packs the screen memory map in rows of bytes, leftmost pixel in the most
+// significant bit and 1 for black, as both PBM and PNG store it.
+fn rows(ram: &[u16]) -> Vec<Vec<u8>> {
+    let mut rows = vec![];
+
+    for row in 0..HEIGHT {
+        let start = SCREEN + row * WORDS_PER_ROW;
+        let mut bytes = Vec::with_capacity(WIDTH / 8);
+        for word in &ram[start..start + WORDS_PER_ROW] {
+            let pixels = word.reverse_bits();
+            bytes.push((pixels >> 8) as u8);
+            bytes.push(pixels as u8);
+        }
+        rows.push(bytes);
+    }
+
+    rows
+}
+
+// binary portable bitmap
+pub fn pbm(ram: &[u16]) -> Vec<u8> {
+    let mut image = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for row in rows(ram) {
+        image.extend(row);
+    }
+    image
+}
+
+// 1-bit grayscale PNG, where 0 is black, so the pixels are inverted
+pub fn png(ram: &[u16]) -> Vec<u8> {
+    let mut data = vec![];
+    for row in rows(ram) {
+        data.push(0); // no filter
+        data.extend(row.iter().map(|byte| !byte));
+    }
+
+    let mut header = vec![];
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    header.extend([1, 0, 0, 0, 0]); // depth, grayscale, deflate, filter, no interlace
+
+    let mut image = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    chunk(&mut image, b"IHDR", &header);
+    chunk(&mut image, b"IDAT", &zlib(&data));
+    chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+// Writes a snapshot of the screen, the format follows the extension.
+pub fn save(filename: &str, ram: &[u16]) -> Result<(), Box<dyn Error>> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    let image = match extension.as_deref() {
+        Some("pbm") => pbm(ram),
+        Some("png") => png(ram),
+        _ => return Err(format!("{}: snapshots must be .pbm or .png", filename).into()),
+    };
+    fs::write(filename, image)?;
+    Ok(())
+}
+
+fn chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend((data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend(kind);
+    image.extend(data);
+    let crc = crc32(&image[start..]);
+    image.extend(crc.to_be_bytes());
+}
+
+// zlib stream made of stored deflate blocks, the screen is small enough
+// not to bother compressing it
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+
+    for (i, block) in blocks.iter().enumerate() {
+        let last = i + 1 == blocks.len();
+        stream.push(last as u8);
+        stream.extend((block.len() as u16).to_le_bytes());
+        stream.extend((!(block.len() as u16)).to_le_bytes());
+        stream.extend(*block);
+    }
+
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::RAM_SIZE;
+
+    fn ram() -> Vec<u16> {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[SCREEN] = 0x0001; // top left pixel
+        ram[SCREEN + 31] = 0x8000; // top right pixel
+        ram[SCREEN + 32] = 0x00ff; // leftmost 8 pixels of the second row
+        ram
+    }
+
+    #[test]
+    fn pbm_pixels() {
+        let image = pbm(&ram());
+        let header = b"P4\n512 256\n";
+        assert_eq!(header, &image[..header.len()]);
+        assert_eq!(header.len() + WIDTH * HEIGHT / 8, image.len());
+
+        let pixels = &image[header.len()..];
+        assert_eq!(0x80, pixels[0]);
+        assert_eq!(0x00, pixels[1]);
+        assert_eq!(0x01, pixels[63]);
+        assert_eq!(0xff, pixels[64]);
+        assert_eq!(0x00, pixels[65]);
+    }
+
+    #[test]
+    fn png_chunks() {
+        let image = png(&ram());
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &image[..8]);
+        assert_eq!(b"IHDR", &image[12..16]);
+        assert_eq!([0, 0, 2, 0, 0, 0, 1, 0, 1], image[16..25]);
+        // IEND with its well known CRC
+        assert_eq!(
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82],
+            image[image.len() - 12..]
+        );
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        assert_eq!(0x11e60398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let data = vec![7; 0x10000];
+        let stream = zlib(&data);
+        // header, two blocks of 5 bytes of framing each, checksum
+        assert_eq!(2 + 5 + 0xffff + 5 + 1 + 4, stream.len());
+        assert_eq!(0, stream[2]);
+        assert_eq!([1, 1, 0, 0xfe, 0xff, 7], stream[2 + 5 + 0xffff..][..6]);
+    }
+
+    #[test]
+    fn save_unknown_format() {
+        assert!(save("screen.bmp", &ram()).is_err());
+    }
+}
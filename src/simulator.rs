@@ -0,0 +1,631 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::assembler;
+use crate::code;
+use crate::config::RunConfig;
+use crate::data;
+use crate::jack_compiler;
+use crate::json;
+use crate::keyboard::Timeline;
+use crate::parser::Location;
+use crate::screen;
+use crate::symbol_table::{Layout, SymbolTable};
+use crate::vm;
+
+// the data memory is addressed with 15 bits, SCREEN and KBD included
+pub const RAM_SIZE: usize = 32768;
+
+// Hack CPU with its ROM and RAM. Every step executes a single instruction,
+// a C-instruction reads and jumps with the value A had before it.
+pub struct Computer {
+    pub rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+}
+
+impl Computer {
+    pub fn new(rom: Vec<u16>) -> Computer {
+        Computer {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.ram = vec![0; RAM_SIZE];
+        self.a = 0;
+        self.d = 0;
+        self.pc = 0;
+        self.cycles = 0;
+    }
+
+    // RAM contents from data directives, applied after every reset
+    pub fn preload(&mut self, image: &[(u16, u16)]) {
+        for (address, word) in image {
+            self.ram[*address as usize % RAM_SIZE] = *word;
+        }
+    }
+
+    pub fn m(&self) -> u16 {
+        self.ram[self.a as usize % RAM_SIZE]
+    }
+
+    // Executes the instruction at PC, returns the RAM address it wrote to.
+    pub fn step(&mut self) -> Option<u16> {
+        let instruction = match self.rom.get(self.pc as usize) {
+            Some(instruction) => *instruction,
+            None => return None,
+        };
+        self.cycles += 1;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1);
+            return None;
+        }
+
+        let y = if instruction & 0x1000 != 0 {
+            self.m()
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, (instruction >> 6) & 0x3f);
+
+        let address = self.a;
+        let mut written = None;
+
+        if instruction & 0x08 != 0 {
+            self.ram[address as usize % RAM_SIZE] = out;
+            written = Some(address % RAM_SIZE as u16);
+        }
+        if instruction & 0x20 != 0 {
+            self.a = out;
+        }
+        if instruction & 0x10 != 0 {
+            self.d = out;
+        }
+
+        if jumps(out, instruction & 0x07) {
+            self.pc = address;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+
+        written
+    }
+
+    // Past the end of the program, or in the `(END) @END 0;JMP` loop
+    // programs finish with.
+    pub fn halted(&self) -> bool {
+        let pc = self.pc as usize;
+
+        match (self.rom.get(pc), self.rom.get(pc + 1)) {
+            (None, _) => true,
+            (Some(load), Some(jump)) => {
+                // @pc, <comp>;JMP
+                *load as usize == pc && *jump & 0xe03f == 0xe007
+            }
+            _ => false,
+        }
+    }
+}
+
+// Runs a program without the debugger, for scripts and CI, feeding it the
+// keyboard timeline and saving screen snapshots along the way.
+pub fn run(config: RunConfig) -> Result<(), Box<dyn Error>> {
+    let program = Program::load(&config.filename)?;
+    let mut timeline = match &config.keys {
+        Some(keys) => Timeline::parse(&fs::read_to_string(keys)?)?,
+        None => Timeline::parse("")?,
+    };
+    let mut snapshots = config.snapshots.clone();
+    snapshots.sort_by_key(|(cycle, _)| *cycle);
+    let mut snapshots = snapshots.into_iter().peekable();
+
+    let mut computer = Computer::new(program.rom);
+    computer.preload(&program.ram);
+    while !computer.halted() && computer.cycles < config.cycles {
+        if let Some(key) = timeline.key_at(computer.cycles) {
+            computer.ram[screen::KBD] = key;
+        }
+        while let Some((_, file)) = snapshots.next_if(|(cycle, _)| *cycle <= computer.cycles) {
+            screen::save(&file, &computer.ram)?;
+        }
+        computer.step();
+    }
+
+    // snapshots past the end show the final screen
+    for (_, file) in snapshots {
+        screen::save(&file, &computer.ram)?;
+    }
+    if let Some(file) = &config.screen {
+        screen::save(file, &computer.ram)?;
+    }
+
+    if computer.halted() {
+        println!("Halted after {} cycles", computer.cycles);
+    } else {
+        println!("Stopped after {} cycles", computer.cycles);
+    }
+
+    Ok(())
+}
+
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let mut x = x;
+    let mut y = y;
+
+    if control & 0x20 != 0 {
+        x = 0;
+    }
+    if control & 0x10 != 0 {
+        x = !x;
+    }
+    if control & 0x08 != 0 {
+        y = 0;
+    }
+    if control & 0x04 != 0 {
+        y = !y;
+    }
+
+    let mut out = if control & 0x02 != 0 {
+        x.wrapping_add(y)
+    } else {
+        x & y
+    };
+
+    if control & 0x01 != 0 {
+        out = !out;
+    }
+
+    out
+}
+
+fn jumps(out: u16, condition: u16) -> bool {
+    let value = out as i16;
+
+    match condition {
+        0b001 => value > 0,
+        0b010 => value == 0,
+        0b011 => value >= 0,
+        0b100 => value < 0,
+        0b101 => value != 0,
+        0b110 => value <= 0,
+        0b111 => true,
+        _ => false,
+    }
+}
+
+// .hack file, one 16 character binary word per line
+pub fn load(contents: &str) -> Result<Vec<u16>, String> {
+    let mut rom = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let valid = line.len() == 16 && line.bytes().all(|b| b == b'0' || b == b'1');
+        match u16::from_str_radix(line, 2) {
+            Ok(word) if valid => rom.push(word),
+            _ => return Err(format!("invalid instruction on line {}", index + 1)),
+        }
+    }
+
+    Ok(rom)
+}
+
+// Turns a word back into assembly, using the first mnemonic from the
+// encoding tables when several mean the same thing.
+pub fn disassemble(word: u16) -> String {
+    if word & 0x8000 == 0 {
+        return format!("@{}", word);
+    }
+
+    let binary = format!("{:016b}", word);
+    let comp = code::mnemonic(&code::COMP, &binary[3..10]).unwrap_or("?");
+    let dest = code::mnemonic(&code::DEST, &binary[10..13]).unwrap_or("null");
+    let jump = code::mnemonic(&code::JUMP, &binary[13..16]).unwrap_or("null");
+
+    let mut result = String::new();
+    if dest != "null" {
+        result.push_str(dest);
+        result.push('=');
+    }
+    result.push_str(comp);
+    if jump != "null" {
+        result.push(';');
+        result.push_str(jump);
+    }
+
+    result
+}
+
+// ROM image with what is known about where it came from. `locations` and
+// `source` are empty when a .hack file has no source map next to it.
+pub struct Program {
+    pub rom: Vec<u16>,
+    pub symbol_table: SymbolTable,
+    pub locations: Vec<Location>,
+    pub source: Vec<String>,
+    pub ram: Vec<(u16, u16)>,
+}
+
+// the VM code as assembly, the errors, if any, in the message
+fn translate(path: &str) -> Result<String, Box<dyn Error>> {
+    let mut diagnostics = vec![];
+    vm::translate(path, &mut diagnostics).map_err(|error| {
+        let mut message: String = diagnostics.iter().map(|d| format!("{}\n", d)).collect();
+        message.push_str(&error.to_string());
+        message.into()
+    })
+}
+
+impl Program {
+    pub fn load(filename: &str) -> Result<Program, Box<dyn Error>> {
+        if jack_compiler::is_jack(filename) {
+            let source = jack_compiler::compile(filename, false)?;
+            return Program::from_asm(&translate(&source)?);
+        }
+        if vm::is_vm(filename) {
+            return Program::from_asm(&translate(filename)?);
+        }
+
+        let contents = fs::read_to_string(filename)?;
+
+        if filename.ends_with(".hack") {
+            Program::from_hack(filename, &contents)
+        } else {
+            Program::from_asm(&contents)
+        }
+    }
+
+    pub fn from_asm(contents: &str) -> Result<Program, Box<dyn Error>> {
+        let (mut statements, directives) = assembler::parse(contents);
+        let entries = assembler::entries(&directives);
+        let jump_words = assembler::jump_to_entry(&mut statements, &entries);
+        let (symbol_table, mut diagnostics) =
+            assembler::allocate(&statements, &directives, &Layout::new());
+
+        let source = &statements[jump_words..];
+        if let Some(diagnostic) = assembler::check(source, &symbol_table).first() {
+            return Err(diagnostic.to_string().into());
+        }
+        diagnostics.extend(assembler::check_entries(&entries, &symbol_table));
+        let rom_diagnostics = assembler::check_rom(&statements, &symbol_table, code::ROM_SIZE);
+        if let Some(diagnostic) = diagnostics.iter().chain(&rom_diagnostics).next() {
+            return Err(diagnostic.to_string().into());
+        }
+        let mut rom = vec![];
+        let mut locations = vec![];
+
+        for statement in &statements {
+            if let Some(word) = assembler::encode(&statement.instruction, &symbol_table) {
+                rom.push(u16::from_str_radix(&word, 2)?);
+                locations.push(statement.location);
+            }
+        }
+
+        let ram = data::image(&directives, &symbol_table)
+            .map_err(|diagnostics| diagnostics[0].to_string())?;
+
+        Ok(Program {
+            rom,
+            symbol_table,
+            locations,
+            source: contents.lines().map(String::from).collect(),
+            ram,
+        })
+    }
+
+    // symbols and lines are read from Foo.hack.map, written by `-g`
+    pub fn from_hack(filename: &str, contents: &str) -> Result<Program, Box<dyn Error>> {
+        let rom = load(contents)?;
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.add_predefined_symbols();
+        let mut locations = vec![];
+        let mut source = vec![];
+
+        if let Ok(map) = fs::read_to_string(format!("{}.map", filename)) {
+            let map = json::parse(&map)?;
+            let entries = |key: &str| map.get(key).and_then(|v| v.as_array()).cloned();
+            let number = |entry: &json::Value, key: &str| {
+                entry.get(key).and_then(|v| v.as_i64()).unwrap_or(0) as u32
+            };
+
+            for label in entries("labels").unwrap_or_default() {
+                if let Some(name) = label.get("name").and_then(|n| n.as_str()) {
+                    symbol_table.add_label(String::from(name), number(&label, "address"))?;
+                }
+            }
+            for variable in entries("variables").unwrap_or_default() {
+                if let Some(name) = variable.get("name").and_then(|n| n.as_str()) {
+                    symbol_table.add_entry(String::from(name), number(&variable, "address"));
+                    symbol_table.variables.push(String::from(name));
+                }
+            }
+            for instruction in entries("instructions").unwrap_or_default() {
+                locations.push(Location {
+                    line: number(&instruction, "line"),
+                    column: number(&instruction, "column"),
+                });
+            }
+            if let Some(file) = map.get("file").and_then(|f| f.as_str()) {
+                if let Ok(text) = fs::read_to_string(file) {
+                    source = text.lines().map(String::from).collect();
+                }
+            }
+        }
+
+        // Foo.ram is written next to Foo.hack by the data directives
+        let ram = match fs::read_to_string(Path::new(filename).with_extension("ram")) {
+            Ok(contents) => data::load_ram(&contents)?,
+            Err(_) => vec![],
+        };
+
+        Ok(Program {
+            rom,
+            symbol_table,
+            locations,
+            source,
+            ram,
+        })
+    }
+
+    // LOOP, or LOOP+2 for an address after the closest label
+    pub fn rom_name(&self, address: u16) -> Option<String> {
+        let address = address as u32;
+
+        let label = self
+            .symbol_table
+            .labels
+            .iter()
+            .filter_map(|l| Some((l, self.symbol_table.get_address(l)?)))
+            .filter(|(_, a)| *a <= address)
+            .max_by_key(|(_, a)| *a)?;
+
+        if label.1 == address {
+            Some(label.0.clone())
+        } else {
+            Some(format!("{}+{}", label.0, address - label.1))
+        }
+    }
+
+    // variables first, then predefined names with SP over R0
+    pub fn ram_name(&self, address: u16) -> Option<String> {
+        let address = address as u32;
+
+        if let Some(variable) = self
+            .symbol_table
+            .variables
+            .iter()
+            .find(|v| self.symbol_table.get_address(v) == Some(address))
+        {
+            return Some(variable.clone());
+        }
+
+        let mut names: Vec<&String> = self
+            .symbol_table
+            .symbols
+            .iter()
+            .filter(|(name, a)| **a == address && !self.symbol_table.labels.contains(name))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort_by_key(|name| (is_register(name), (*name).clone()));
+
+        names.first().map(|name| (*name).clone())
+    }
+
+    pub fn is_label(&self, name: &str) -> bool {
+        self.symbol_table.labels.iter().any(|l| l == name)
+    }
+
+    pub fn source_line(&self, address: u16) -> Option<(u32, String)> {
+        let location = self.locations.get(address as usize)?;
+        let text = self.source.get(location.line as usize - 1)?;
+        Some((location.line, String::from(text.trim())))
+    }
+}
+
+fn is_register(name: &str) -> bool {
+    name.len() > 1 && name.starts_with('R') && name[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn computer(contents: &str) -> Computer {
+        let (statements, _) = assembler::parse(contents);
+        let symbol_table = assembler::resolve(&statements);
+        let rom = statements
+            .iter()
+            .filter_map(|s| assembler::encode(&s.instruction, &symbol_table))
+            .map(|word| u16::from_str_radix(&word, 2).unwrap())
+            .collect();
+        Computer::new(rom)
+    }
+
+    fn run(computer: &mut Computer) {
+        while !computer.halted() {
+            computer.step();
+        }
+    }
+
+    fn comp(mnemonic: &str, d: u16, a: u16, m: u16) -> u16 {
+        let mut computer = computer(&format!("D={}", mnemonic));
+        computer.d = d;
+        computer.a = a;
+        computer.ram[a as usize] = m;
+        computer.step();
+        computer.d
+    }
+
+    #[test]
+    fn every_comp() {
+        let d = 6;
+        let a = 100;
+        let m = 3;
+        let expected: [(&str, i16); 28] = [
+            ("0", 0),
+            ("1", 1),
+            ("-1", -1),
+            ("D", 6),
+            ("A", 100),
+            ("M", 3),
+            ("!D", !6),
+            ("!A", !100),
+            ("!M", !3),
+            ("-D", -6),
+            ("-A", -100),
+            ("-M", -3),
+            ("D+1", 7),
+            ("A+1", 101),
+            ("M+1", 4),
+            ("D-1", 5),
+            ("A-1", 99),
+            ("M-1", 2),
+            ("D+A", 106),
+            ("D+M", 9),
+            ("D-A", -94),
+            ("D-M", 3),
+            ("A-D", 94),
+            ("M-D", -3),
+            ("D&A", 6 & 100),
+            ("D&M", 6 & 3),
+            ("D|A", 6 | 100),
+            ("D|M", 6 | 3),
+        ];
+
+        for (mnemonic, value) in expected {
+            assert_eq!(value as u16, comp(mnemonic, d, a, m), "{}", mnemonic);
+        }
+    }
+
+    #[test]
+    fn writes_m_with_old_a() {
+        let mut computer = computer("@5\nAM=A+1");
+        computer.step();
+        assert_eq!(Some(5), computer.step());
+        assert_eq!(6, computer.ram[5]);
+        assert_eq!(6, computer.a);
+    }
+
+    #[test]
+    fn jump_conditions() {
+        let mut computer = computer("@4\nD;JGT\n@0\nD=A\n@0");
+        computer.d = (-1i16) as u16;
+        computer.step();
+        computer.step();
+        assert_eq!(2, computer.pc);
+
+        computer.pc = 0;
+        computer.d = 1;
+        computer.step();
+        computer.step();
+        assert_eq!(4, computer.pc);
+    }
+
+    #[test]
+    fn max() {
+        let mut computer = computer(
+            "@R0\nD=M\n@R1\nD=D-M\n@FIRST\nD;JGT\n@R1\nD=M\n@OUT\n0;JMP\n(FIRST)\n@R0\nD=M\n(OUT)\n@R2\nM=D\n(END)\n@END\n0;JMP",
+        );
+        computer.ram[0] = 3;
+        computer.ram[1] = 7;
+        run(&mut computer);
+        assert_eq!(7, computer.ram[2]);
+        assert_eq!(14, computer.pc);
+    }
+
+    #[test]
+    fn halts_past_end() {
+        let mut computer = computer("@1\nD=A");
+        assert!(!computer.halted());
+        run(&mut computer);
+        assert_eq!(2, computer.cycles);
+        assert_eq!(None, computer.step());
+    }
+
+    #[test]
+    fn starts_at_entry() {
+        let program =
+            Program::from_asm(".entry MAIN\n@1\nD=A\n(MAIN)\n@2\nD=A\n(END)\n@END\n0;JMP").unwrap();
+        assert_eq!(vec![4, 0xea87], program.rom[..2]);
+        assert_eq!(1, program.locations[0].line);
+
+        let mut computer = Computer::new(program.rom);
+        for _ in 0..4 {
+            computer.step();
+        }
+        assert_eq!(2, computer.d);
+
+        assert_eq!(
+            "error: `NOPE` is not a label of the program (line 1, column 1)",
+            Program::from_asm(".entry NOPE\n@1")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn load_hack() {
+        assert_eq!(
+            Ok(vec![2, 0xec10]),
+            load("0000000000000010\n1110110000010000\n")
+        );
+        assert_eq!(
+            Err(String::from("invalid instruction on line 2")),
+            load("0000000000000010\n11101100\n")
+        );
+    }
+
+    #[test]
+    fn disassemble_words() {
+        assert_eq!("@17", disassemble(17));
+        assert_eq!("D=M", disassemble(0xfc10));
+        assert_eq!("0;JMP", disassemble(0xea87));
+        assert_eq!("AM=M+1;JNE", disassemble(0xfded));
+    }
+
+    #[test]
+    fn run_with_keys_and_snapshots() {
+        let dir = std::env::temp_dir().join(format!("hack-run-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let program = "(WAIT)\n@KBD\nD=M\n@WAIT\nD;JEQ\n@SCREEN\nM=-1\n(END)\n@END\n0;JMP";
+        fs::write(file("Wait.asm"), program).unwrap();
+        fs::write(file("keys.txt"), "100 A\n").unwrap();
+
+        let config = RunConfig {
+            filename: file("Wait.asm"),
+            cycles: 1000,
+            keys: Some(file("keys.txt")),
+            snapshots: vec![(50, file("before.pbm"))],
+            screen: Some(file("after.pbm")),
+        };
+        super::run(config).unwrap();
+
+        let header = b"P4\n512 256\n".len();
+        let before = fs::read(file("before.pbm")).unwrap();
+        let after = fs::read(file("after.pbm")).unwrap();
+        assert_eq!([0, 0], before[header..header + 2]);
+        assert_eq!([0xff, 0xff], after[header..header + 2]);
+        assert_eq!(0, after[header + 2]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use crate::json::Value;
+use crate::parser::Location;
+
+// Links every ROM address back to the source line it was assembled from,
+// together with the addresses of labels and variables. Written as JSON next
+// to the .hack file, for simulators and editors to set breakpoints on.
+pub struct SourceMap {
+    pub file: String,
+    pub locations: Vec<Location>,
+    pub labels: Vec<(String, u32, Location)>,
+    pub variables: Vec<(String, u32)>,
+}
+
+impl SourceMap {
+    pub fn new(file: &str) -> SourceMap {
+        SourceMap {
+            file: String::from(file),
+            locations: vec![],
+            labels: vec![],
+            variables: vec![],
+        }
+    }
+
+    // instructions have to be added in ROM order
+    pub fn add_instruction(&mut self, location: Location) {
+        self.locations.push(location);
+    }
+
+    pub fn add_label(&mut self, label: &str, address: u32, location: Location) {
+        self.labels.push((String::from(label), address, location));
+    }
+
+    pub fn add_variable(&mut self, variable: &str, address: u32) {
+        self.variables.push((String::from(variable), address));
+    }
+
+    pub fn to_json(&self) -> Value {
+        let instructions = self
+            .locations
+            .iter()
+            .enumerate()
+            .map(|(address, location)| {
+                Value::object()
+                    .with("address", address)
+                    .with("file", self.file.as_str())
+                    .with("line", location.line)
+                    .with("column", location.column)
+            })
+            .collect::<Vec<Value>>();
+
+        let labels = self
+            .labels
+            .iter()
+            .map(|(label, address, location)| {
+                Value::object()
+                    .with("name", label.as_str())
+                    .with("address", *address)
+                    .with("file", self.file.as_str())
+                    .with("line", location.line)
+                    .with("column", location.column)
+            })
+            .collect::<Vec<Value>>();
+
+        let variables = self
+            .variables
+            .iter()
+            .map(|(variable, address)| {
+                Value::object()
+                    .with("name", variable.as_str())
+                    .with("address", *address)
+            })
+            .collect::<Vec<Value>>();
+
+        Value::object()
+            .with("version", 1u32)
+            .with("file", self.file.as_str())
+            .with("instructions", instructions)
+            .with("labels", labels)
+            .with("variables", variables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let source_map = SourceMap::new("Empty.asm");
+        assert_eq!(
+            "{\"version\":1,\"file\":\"Empty.asm\",\"instructions\":[],\"labels\":[],\"variables\":[]}",
+            source_map.to_json().to_string()
+        );
+    }
+
+    #[test]
+    fn instructions_by_address() {
+        let mut source_map = SourceMap::new("Max.asm");
+        source_map.add_instruction(Location { line: 2, column: 4 });
+        source_map.add_instruction(Location { line: 3, column: 1 });
+        source_map.add_label("LOOP", 2, Location { line: 4, column: 1 });
+        source_map.add_variable("i", 16);
+
+        let json = source_map.to_json().to_string();
+        assert!(json.contains(
+            "{\"address\":0,\"file\":\"Max.asm\",\"line\":2,\"column\":4},{\"address\":1,"
+        ));
+        assert!(json.contains(
+            "\"labels\":[{\"name\":\"LOOP\",\"address\":2,\"file\":\"Max.asm\",\"line\":4,\"column\":1}]"
+        ));
+        assert!(json.contains("\"variables\":[{\"name\":\"i\",\"address\":16}]"));
+    }
+}
@@ -1,13 +1,70 @@
 use std::collections::HashMap;
 
+// first free RAM address after R0..R15
+const VARIABLE_BASE_ADDRESS: u32 = 16;
+
+// the screen memory map starts right after the variables
+const VARIABLE_LIMIT: u32 = 16383;
+
+// Where variables go in RAM, the nand2tetris scheme allocates them from 16
+// in order of first use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub base: u32,
+    // the last address a variable may take
+    pub limit: u32,
+    pub alphabetical: bool,
+}
+
+impl Layout {
+    pub fn new() -> Layout {
+        Layout {
+            base: VARIABLE_BASE_ADDRESS,
+            limit: VARIABLE_LIMIT,
+            alphabetical: false,
+        }
+    }
+}
+
+// What one file of a linked program keeps to itself, its local labels and
+// its private variables. They are looked up before the global symbols.
+pub struct Locals {
+    pub file: String,
+    pub symbols: HashMap<String, u32>,
+    pub variables: Vec<String>,
+}
+
 pub struct SymbolTable {
     pub symbols: HashMap<String, u32>,
+    pub labels: Vec<String>,
+    pub variables: Vec<String>,
+    // variables given an address with `.var`
+    pub pinned: Vec<String>,
+    pub locals: Vec<Locals>,
+    next_variable: u32,
+    limit: u32,
+    // the last global label and the number of anonymous labels, while
+    // naming labels in source order
+    scope: String,
+    anonymous: u32,
 }
 
 impl SymbolTable {
     pub fn new() -> SymbolTable {
+        SymbolTable::with_layout(&Layout::new())
+    }
+
+    pub fn with_layout(layout: &Layout) -> SymbolTable {
         SymbolTable {
             symbols: HashMap::new(),
+            labels: vec![],
+            variables: vec![],
+            pinned: vec![],
+            locals: vec![],
+            next_variable: layout.base,
+            limit: layout.limit,
+            scope: String::new(),
+            anonymous: 0,
         }
     }
 
@@ -38,17 +95,198 @@ impl SymbolTable {
     }
 
     pub fn add_entry(&mut self, symbol: String, address: u32) {
-        println!("add_entry, {} -> {}", symbol, address);
         self.symbols.insert(symbol, address);
     }
 
+    // A label never replaces another symbol, the first definition is
+    // kept.
+    pub fn add_label(&mut self, label: String, address: u32) -> Result<(), String> {
+        if self.contains(&label) {
+            return Err(if self.labels.contains(&label) {
+                format!("label `{}` is defined twice", label)
+            } else if self.variables.contains(&label) {
+                format!("label `{}` is also used as a variable", label)
+            } else {
+                format!("label `{}` shadows a predefined symbol", label)
+            });
+        }
+        self.add_entry(label.clone(), address);
+        self.labels.push(label);
+        Ok(())
+    }
+
+    // Labels are named in source order. A local label like `(.loop)`
+    // belongs to the last global label, after `(MAIN)` it is `MAIN.loop`.
+    // Anonymous labels `()` are numbered `:1`, `:2`, ...
+    pub fn scoped_label(&mut self, label: &str) -> String {
+        if label.is_empty() {
+            self.anonymous += 1;
+            format!(":{}", self.anonymous)
+        } else if label.starts_with('.') {
+            format!("{}{}", self.scope, label)
+        } else {
+            self.scope = String::from(label);
+            String::from(label)
+        }
+    }
+
+    // The full name of a local or anonymous reference at the current
+    // point, `@+` is the next anonymous label, `@--` the one before the
+    // previous one. Other symbols have no scope.
+    pub fn scoped_symbol(&self, symbol: &str) -> Option<String> {
+        if symbol.starts_with('.') {
+            return Some(format!("{}{}", self.scope, symbol));
+        }
+
+        let count = symbol.len() as u32;
+        if !symbol.is_empty() && symbol.chars().all(|c| c == '+') {
+            Some(format!(":{}", self.anonymous + count))
+        } else if !symbol.is_empty() && symbol.chars().all(|c| c == '-') && count <= self.anonymous
+        {
+            Some(format!(":{}", self.anonymous + 1 - count))
+        } else {
+            None
+        }
+    }
+
+    // Variables get consecutive RAM addresses in the order they are added,
+    // skipping the pinned ones.
+    pub fn add_variable(&mut self, symbol: String) -> Result<u32, String> {
+        let address = self.next_address(&symbol)?;
+        self.add_entry(symbol.clone(), address);
+        self.variables.push(symbol);
+        Ok(address)
+    }
+
+    fn next_address(&mut self, symbol: &str) -> Result<u32, String> {
+        while self.is_pinned(self.next_variable) {
+            self.next_variable += 1;
+        }
+        if self.next_variable > self.limit {
+            return Err(format!(
+                "no RAM left for variable `{}`, variables end at RAM[{}]",
+                symbol, self.limit
+            ));
+        }
+        self.next_variable += 1;
+        Ok(self.next_variable - 1)
+    }
+
+    // Returns the index of the file's local symbols.
+    pub fn add_file(&mut self, file: &str) -> usize {
+        self.locals.push(Locals {
+            file: String::from(file),
+            symbols: HashMap::new(),
+            variables: vec![],
+        });
+        self.locals.len() - 1
+    }
+
+    pub fn add_local(&mut self, file: usize, symbol: String, address: u32) {
+        self.locals[file].symbols.insert(symbol, address);
+    }
+
+    // a variable of its own for the file, taken from the same RAM as the
+    // global ones
+    pub fn add_local_variable(&mut self, file: usize, symbol: String) -> Result<u32, String> {
+        let address = self.next_address(&symbol)?;
+        self.add_local(file, symbol.clone(), address);
+        self.locals[file].variables.push(symbol);
+        Ok(address)
+    }
+
+    pub fn get_local_address(&self, file: usize, symbol: &str) -> Option<u32> {
+        self.locals[file]
+            .symbols
+            .get(symbol)
+            .copied()
+            .or_else(|| self.get_address(symbol))
+    }
+
+    pub fn variable_count(&self) -> usize {
+        let private: usize = self
+            .locals
+            .iter()
+            .map(|locals| locals.variables.len())
+            .sum();
+        self.variables.len() + private
+    }
+
+    // .var counter 100
+    // the address has to be in the variable memory of the layout, R0..R15
+    // are left to the program
+    pub fn pin_variable(&mut self, symbol: String, address: u32) -> Result<(), String> {
+        if self.contains(&symbol) {
+            return Err(format!("`{}` is already defined", symbol));
+        }
+        if address < VARIABLE_BASE_ADDRESS {
+            return Err(format!(
+                "RAM[{}] is the register R{}, variables start at RAM[{}]",
+                address, address, VARIABLE_BASE_ADDRESS
+            ));
+        }
+        if address > self.limit {
+            return Err(format!(
+                "RAM[{}] is outside of the variable memory",
+                address
+            ));
+        }
+        if let Some(other) = self
+            .pinned
+            .iter()
+            .find(|other| self.get_address(other) == Some(address))
+        {
+            return Err(format!("RAM[{}] is already taken by `{}`", address, other));
+        }
+
+        self.add_entry(symbol.clone(), address);
+        self.variables.push(symbol.clone());
+        self.pinned.push(symbol);
+        Ok(())
+    }
+
+    fn is_pinned(&self, address: u32) -> bool {
+        self.pinned
+            .iter()
+            .any(|symbol| self.get_address(symbol) == Some(address))
+    }
+
+    //   16  i
+    //   17  temp  (Mult.obj)
+    //  100  counter  (pinned)
+    pub fn ram_map(&self) -> String {
+        let mut variables: Vec<(u32, &String, Option<&String>)> = self
+            .variables
+            .iter()
+            .filter_map(|v| Some((self.get_address(v)?, v, None)))
+            .collect();
+        for locals in &self.locals {
+            for variable in &locals.variables {
+                let address = locals.symbols[variable];
+                variables.push((address, variable, Some(&locals.file)));
+            }
+        }
+        variables.sort();
+
+        let mut map = String::from("RAM map\n");
+        for (address, variable, file) in variables {
+            if let Some(file) = file {
+                map.push_str(&format!("{:>5}  {}  ({})\n", address, variable, file));
+            } else if self.pinned.contains(variable) {
+                map.push_str(&format!("{:>5}  {}  (pinned)\n", address, variable));
+            } else {
+                map.push_str(&format!("{:>5}  {}\n", address, variable));
+            }
+        }
+        map
+    }
+
     pub fn contains(&self, symbol: &str) -> bool {
         self.symbols.contains_key(symbol)
     }
 
-    pub fn get_address(&self, symbol: &str) -> u32 {
-        let result = self.symbols.get(symbol);
-        return result.unwrap().clone();
+    pub fn get_address(&self, symbol: &str) -> Option<u32> {
+        self.symbols.get(symbol).copied()
     }
 }
 
@@ -56,164 +294,285 @@ impl SymbolTable {
 mod tests {
     use super::*;
 
+    #[test]
+    fn add_variables() {
+        let mut st = SymbolTable::new();
+        assert_eq!(Ok(16), st.add_variable(String::from("i")));
+        assert_eq!(Ok(17), st.add_variable(String::from("sum")));
+        assert_eq!(Some(17), st.get_address("sum"));
+        assert_eq!(vec!["i", "sum"], st.variables);
+    }
+
+    #[test]
+    fn layout_and_pinned_variables() {
+        let mut st = SymbolTable::with_layout(&Layout {
+            base: 100,
+            limit: 102,
+            alphabetical: false,
+        });
+        assert_eq!(Ok(()), st.pin_variable(String::from("counter"), 101));
+        assert_eq!(
+            Err(String::from("RAM[101] is already taken by `counter`")),
+            st.pin_variable(String::from("other"), 101)
+        );
+        assert_eq!(
+            Err(String::from("`counter` is already defined")),
+            st.pin_variable(String::from("counter"), 5)
+        );
+        assert_eq!(
+            Err(String::from("RAM[16384] is outside of the variable memory")),
+            st.pin_variable(String::from("x"), 16384)
+        );
+        assert_eq!(
+            Err(String::from("RAM[103] is outside of the variable memory")),
+            st.pin_variable(String::from("x"), 103)
+        );
+        assert_eq!(
+            Err(String::from(
+                "RAM[5] is the register R5, variables start at RAM[16]"
+            )),
+            st.pin_variable(String::from("x"), 5)
+        );
+        assert_eq!(Ok(100), st.add_variable(String::from("i")));
+        assert_eq!(Ok(102), st.add_variable(String::from("j")));
+        assert_eq!(
+            Err(String::from(
+                "no RAM left for variable `k`, variables end at RAM[102]"
+            )),
+            st.add_variable(String::from("k"))
+        );
+        assert_eq!(
+            "RAM map\n  100  i\n  101  counter  (pinned)\n  102  j\n",
+            st.ram_map()
+        );
+    }
+
+    #[test]
+    fn local_symbols() {
+        let mut st = SymbolTable::new();
+        let main = st.add_file("Main.obj");
+        let mult = st.add_file("Mult.obj");
+        assert_eq!(Ok(16), st.add_variable(String::from("result")));
+        assert_eq!(Ok(17), st.add_local_variable(main, String::from("i")));
+        assert_eq!(Ok(18), st.add_local_variable(mult, String::from("i")));
+        st.add_local(mult, String::from(":1"), 40);
+
+        assert_eq!(Some(17), st.get_local_address(main, "i"));
+        assert_eq!(Some(18), st.get_local_address(mult, "i"));
+        assert_eq!(Some(16), st.get_local_address(mult, "result"));
+        assert_eq!(None, st.get_local_address(main, ":1"));
+        assert_eq!(None, st.get_address("i"));
+        assert_eq!(3, st.variable_count());
+        assert_eq!(
+            "RAM map\n   16  result\n   17  i  (Main.obj)\n   18  i  (Mult.obj)\n",
+            st.ram_map()
+        );
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let mut st = SymbolTable::new();
+        assert_eq!(Ok(()), st.add_label(String::from("LOOP"), 2));
+        assert_eq!(
+            Err(String::from("label `LOOP` is defined twice")),
+            st.add_label(String::from("LOOP"), 7)
+        );
+        assert_eq!(Some(2), st.get_address("LOOP"));
+    }
+
+    #[test]
+    fn conflicting_labels() {
+        let mut st = SymbolTable::new();
+        st.add_predefined_symbols();
+        st.add_variable(String::from("i")).unwrap();
+        assert_eq!(
+            Err(String::from("label `R1` shadows a predefined symbol")),
+            st.add_label(String::from("R1"), 0)
+        );
+        assert_eq!(
+            Err(String::from("label `i` is also used as a variable")),
+            st.add_label(String::from("i"), 0)
+        );
+        assert_eq!(Some(1), st.get_address("R1"));
+        assert_eq!(None, st.get_address("LOOP"));
+    }
+
+    #[test]
+    fn local_and_anonymous_labels() {
+        let mut st = SymbolTable::new();
+        assert_eq!(".start", st.scoped_label(".start"));
+        assert_eq!(None, st.scoped_symbol("-"));
+        assert_eq!(Some(String::from(":1")), st.scoped_symbol("+"));
+        assert_eq!("MAIN", st.scoped_label("MAIN"));
+        assert_eq!("MAIN.loop", st.scoped_label(".loop"));
+        assert_eq!(":1", st.scoped_label(""));
+        assert_eq!(":2", st.scoped_label(""));
+        assert_eq!(Some(String::from("MAIN.loop")), st.scoped_symbol(".loop"));
+        assert_eq!(Some(String::from(":2")), st.scoped_symbol("-"));
+        assert_eq!(Some(String::from(":1")), st.scoped_symbol("--"));
+        assert_eq!(Some(String::from(":4")), st.scoped_symbol("++"));
+        assert_eq!(None, st.scoped_symbol("LOOP"));
+        assert_eq!(None, st.scoped_symbol("+-"));
+    }
+
     #[test]
     fn variable_r0() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(0, st.get_address("R0"));
+        assert_eq!(Some(0), st.get_address("R0"));
     }
 
     #[test]
     fn variable_r1() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(1, st.get_address("R1"));
+        assert_eq!(Some(1), st.get_address("R1"));
     }
 
     #[test]
     fn variable_r2() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(2, st.get_address("R2"));
+        assert_eq!(Some(2), st.get_address("R2"));
     }
 
     #[test]
     fn variable_r3() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(3, st.get_address("R3"));
+        assert_eq!(Some(3), st.get_address("R3"));
     }
 
     #[test]
     fn variable_r4() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(4, st.get_address("R4"));
+        assert_eq!(Some(4), st.get_address("R4"));
     }
 
     #[test]
     fn variable_r5() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(5, st.get_address("R5"));
+        assert_eq!(Some(5), st.get_address("R5"));
     }
 
     #[test]
     fn variable_r6() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(6, st.get_address("R6"));
+        assert_eq!(Some(6), st.get_address("R6"));
     }
 
     #[test]
     fn variable_r7() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(7, st.get_address("R7"));
+        assert_eq!(Some(7), st.get_address("R7"));
     }
 
     #[test]
     fn variable_r8() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(8, st.get_address("R8"));
+        assert_eq!(Some(8), st.get_address("R8"));
     }
 
     #[test]
     fn variable_r9() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(9, st.get_address("R9"));
+        assert_eq!(Some(9), st.get_address("R9"));
     }
 
     #[test]
     fn variable_r10() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(10, st.get_address("R10"));
+        assert_eq!(Some(10), st.get_address("R10"));
     }
 
     #[test]
     fn variable_r11() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(11, st.get_address("R11"));
+        assert_eq!(Some(11), st.get_address("R11"));
     }
 
     #[test]
     fn variable_r12() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(12, st.get_address("R12"));
+        assert_eq!(Some(12), st.get_address("R12"));
     }
 
     #[test]
     fn variable_r13() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(13, st.get_address("R13"));
+        assert_eq!(Some(13), st.get_address("R13"));
     }
 
     #[test]
     fn variable_r14() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(14, st.get_address("R14"));
+        assert_eq!(Some(14), st.get_address("R14"));
     }
 
     #[test]
     fn variable_r15() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(15, st.get_address("R15"));
+        assert_eq!(Some(15), st.get_address("R15"));
     }
 
     #[test]
     fn variable_sp() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(0, st.get_address("SP"));
+        assert_eq!(Some(0), st.get_address("SP"));
     }
 
     #[test]
     fn variable_lcl() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(1, st.get_address("LCL"));
+        assert_eq!(Some(1), st.get_address("LCL"));
     }
 
     #[test]
     fn variable_arg() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(2, st.get_address("ARG"));
+        assert_eq!(Some(2), st.get_address("ARG"));
     }
 
     #[test]
     fn variable_this() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(3, st.get_address("THIS"));
+        assert_eq!(Some(3), st.get_address("THIS"));
     }
 
     #[test]
     fn variable_that() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(4, st.get_address("THAT"));
+        assert_eq!(Some(4), st.get_address("THAT"));
     }
 
     #[test]
     fn variable_screen() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(16384, st.get_address("SCREEN"));
+        assert_eq!(Some(16384), st.get_address("SCREEN"));
     }
 
     #[test]
     fn variable_kbd() {
         let mut st = SymbolTable::new();
         st.add_predefined_symbols();
-        assert_eq!(24576, st.get_address("KBD"));
+        assert_eq!(Some(24576), st.get_address("KBD"));
     }
 }
@@ -0,0 +1,453 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::simulator::{Computer, Program};
+
+// Runs nand2tetris CPU emulator test scripts:
+//
+//     load Max.asm, output-file Max.out, compare-to Max.cmp,
+//     output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+//     set RAM[0] 3, set RAM[1] 5;
+//     repeat 14 { ticktock; }
+//     output;
+//
+// Files are relative to the script. The output is compared line by line
+// as it is produced, the first difference fails the test.
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Words(Vec<String>),
+    Repeat(u64, Vec<Command>),
+}
+
+// `/* */` and `//` comments are dropped, `,` `;` `!` `{` and `}` are
+// tokens of their own
+fn tokenize(script: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut chars = script.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            ',' | ';' | '!' | '{' | '}' => {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+                tokens.push(c.to_string());
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+
+    tokens
+}
+
+fn parse(tokens: &mut std::slice::Iter<String>, nested: bool) -> Result<Vec<Command>, String> {
+    let mut commands = vec![];
+    let mut words: Vec<String> = vec![];
+
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "," | ";" | "!" => {
+                if !words.is_empty() {
+                    commands.push(Command::Words(std::mem::take(&mut words)));
+                }
+            }
+            "{" => {
+                let count = match words.as_slice() {
+                    [repeat, count] if repeat == "repeat" => count
+                        .parse()
+                        .map_err(|_| format!("invalid repeat count `{}`", count))?,
+                    _ => return Err(String::from("only `repeat N { ... }` is supported")),
+                };
+                words.clear();
+                commands.push(Command::Repeat(count, parse(tokens, true)?));
+            }
+            "}" if nested => {
+                if !words.is_empty() {
+                    return Err(format!("missing `;` after `{}`", words.join(" ")));
+                }
+                return Ok(commands);
+            }
+            "}" => return Err(String::from("unexpected `}`")),
+            _ => words.push(token.clone()),
+        }
+    }
+
+    if nested {
+        return Err(String::from("unclosed `{`"));
+    }
+    if !words.is_empty() {
+        return Err(format!("missing `;` after `{}`", words.join(" ")));
+    }
+    Ok(commands)
+}
+
+// RAM[0]%D2.6.2 is RAM[0] in decimal, with 2 spaces on the left, 6 digits
+// and 2 spaces on the right
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    name: String,
+    format: char,
+    left: usize,
+    width: usize,
+    right: usize,
+}
+
+fn column(text: &str) -> Result<Column, String> {
+    let invalid = || format!("invalid output column `{}`", text);
+
+    let (name, format) = text.split_once('%').ok_or_else(invalid)?;
+    let mut chars = format.chars();
+    let format = chars
+        .next()
+        .filter(|c| "DBXS".contains(*c))
+        .ok_or_else(invalid)?;
+    let sizes: Vec<usize> = chars
+        .as_str()
+        .split('.')
+        .map(|size| size.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+
+    match sizes.as_slice() {
+        [left, width, right] => Ok(Column {
+            name: String::from(name),
+            format,
+            left: *left,
+            width: *width,
+            right: *right,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+impl Column {
+    // the name is centered over the column
+    fn header(&self) -> String {
+        let width = self.left + self.width + self.right;
+        let name: String = self.name.chars().take(width).collect();
+        let left = (width - name.len()) / 2;
+        format!(
+            "{}{}{}",
+            " ".repeat(left),
+            name,
+            " ".repeat(width - left - name.len())
+        )
+    }
+
+    fn cell(&self, value: u16) -> String {
+        let text = match self.format {
+            'D' => (value as i16).to_string(),
+            'B' => format!("{:016b}", value),
+            'X' => format!("{:04X}", value),
+            _ => ((value & 0xff) as u8 as char).to_string(),
+        };
+        // binary and hex keep their lowest digits when narrower
+        let text = match self.format {
+            'B' | 'X' if text.len() > self.width => String::from(&text[text.len() - self.width..]),
+            _ => text,
+        };
+        format!(
+            "{}{:>width$}{}",
+            " ".repeat(self.left),
+            text,
+            " ".repeat(self.right),
+            width = self.width
+        )
+    }
+}
+
+// 42, -1, %X2A, %B101010, %D42
+fn number(text: &str) -> Result<u16, String> {
+    let value = match text.strip_prefix('%') {
+        Some(rest) if rest.starts_with('X') => i64::from_str_radix(&rest[1..], 16),
+        Some(rest) if rest.starts_with('B') => i64::from_str_radix(&rest[1..], 2),
+        Some(rest) if rest.starts_with('D') => rest[1..].parse(),
+        _ => text.parse(),
+    };
+    match value {
+        Ok(value) if (-32768..=65535).contains(&value) => Ok(value as u16),
+        _ => Err(format!("invalid value `{}`", text)),
+    }
+}
+
+// RAM[16] -> 16
+fn ram_address(name: &str) -> Option<usize> {
+    name.strip_prefix("RAM[")?.strip_suffix(']')?.parse().ok()
+}
+
+struct Script<'a> {
+    dir: &'a Path,
+    computer: Option<Computer>,
+    columns: Vec<Column>,
+    output: Vec<String>,
+    output_file: Option<String>,
+    compare: Option<Vec<String>>,
+}
+
+impl Script<'_> {
+    fn computer(&mut self) -> Result<&mut Computer, String> {
+        self.computer
+            .as_mut()
+            .ok_or_else(|| String::from("no program is loaded"))
+    }
+
+    fn get(&mut self, name: &str) -> Result<u16, String> {
+        let computer = self.computer()?;
+        match name {
+            "A" => Ok(computer.a),
+            "D" => Ok(computer.d),
+            "PC" => Ok(computer.pc),
+            "time" => Ok(computer.cycles as u16),
+            _ => match ram_address(name) {
+                Some(address) if address < computer.ram.len() => Ok(computer.ram[address]),
+                _ => Err(format!("unknown variable `{}`", name)),
+            },
+        }
+    }
+
+    fn set(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let computer = self.computer()?;
+        match name {
+            "A" => computer.a = value,
+            "D" => computer.d = value,
+            "PC" => computer.pc = value,
+            _ => match ram_address(name) {
+                Some(address) if address < computer.ram.len() => computer.ram[address] = value,
+                _ => return Err(format!("unknown variable `{}`", name)),
+            },
+        }
+        Ok(())
+    }
+
+    // adds a line to the output, failing on the first difference
+    fn write(&mut self, line: String) -> Result<(), String> {
+        self.output.push(line);
+        let number = self.output.len();
+        if let Some(compare) = &self.compare {
+            if compare.get(number - 1) != self.output.last() {
+                return Err(format!("Comparison failure at line {}", number));
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().into_owned()
+    }
+
+    fn execute(&mut self, commands: &[Command]) -> Result<(), String> {
+        for command in commands {
+            let words = match command {
+                Command::Repeat(count, commands) => {
+                    for _ in 0..*count {
+                        self.execute(commands)?;
+                    }
+                    continue;
+                }
+                Command::Words(words) => words,
+            };
+
+            match (words[0].as_str(), &words[1..]) {
+                ("load", [file]) => {
+                    let program =
+                        Program::load(&self.path(file)).map_err(|error| error.to_string())?;
+                    let mut computer = Computer::new(program.rom);
+                    computer.preload(&program.ram);
+                    self.computer = Some(computer);
+                }
+                ("output-file", [file]) => self.output_file = Some(self.path(file)),
+                ("compare-to", [file]) => {
+                    let contents = fs::read_to_string(self.path(file))
+                        .map_err(|error| format!("cannot read {}: {}", file, error))?;
+                    self.compare = Some(
+                        contents
+                            .lines()
+                            .map(|line| String::from(line.trim_end()))
+                            .collect(),
+                    );
+                }
+                ("output-list", columns) => {
+                    self.columns = columns
+                        .iter()
+                        .map(|text| column(text))
+                        .collect::<Result<_, _>>()?;
+                    let headers: Vec<String> = self.columns.iter().map(Column::header).collect();
+                    self.write(format!("|{}|", headers.join("|")))?;
+                }
+                ("output", []) => {
+                    let mut cells = vec![];
+                    for column in self.columns.clone() {
+                        cells.push(column.cell(self.get(&column.name)?));
+                    }
+                    self.write(format!("|{}|", cells.join("|")))?;
+                }
+                ("set", [name, value]) => {
+                    let value = number(value)?;
+                    self.set(name, value)?;
+                }
+                ("ticktock" | "tock", []) => {
+                    self.computer()?.step();
+                }
+                ("tick" | "echo" | "clear-echo", _) => {}
+                _ => return Err(format!("unsupported command `{}`", words.join(" "))),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns how the script ended, or where the comparison failed.
+pub fn run(path: &str) -> Result<String, Box<dyn Error>> {
+    let script = fs::read_to_string(path)?;
+    let commands = parse(&mut tokenize(&script).iter(), false)
+        .map_err(|error| format!("{}: {}", path, error))?;
+
+    let mut script = Script {
+        dir: Path::new(path).parent().unwrap_or(Path::new("")),
+        computer: None,
+        columns: vec![],
+        output: vec![],
+        output_file: None,
+        compare: None,
+    };
+    let result = script.execute(&commands);
+
+    // the output is kept on failure, to see where it went wrong
+    if let Some(output_file) = &script.output_file {
+        let mut contents = script.output.join("\n");
+        contents.push('\n');
+        fs::write(output_file, contents)?;
+    }
+
+    result?;
+    if script.compare.is_some() {
+        Ok(String::from(
+            "End of script - Comparison ended successfully",
+        ))
+    } else {
+        Ok(String::from("End of script"))
+    }
+}
+
+// Runs the script that goes with the program, see script_for().
+pub fn run_for(path: &str) -> Result<String, Box<dyn Error>> {
+    let script = script_for(path).ok_or_else(|| format!("no test script for {}", path))?;
+    run(&script)
+}
+
+// Foo.asm and Foo/ are tested by Foo.tst and Foo/Foo.tst
+pub fn script_for(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let script = if path.is_dir() {
+        path.join(path.file_name()?).with_extension("tst")
+    } else {
+        path.with_extension("tst")
+    };
+    script
+        .is_file()
+        .then(|| script.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_scripts() {
+        let script =
+            "load Max.asm, // the program\n/* a\n comment */ repeat 2 {\n  ticktock;\n}\noutput;";
+        assert_eq!(
+            Ok(vec![
+                Command::Words(vec![String::from("load"), String::from("Max.asm")]),
+                Command::Repeat(2, vec![Command::Words(vec![String::from("ticktock")])]),
+                Command::Words(vec![String::from("output")]),
+            ]),
+            parse(&mut tokenize(script).iter(), false)
+        );
+        assert_eq!(
+            Err(String::from("unclosed `{`")),
+            parse(&mut tokenize("repeat 2 { tick;").iter(), false)
+        );
+        assert_eq!(
+            Err(String::from("missing `;` after `output`")),
+            parse(&mut tokenize("output").iter(), false)
+        );
+    }
+
+    #[test]
+    fn output_columns() {
+        let ram = column("RAM[0]%D2.6.2").unwrap();
+        assert_eq!("  RAM[0]  ", ram.header());
+        assert_eq!("      -1  ", ram.cell(0xffff));
+        let pc = column("PC%D1.6.1").unwrap();
+        assert_eq!("   PC   ", pc.header());
+        assert_eq!(" 0000000000000101 ", column("A%B1.16.1").unwrap().cell(5));
+        assert_eq!(" 2A ", column("D%X1.2.1").unwrap().cell(42));
+        assert!(column("RAM[0]%Q1.6.1").is_err());
+        assert_eq!(Ok(42), number("%X2A"));
+        assert_eq!(Ok(0xffff), number("-1"));
+    }
+
+    #[test]
+    fn run_scripts() {
+        let dir = std::env::temp_dir().join(format!("hack-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        fs::write(file("Add.asm"), "@0\nD=M\n@1\nD=D+M\n@2\nM=D\n").unwrap();
+        fs::write(
+            file("Add.tst"),
+            "load Add.asm, output-file Add.out, compare-to Add.cmp,\n\
+             output-list RAM[2]%D2.6.2;\n\
+             set RAM[0] 2, set RAM[1] 3;\n\
+             repeat 6 { ticktock; }\n\
+             output;\n",
+        )
+        .unwrap();
+
+        fs::write(file("Add.cmp"), "|  RAM[2]  |\n|       5  |\n").unwrap();
+        assert_eq!(
+            "End of script - Comparison ended successfully",
+            run(&file("Add.tst")).unwrap()
+        );
+        assert_eq!(
+            "|  RAM[2]  |\n|       5  |\n",
+            fs::read_to_string(file("Add.out")).unwrap()
+        );
+
+        fs::write(file("Add.cmp"), "|  RAM[2]  |\n|       6  |\n").unwrap();
+        assert_eq!(
+            "Comparison failure at line 2",
+            run(&file("Add.tst")).unwrap_err().to_string()
+        );
+        assert_eq!(Some(file("Add.tst")), script_for(&file("Add.asm")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
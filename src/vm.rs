@@ -0,0 +1,652 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::Location;
+
+const SEGMENTS: [&str; 8] = [
+    "constant", "local", "argument", "this", "that", "static", "temp", "pointer",
+];
+
+const ARITHMETIC: [&str; 9] = ["add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not"];
+
+// the stack starts right after the registers and statics
+const STACK_BASE: u16 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Arithmetic(String),
+    Push(String, u16),
+    Pop(String, u16),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Function(String, u16),
+    Call(String, u16),
+    Return,
+}
+
+// Foo.vm and directories of .vm files go through the translator first
+pub fn is_vm(path: &str) -> bool {
+    let path = Path::new(path);
+    path.is_dir() || path.extension().is_some_and(|extension| extension == "vm")
+}
+
+// Foo.vm -> Foo.asm, Prog/ -> Prog/Prog.asm
+pub fn output_filename(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_dir() {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        path.join(name).with_extension("asm")
+    } else {
+        path.with_extension("asm")
+    }
+}
+
+// Translates a .vm file, or every .vm file of a directory, to Hack assembly.
+// The errors go in `diagnostics`, with the file they are in.
+pub fn translate(path: &str, diagnostics: &mut Vec<Diagnostic>) -> Result<String, Box<dyn Error>> {
+    let mut files = vec![];
+    let directory = if Path::new(path).is_dir() {
+        for entry in fs::read_dir(path)? {
+            let file = entry?.path();
+            if file.extension().is_some_and(|extension| extension == "vm") {
+                files.push(file);
+            }
+        }
+        files.sort();
+        Path::new(path)
+    } else {
+        files.push(PathBuf::from(path));
+        Path::new(path).parent().unwrap_or(Path::new(""))
+    };
+
+    if files.is_empty() {
+        return Err(format!("no .vm files in {}", path).into());
+    }
+
+    let mut sources = vec![];
+    for file in &files {
+        let name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or("invalid file name")?;
+        sources.push((String::from(name), fs::read_to_string(file)?));
+    }
+
+    generate(&sources).map_err(|errors| {
+        let count = errors.len();
+        // generate() only knows the names of the files
+        diagnostics.extend(errors.into_iter().map(|mut diagnostic| {
+            let file = diagnostic.file.take().unwrap_or_default();
+            diagnostic.file = Some(directory.join(file).to_string_lossy().into_owned());
+            diagnostic
+        }));
+        format!("could not translate {} due to {} errors", path, count).into()
+    })
+}
+
+// (file name, contents) pairs, the name prefixes the file's statics
+pub fn generate(sources: &[(String, String)]) -> Result<String, Vec<Diagnostic>> {
+    let mut files = vec![];
+    let mut errors = vec![];
+
+    for (name, contents) in sources {
+        let (commands, diagnostics) = parse(contents);
+        let file = format!("{}.vm", name);
+        errors.extend(
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.in_file(&file)),
+        );
+        files.push((name, commands));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut writer = Writer::new();
+
+    // only programs with an entry point get the bootstrap code, single
+    // files without one expect the test script to set up SP
+    let has_entry = files.iter().any(|(_, commands)| {
+        commands
+            .iter()
+            .any(|(command, _)| matches!(command, Command::Function(name, _) if name == "Sys.init"))
+    });
+    if has_entry {
+        writer.bootstrap("Sys.init");
+    }
+
+    for (name, commands) in files {
+        writer.file = name.clone();
+        writer.function.clear();
+        for (command, _) in &commands {
+            writer.write(command);
+        }
+    }
+
+    Ok(writer.output.join("\n") + "\n")
+}
+
+// The code that sets SP to 256 and calls `function`, which the linker puts
+// before assembly programs as well
+pub fn bootstrap(function: &str) -> String {
+    let mut writer = Writer::new();
+    writer.bootstrap(function);
+    writer.output.join("\n") + "\n"
+}
+
+pub fn parse(contents: &str) -> (Vec<(Command, Location)>, Vec<Diagnostic>) {
+    let mut commands = vec![];
+    let mut diagnostics = vec![];
+
+    for (i, line) in contents.lines().enumerate() {
+        let code = match line.find("//") {
+            Some(index) => &line[..index],
+            None => line,
+        };
+        if code.trim().is_empty() {
+            continue;
+        }
+
+        let location = Location {
+            line: i as u32 + 1,
+            column: (code.len() - code.trim_start().len()) as u32 + 1,
+        };
+
+        match command(code.trim()) {
+            Ok(command) => commands.push((command, location)),
+            Err(message) => diagnostics.push(Diagnostic::error(
+                location,
+                code.trim().len() as u32,
+                message,
+            )),
+        }
+    }
+
+    (commands, diagnostics)
+}
+
+fn command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        [operation] if ARITHMETIC.contains(operation) => {
+            Ok(Command::Arithmetic(String::from(*operation)))
+        }
+        ["return"] => Ok(Command::Return),
+        ["label", label] => Ok(Command::Label(String::from(*label))),
+        ["goto", label] => Ok(Command::Goto(String::from(*label))),
+        ["if-goto", label] => Ok(Command::IfGoto(String::from(*label))),
+        ["function", name, locals] => Ok(Command::Function(String::from(*name), index(locals)?)),
+        ["call", name, arguments] => Ok(Command::Call(String::from(*name), index(arguments)?)),
+        ["push", segment, value] => {
+            let value = index(value)?;
+            check_segment(segment, value)?;
+            Ok(Command::Push(String::from(*segment), value))
+        }
+        ["pop", segment, value] => {
+            let value = index(value)?;
+            check_segment(segment, value)?;
+            if *segment == "constant" {
+                return Err(String::from("cannot pop to `constant`"));
+            }
+            Ok(Command::Pop(String::from(*segment), value))
+        }
+        [operation, ..] => match *operation {
+            "push" | "pop" => Err(format!("`{}` expects a segment and an index", operation)),
+            "label" | "goto" | "if-goto" => Err(format!("`{}` expects a label", operation)),
+            "function" | "call" => Err(format!("`{}` expects a name and a count", operation)),
+            _ => Err(format!("unknown command `{}`", operation)),
+        },
+        [] => Err(String::from("empty command")),
+    }
+}
+
+fn index(value: &str) -> Result<u16, String> {
+    value
+        .parse::<u16>()
+        .ok()
+        .filter(|value| *value <= 32767)
+        .ok_or_else(|| format!("invalid index `{}`", value))
+}
+
+fn check_segment(segment: &str, index: u16) -> Result<(), String> {
+    let size = match segment {
+        "temp" => 8,
+        "pointer" => 2,
+        _ if SEGMENTS.contains(&segment) => return Ok(()),
+        _ => return Err(format!("unknown segment `{}`", segment)),
+    };
+
+    if index >= size {
+        return Err(format!("index {} out of range for `{}`", index, segment));
+    }
+    Ok(())
+}
+
+// Emits the assembly for each command, labels are scoped by the function
+// they appear in and statics by the file.
+struct Writer {
+    output: Vec<String>,
+    file: String,
+    function: String,
+    labels: u32,
+}
+
+impl Writer {
+    fn new() -> Writer {
+        Writer {
+            output: vec![],
+            file: String::new(),
+            function: String::new(),
+            labels: 0,
+        }
+    }
+
+    fn emit(&mut self, lines: &[&str]) {
+        self.output
+            .extend(lines.iter().map(|line| String::from(*line)));
+    }
+
+    // code outside of any function is scoped by its file
+    fn scope(&self) -> &str {
+        if self.function.is_empty() {
+            &self.file
+        } else {
+            &self.function
+        }
+    }
+
+    fn unique_label(&mut self, kind: &str) -> String {
+        let label = format!("{}${}.{}", self.scope(), kind, self.labels);
+        self.labels += 1;
+        label
+    }
+
+    fn bootstrap(&mut self, function: &str) {
+        self.file = String::from("Bootstrap");
+        self.output.push(String::from("// bootstrap"));
+        self.output.push(format!("@{}", STACK_BASE));
+        self.emit(&["D=A", "@SP", "M=D"]);
+        self.write(&Command::Call(String::from(function), 0));
+    }
+
+    fn write(&mut self, command: &Command) {
+        self.output.push(format!("// {}", vm_text(command)));
+
+        match command {
+            Command::Arithmetic(operation) => self.arithmetic(operation),
+            Command::Push(segment, index) => self.push(segment, *index),
+            Command::Pop(segment, index) => self.pop(segment, *index),
+            Command::Label(label) => {
+                let label = format!("({}${})", self.scope(), label);
+                self.output.push(label);
+            }
+            Command::Goto(label) => {
+                let label = format!("@{}${}", self.scope(), label);
+                self.output.push(label);
+                self.emit(&["0;JMP"]);
+            }
+            Command::IfGoto(label) => {
+                self.pop_d();
+                let label = format!("@{}${}", self.scope(), label);
+                self.output.push(label);
+                self.emit(&["D;JNE"]);
+            }
+            Command::Function(name, locals) => {
+                self.function = name.clone();
+                self.output.push(format!("({})", name));
+                if *locals > 0 {
+                    self.emit(&["D=0"]);
+                    for _ in 0..*locals {
+                        self.push_d();
+                    }
+                }
+            }
+            Command::Call(name, arguments) => self.call(name, *arguments),
+            Command::Return => self.ret(),
+        }
+    }
+
+    fn arithmetic(&mut self, operation: &str) {
+        match operation {
+            "neg" | "not" => {
+                let comp = if operation == "neg" { "M=-M" } else { "M=!M" };
+                self.emit(&["@SP", "A=M-1", comp]);
+            }
+            "add" | "sub" | "and" | "or" => {
+                let comp = match operation {
+                    "add" => "M=D+M",
+                    "sub" => "M=M-D",
+                    "and" => "M=D&M",
+                    _ => "M=D|M",
+                };
+                self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", comp]);
+            }
+            _ => {
+                // eq, gt, lt: assume true, then clear it when the jump is
+                // not taken
+                let label = self.unique_label(operation);
+                let jump = format!("D;J{}", operation.to_uppercase());
+                self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", "D=M-D", "M=-1"]);
+                self.output.push(format!("@{}", label));
+                self.output.push(jump);
+                self.emit(&["@SP", "A=M-1", "M=0"]);
+                self.output.push(format!("({})", label));
+            }
+        }
+    }
+
+    // the symbol of a fixed address segment entry, statics become
+    // variables named after their file, temp is R5 to R12
+    fn address(&self, segment: &str, index: u16) -> String {
+        match segment {
+            "static" => format!("{}.{}", self.file, index),
+            "temp" => format!("R{}", 5 + index),
+            _ if index == 0 => String::from("THIS"),
+            _ => String::from("THAT"),
+        }
+    }
+
+    fn base(segment: &str) -> &'static str {
+        match segment {
+            "local" => "LCL",
+            "argument" => "ARG",
+            "this" => "THIS",
+            _ => "THAT",
+        }
+    }
+
+    fn push(&mut self, segment: &str, index: u16) {
+        match segment {
+            "constant" => {
+                self.output.push(format!("@{}", index));
+                self.emit(&["D=A"]);
+            }
+            "static" | "temp" | "pointer" => {
+                let address = self.address(segment, index);
+                self.output.push(format!("@{}", address));
+                self.emit(&["D=M"]);
+            }
+            _ => {
+                self.output.push(format!("@{}", index));
+                self.emit(&[
+                    "D=A",
+                    &format!("@{}", Writer::base(segment)),
+                    "A=D+M",
+                    "D=M",
+                ]);
+            }
+        }
+        self.push_d();
+    }
+
+    fn pop(&mut self, segment: &str, index: u16) {
+        match segment {
+            "static" | "temp" | "pointer" => {
+                let address = self.address(segment, index);
+                self.pop_d();
+                self.output.push(format!("@{}", address));
+                self.emit(&["M=D"]);
+            }
+            _ => {
+                // the target address is kept in R13 while popping
+                self.output.push(format!("@{}", index));
+                self.emit(&["D=A", &format!("@{}", Writer::base(segment)), "D=D+M"]);
+                self.emit(&["@R13", "M=D"]);
+                self.pop_d();
+                self.emit(&["@R13", "A=M", "M=D"]);
+            }
+        }
+    }
+
+    fn push_d(&mut self) {
+        self.emit(&["@SP", "AM=M+1", "A=A-1", "M=D"]);
+    }
+
+    fn pop_d(&mut self) {
+        self.emit(&["@SP", "AM=M-1", "D=M"]);
+    }
+
+    fn call(&mut self, name: &str, arguments: u16) {
+        let return_address = self.unique_label("ret");
+
+        self.output.push(format!("@{}", return_address));
+        self.emit(&["D=A"]);
+        self.push_d();
+        for pointer in ["LCL", "ARG", "THIS", "THAT"] {
+            self.output.push(format!("@{}", pointer));
+            self.emit(&["D=M"]);
+            self.push_d();
+        }
+
+        // ARG = SP - 5 - arguments, LCL = SP
+        self.emit(&["@SP", "D=M"]);
+        self.output.push(format!("@{}", 5 + arguments as u32));
+        self.emit(&["D=D-A", "@ARG", "M=D", "@SP", "D=M", "@LCL", "M=D"]);
+
+        self.output.push(format!("@{}", name));
+        self.emit(&["0;JMP"]);
+        self.output.push(format!("({})", return_address));
+    }
+
+    fn ret(&mut self) {
+        // R13 = frame, R14 = return address, read before the return value
+        // may overwrite it when there are no arguments
+        self.emit(&["@LCL", "D=M", "@R13", "M=D"]);
+        self.emit(&["@5", "A=D-A", "D=M", "@R14", "M=D"]);
+        self.pop_d();
+        self.emit(&["@ARG", "A=M", "M=D"]);
+        self.emit(&["@ARG", "D=M+1", "@SP", "M=D"]);
+        for pointer in ["THAT", "THIS", "ARG", "LCL"] {
+            self.emit(&["@R13", "AM=M-1", "D=M"]);
+            self.output.push(format!("@{}", pointer));
+            self.emit(&["M=D"]);
+        }
+        self.emit(&["@R14", "A=M", "0;JMP"]);
+    }
+}
+
+// push constant 7
+fn vm_text(command: &Command) -> String {
+    match command {
+        Command::Arithmetic(operation) => operation.clone(),
+        Command::Push(segment, index) => format!("push {} {}", segment, index),
+        Command::Pop(segment, index) => format!("pop {} {}", segment, index),
+        Command::Label(label) => format!("label {}", label),
+        Command::Goto(label) => format!("goto {}", label),
+        Command::IfGoto(label) => format!("if-goto {}", label),
+        Command::Function(name, locals) => format!("function {} {}", name, locals),
+        Command::Call(name, arguments) => format!("call {} {}", name, arguments),
+        Command::Return => String::from("return"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{Computer, Program};
+
+    fn source(name: &str, contents: &str) -> (String, String) {
+        (String::from(name), String::from(contents))
+    }
+
+    // runs the translated program, with SP set up by hand when there is
+    // no bootstrap
+    fn run(sources: &[(String, String)]) -> Computer {
+        let asm = generate(sources).unwrap();
+        let program = Program::from_asm(&asm).unwrap();
+        let mut computer = Computer::new(program.rom);
+        computer.ram[0] = STACK_BASE;
+        while !computer.halted() && computer.cycles < 100_000 {
+            computer.step();
+        }
+        computer
+    }
+
+    fn errors(contents: &str) -> Vec<String> {
+        parse(contents).1.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        let (commands, diagnostics) =
+            parse("// comment\n  push constant 7 // seven\nadd\nfunction Main.main 2\nreturn\n");
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            vec![
+                (
+                    Command::Push(String::from("constant"), 7),
+                    Location { line: 2, column: 3 }
+                ),
+                (
+                    Command::Arithmetic(String::from("add")),
+                    Location { line: 3, column: 1 }
+                ),
+                (
+                    Command::Function(String::from("Main.main"), 2),
+                    Location { line: 4, column: 1 }
+                ),
+                (Command::Return, Location { line: 5, column: 1 }),
+            ],
+            commands
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            vec![
+                "error: unknown command `mul` (line 1, column 1)",
+                "error: unknown segment `heap` (line 2, column 1)",
+                "error: cannot pop to `constant` (line 3, column 1)",
+                "error: index 8 out of range for `temp` (line 4, column 1)",
+                "error: invalid index `x` (line 5, column 1)",
+                "error: `goto` expects a label (line 6, column 1)",
+            ],
+            errors("mul\npush heap 1\npop constant 1\npush temp 8\npush local x\ngoto")
+        );
+    }
+
+    #[test]
+    fn generate_reports_file() {
+        assert_eq!(
+            Err(vec![String::from(
+                "Main.vm: error: unknown command `mul` (line 2, column 1)"
+            )]),
+            generate(&[source("Main", "push constant 1\nmul")])
+                .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+        );
+    }
+
+    #[test]
+    fn translate_reports_path() {
+        let dir = std::env::temp_dir().join(format!("hack-vm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Main.vm"), "push constant 1\nmul\n").unwrap();
+
+        let mut diagnostics = vec![];
+        let path = dir.to_str().unwrap();
+        assert!(translate(path, &mut diagnostics).is_err());
+        let file = dir.join("Main.vm").to_str().unwrap().to_string();
+        assert_eq!(
+            vec![Some(file)],
+            diagnostics
+                .iter()
+                .map(|d| d.file.clone())
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn simple_add() {
+        let computer = run(&[source("SimpleAdd", "push constant 7\npush constant 8\nadd")]);
+        assert_eq!(257, computer.ram[0]);
+        assert_eq!(15, computer.ram[256]);
+    }
+
+    #[test]
+    fn arithmetic_and_comparisons() {
+        let computer = run(&[source(
+            "Stack",
+            "push constant 17\npush constant 17\neq\n\
+             push constant 892\npush constant 891\nlt\n\
+             push constant 32767\npush constant 32766\ngt\n\
+             push constant 57\npush constant 31\nsub\nneg\n\
+             push constant 82\npush constant 112\nand\n\
+             push constant 3\nnot\n\
+             push constant 4\npush constant 1\nor",
+        )]);
+        assert_eq!(263, computer.ram[0]);
+        assert_eq!(
+            [0xffff, 0, 0xffff, (-26i16) as u16, 80, !3, 5],
+            computer.ram[256..263]
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let mut sources = vec![source(
+            "Seg",
+            "push constant 3030\npop pointer 0\npush constant 3040\npop pointer 1\n\
+             push constant 32\npop this 2\npush constant 46\npop that 6\n\
+             push constant 510\npop temp 6\npush constant 9\npop static 3\n\
+             push this 2\npush that 6\nadd\npush temp 6\nadd\npush static 3\nadd",
+        )];
+        let computer = run(&sources);
+        assert_eq!(3030, computer.ram[3]);
+        assert_eq!(3040, computer.ram[4]);
+        assert_eq!(32, computer.ram[3032]);
+        assert_eq!(46, computer.ram[3046]);
+        assert_eq!(510, computer.ram[11]);
+        assert_eq!(597, computer.ram[256]);
+
+        // statics are named after their file
+        sources[0].0 = String::from("Other");
+        assert!(generate(&sources).unwrap().contains("@Other.3"));
+    }
+
+    #[test]
+    fn functions_with_bootstrap() {
+        let main = "function Main.fibonacci 0\n\
+                    push argument 0\npush constant 2\nlt\nif-goto BASE\n\
+                    push argument 0\npush constant 2\nsub\ncall Main.fibonacci 1\n\
+                    push argument 0\npush constant 1\nsub\ncall Main.fibonacci 1\n\
+                    add\nreturn\n\
+                    label BASE\npush argument 0\nreturn";
+        let sys = "function Sys.init 1\n\
+                   push constant 6\ncall Main.fibonacci 1\npop local 0\n\
+                   push local 0\npop static 0\n\
+                   label END\ngoto END";
+        let computer = run(&[source("Main", main), source("Sys", sys)]);
+        assert_eq!(8, computer.ram[16]);
+        // Sys.init's frame sits right after the bootstrap call's
+        assert_eq!(262, computer.ram[0]);
+        assert!(generate(&[source("Sys", sys)]).unwrap().starts_with(
+            "// bootstrap\n@256\nD=A\n@SP\nM=D\n// call Sys.init 0\n@Bootstrap$ret.0\n"
+        ));
+    }
+
+    #[test]
+    fn labels_are_scoped() {
+        let asm = generate(&[source(
+            "Main",
+            "function Main.a 0\nlabel LOOP\ngoto LOOP\nfunction Main.b 0\nlabel LOOP\n",
+        )])
+        .unwrap();
+        assert!(asm.contains("(Main.a$LOOP)"));
+        assert!(asm.contains("(Main.b$LOOP)"));
+        assert!(asm.contains("// goto LOOP\n@Main.a$LOOP\n0;JMP"));
+        assert!(!asm.contains("// bootstrap"));
+    }
+
+    #[test]
+    fn output_filenames() {
+        assert_eq!(PathBuf::from("Foo.asm"), output_filename("Foo.vm"));
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::assembler;
+use crate::config::Config;
+use crate::test_script;
+
+// Modification times are polled, which works the same everywhere and is
+// quick enough for a handful of files.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Reassembles the program whenever one of its sources changes, and reruns
+// its test script with `--test`. Diagnostics already shown by the previous
+// build are not repeated.
+pub fn run(config: Config) -> ! {
+    let mut previous: HashSet<String> = HashSet::new();
+
+    loop {
+        // taken before building, so saves during the build are not missed
+        let snapshot = snapshot(&sources(&config));
+
+        let mut diagnostics = vec![];
+        let result = assembler::build(&config, &mut diagnostics);
+
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        let unchanged = messages.iter().filter(|m| previous.contains(*m)).count();
+        for (diagnostic, message) in diagnostics.iter().zip(&messages) {
+            if !previous.contains(message) {
+                println!("{}", assembler::render(diagnostic, &config.filename));
+            }
+        }
+        if unchanged > 0 {
+            println!("{} diagnostics unchanged", unchanged);
+        }
+        previous = messages.into_iter().collect();
+
+        match result {
+            Ok(output) => {
+                print!("{}", output.report);
+                if config.test {
+                    match test_script::run_for(&config.filename) {
+                        Ok(message) => println!("{}", message),
+                        Err(e) => println!("Test failed: {}", e),
+                    }
+                }
+            }
+            Err(e) => println!("Application error: {}", e),
+        }
+
+        println!("Watching {} for changes", config.filename);
+        while snapshot == self::snapshot(&sources(&config)) {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+// The files a build reads: the .asm file, or the .jack or .vm files of a
+// directory, never the files generated from them.
+fn sources(config: &Config) -> Vec<PathBuf> {
+    let path = Path::new(&config.filename);
+
+    let mut files = if path.is_dir() {
+        let jack = with_extension(path, "jack");
+        if jack.is_empty() {
+            with_extension(path, "vm")
+        } else {
+            jack
+        }
+    } else {
+        config
+            .files
+            .iter()
+            .chain(&config.libraries)
+            .map(PathBuf::from)
+            .collect()
+    };
+
+    if config.test {
+        if let Some(script) = test_script::script_for(&config.filename) {
+            files.push(PathBuf::from(script));
+        }
+    }
+    files
+}
+
+fn with_extension(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| file.extension().is_some_and(|e| e == extension))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+// files that are missing while an editor saves them have no time
+fn snapshot(files: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+    files
+        .iter()
+        .map(|file| {
+            let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+            (file.clone(), modified)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watched_sources() {
+        let dir = std::env::temp_dir().join(format!("hack-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let args = |path: &Path, option: &str| {
+            let path = path.to_string_lossy().into_owned();
+            Config::new(&[String::from("assembler"), path, String::from(option)]).unwrap()
+        };
+
+        // generated .vm files are not sources of a Jack program
+        fs::write(dir.join("Main.vm"), "").unwrap();
+        assert_eq!(vec![dir.join("Main.vm")], sources(&args(&dir, "--watch")));
+        fs::write(dir.join("Main.jack"), "").unwrap();
+        assert_eq!(vec![dir.join("Main.jack")], sources(&args(&dir, "--watch")));
+
+        let asm = dir.join("Max.asm");
+        fs::write(&asm, "").unwrap();
+        fs::write(dir.join("Max.tst"), "").unwrap();
+        assert_eq!(
+            vec![asm.clone(), dir.join("Max.tst")],
+            sources(&args(&asm, "--test"))
+        );
+
+        let files = vec![asm];
+        let before = snapshot(&files);
+        assert_eq!(before, snapshot(&files));
+        fs::remove_file(&files[0]).unwrap();
+        assert_ne!(before, snapshot(&files));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}